@@ -0,0 +1,284 @@
+use crate::config::{AppPaths, BackupConfig};
+use crate::db::{self, DbConnection, DbPool};
+use crate::error::{Error, Result};
+use crate::models::{BackupInfo, RestoreReport};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FILE_PREFIX: &str = "library-";
+const FILE_EXTENSION: &str = "db";
+
+/// Pages copied per backup step. Between steps the source is unlocked so imports and edits
+/// can keep writing; SQLite restarts the copy if they do.
+const PAGES_PER_STEP: i32 = 512;
+const STEP_PAUSE: Duration = Duration::from_millis(20);
+
+/// How often the scheduler checks whether a backup is due.
+const SCHEDULE_TICK: Duration = Duration::from_secs(60);
+
+/// Runs `PRAGMA integrity_check` and turns anything but "ok" into an error.
+pub fn integrity_check(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if problems.len() == 1 && problems[0] == "ok" {
+        return Ok(());
+    }
+    Err(Error::Validation(format!(
+        "Integrity check failed: {}",
+        problems.join("; ")
+    )))
+}
+
+/// Snapshots the library into `dir`, then drops all but the newest `keep` snapshots.
+pub fn create_backup(conn: &Connection, dir: &Path, keep: usize) -> Result<BackupInfo> {
+    let info = snapshot(conn, dir)?;
+    rotate(dir, keep)?;
+    Ok(info)
+}
+
+/// Copies the live database with the online backup API. The copy is written under a temporary
+/// name and only renamed into place once it passes its own integrity check.
+fn snapshot(conn: &Connection, dir: &Path) -> Result<BackupInfo> {
+    integrity_check(conn)?;
+    std::fs::create_dir_all(dir)?;
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S-%3f");
+    let path = dir.join(format!("{FILE_PREFIX}{stamp}.{FILE_EXTENSION}"));
+    let partial = path.with_extension("partial");
+
+    let copied = copy_database(conn, &partial).and_then(|_| {
+        let copy = Connection::open_with_flags(&partial, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        integrity_check(&copy)
+    });
+    if let Err(err) = copied {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err);
+    }
+    std::fs::rename(&partial, &path)?;
+    log::info!("Library backed up to {}", path.display());
+    describe(&path)
+}
+
+fn copy_database(conn: &Connection, dest_path: &Path) -> Result<()> {
+    let mut dest = Connection::open(dest_path)?;
+    let backup = Backup::new(conn, &mut dest)?;
+    backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)?;
+    Ok(())
+}
+
+/// Snapshots in `dir`, newest first. A missing folder has no backups.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_backup_file(&path) {
+            backups.push(describe(&path)?);
+        }
+    }
+    backups.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| b.file_name.cmp(&a.file_name))
+    });
+    Ok(backups)
+}
+
+/// Deletes all but the newest `keep` snapshots and returns how many were removed.
+pub fn rotate(dir: &Path, keep: usize) -> Result<usize> {
+    let mut removed = 0;
+    for stale in list_backups(dir)?.into_iter().skip(keep.max(1)) {
+        match std::fs::remove_file(&stale.path) {
+            Ok(()) => removed += 1,
+            Err(err) => log::warn!("Failed to remove old backup {}: {}", stale.path, err),
+        }
+    }
+    Ok(removed)
+}
+
+fn is_backup_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    path.is_file()
+        && name.starts_with(FILE_PREFIX)
+        && path.extension().and_then(|ext| ext.to_str()) == Some(FILE_EXTENSION)
+}
+
+fn describe(path: &Path) -> Result<BackupInfo> {
+    let meta = std::fs::metadata(path)?;
+    let created_at = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|age| age.as_secs() as i64)
+        .unwrap_or_default();
+    // An unreadable file is still listed so it can be seen and cleaned up.
+    let schema_version = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .ok()
+        .and_then(|conn| applied_versions(&conn).ok())
+        .and_then(|versions| versions.into_iter().max());
+    Ok(BackupInfo {
+        path: path.to_string_lossy().to_string(),
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size: meta.len(),
+        created_at,
+        schema_version,
+    })
+}
+
+fn applied_versions(conn: &Connection) -> Result<Vec<String>> {
+    let has_table: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if has_table.is_none() {
+        return Err(Error::Validation(
+            "Not a library backup (no schema_migrations)".into(),
+        ));
+    }
+    let mut stmt = conn.prepare("SELECT version FROM schema_migrations ORDER BY version")?;
+    let versions = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(versions)
+}
+
+/// A backup can be restored when it is intact and every migration it has applied is one this
+/// build knows. Returns its newest version and whether migrations still have to run on it.
+fn check_restorable(backup: &Connection) -> Result<(Option<String>, bool)> {
+    integrity_check(backup)?;
+    let applied = applied_versions(backup)?;
+    let known: HashSet<&str> = db::MIGRATIONS.iter().map(|(version, _)| *version).collect();
+    if let Some(unknown) = applied.iter().find(|v| !known.contains(v.as_str())) {
+        return Err(Error::Validation(format!(
+            "The backup uses schema version {unknown}, which this version of the app does not know"
+        )));
+    }
+    let pending = applied.len() < known.len();
+    Ok((applied.into_iter().max(), pending))
+}
+
+/// Replaces the live library with the snapshot at `backup_path`. The current library is
+/// snapshotted into `dir` first, and older schemas are migrated forward after the copy.
+pub fn restore_backup(
+    conn: &mut DbConnection,
+    paths: &AppPaths,
+    backup_path: &Path,
+    dir: &Path,
+    keep: usize,
+) -> Result<RestoreReport> {
+    if same_file(backup_path, &paths.db_path) {
+        return Err(Error::Validation(
+            "Cannot restore the library onto itself".into(),
+        ));
+    }
+    let source = Connection::open_with_flags(backup_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let (schema_version, migrated) = check_restorable(&source)?;
+
+    let safety_backup = snapshot(conn, dir)?;
+    {
+        let backup = Backup::new(&source, &mut **conn)?;
+        backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)?;
+    }
+    integrity_check(conn)?;
+    if migrated {
+        db::run_migrations(conn)?;
+    }
+    // Rotate last so the snapshot being restored cannot be removed before it is copied.
+    rotate(dir, keep)?;
+    log::info!("Library restored from {}", backup_path.display());
+
+    Ok(RestoreReport {
+        restored_from: backup_path.to_string_lossy().to_string(),
+        schema_version,
+        migrated,
+        safety_backup,
+    })
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Background thread that takes a backup whenever the newest one is older than the configured
+/// interval. Changes to `config` are picked up on the next tick.
+pub fn spawn_scheduler(pool: DbPool, paths: AppPaths, config: Arc<Mutex<BackupConfig>>) {
+    thread::spawn(move || loop {
+        let current = config.lock().unwrap().clone();
+        if let Some(hours) = current.interval_hours {
+            let dir = current.resolve_dir(&paths);
+            if let Err(err) = run_if_due(&pool, &dir, hours, current.keep) {
+                log::warn!("Scheduled backup failed: {err}");
+            }
+        }
+        thread::sleep(SCHEDULE_TICK);
+    });
+}
+
+fn run_if_due(pool: &DbPool, dir: &Path, interval_hours: u64, keep: usize) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|age| age.as_secs() as i64)
+        .unwrap_or_default();
+    let last = list_backups(dir)?.first().map(|info| info.created_at);
+    if last.is_some_and(|last| now - last < (interval_hours * 3600) as i64) {
+        return Ok(());
+    }
+    let conn = pool.get()?;
+    create_backup(&conn, dir, keep)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_are_verified_listed_and_rotated() {
+        let dir = std::env::temp_dir().join(format!("pt_backup_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE schema_migrations (version TEXT PRIMARY KEY);
+             INSERT INTO schema_migrations (version) VALUES ('0001'), ('0002');",
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            create_backup(&conn, &dir, 2).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].schema_version.as_deref(), Some("0002"));
+
+        let copy = Connection::open(&backups[0].path).unwrap();
+        assert!(check_restorable(&copy).unwrap().1);
+        copy.execute(
+            "INSERT INTO schema_migrations (version) VALUES ('9999')",
+            [],
+        )
+        .unwrap();
+        assert!(check_restorable(&copy).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
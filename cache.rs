@@ -0,0 +1,877 @@
+use crate::config::{AppPaths, RawDecodeMode, RenditionConfig, RenditionProfile};
+use crate::db::{self, DbConnection};
+use crate::error::Result;
+use crate::exiftool;
+use crate::models::{CacheGcReport, PhotoRecord, RenditionInfo};
+use crate::raw;
+use crate::thumbnails;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+use xxhash_rust::xxh3::xxh3_128;
+
+/// Cache kind for previews extracted from RAW/JPEG files. They are only an intermediate
+/// source for profile renditions and are never referenced by photos.
+pub const KIND_EMBEDDED: &str = "embedded";
+
+/// Cache kind for RAW files demosaiced natively; intermediate like [`KIND_EMBEDDED`].
+pub const KIND_NATIVE: &str = "native";
+
+/// Native decodes are stored at high quality since every profile is rendered from them.
+const NATIVE_JPEG_QUALITY: u8 = 95;
+
+/// Bump when rendering changes in a way that should invalidate existing cache files.
+/// v2: renditions are converted to sRGB.
+const CACHE_VERSION: u32 = 2;
+
+/// Cache hits are collected in memory and written at most this often; LRU eviction does not
+/// need finer access times than that, and serving a thumbnail should not cost a write.
+const ACCESS_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// One lock per cache key being rendered on demand, so concurrent requests for the same
+    /// rendition wait for a single render instead of racing on the same file.
+    static ref RENDER_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+    static ref PENDING_ACCESS: Mutex<PendingAccess> = Mutex::new(PendingAccess::default());
+}
+
+/// Cache file paths hit since access times were last written.
+#[derive(Default)]
+struct PendingAccess {
+    paths: HashSet<String>,
+    flushed_at: Option<Instant>,
+}
+
+/// Notes a cache hit, writing the collected hits in one transaction once the interval is up.
+fn record_access(conn: &DbConnection, path: &Path) -> Result<()> {
+    {
+        let mut pending = PENDING_ACCESS.lock().unwrap();
+        pending.paths.insert(path.to_string_lossy().to_string());
+        if pending
+            .flushed_at
+            .is_some_and(|at| at.elapsed() < ACCESS_FLUSH_INTERVAL)
+        {
+            return Ok(());
+        }
+    }
+    flush_access(conn)
+}
+
+/// Writes hits not yet recorded, so eviction sees current access times.
+fn flush_access(conn: &DbConnection) -> Result<()> {
+    let paths = {
+        let mut pending = PENDING_ACCESS.lock().unwrap();
+        pending.flushed_at = Some(Instant::now());
+        std::mem::take(&mut pending.paths)
+    };
+    if paths.is_empty() {
+        return Ok(());
+    }
+    db::touch_cache_entries(conn, &paths)
+}
+
+/// Cache files are keyed by the source content hash plus everything that affects the pixels,
+/// so identical files share renditions and renamed/moved files keep theirs.
+pub fn cache_key(content_hash: &str, kind: &str, params: &str, orientation: Option<i64>) -> String {
+    let input = format!(
+        "{content_hash}|{kind}|{params}|{}|v{CACHE_VERSION}",
+        orientation.unwrap_or(1)
+    );
+    format!("{:032x}", xxh3_128(input.as_bytes()))
+}
+
+/// `dir/ab/abcdef....jpg`; the two-character shard keeps directories small on big libraries.
+pub fn entry_path(dir: &Path, key: &str, ext: &str) -> PathBuf {
+    dir.join(&key[..2]).join(format!("{key}.{ext}"))
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheFile {
+    pub key: String,
+    pub kind: String,
+    pub params: String,
+    pub path: PathBuf,
+}
+
+/// The original file renditions are made from, with what rendering needs to know about it.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceImage<'a> {
+    pub path: &'a Path,
+    pub content_hash: &'a str,
+    pub orientation: Option<i64>,
+    /// Color space recorded at import; used when the pixels carry no ICC profile.
+    pub color_space: Option<&'a str>,
+}
+
+#[derive(Debug, Default)]
+pub struct Renditions {
+    pub preview: Option<CacheFile>,
+    pub thumb: Option<CacheFile>,
+    /// Embedded preview or native decode the renditions were made from.
+    pub intermediate: Option<CacheFile>,
+}
+
+/// Pixels profiles are rendered from. `intermediate` is set when they were extracted or decoded
+/// into the cache, so the file can be registered with the renditions.
+struct RenderInput {
+    path: PathBuf,
+    upright: bool,
+    intermediate: Option<CacheFile>,
+}
+
+impl RenderInput {
+    fn cached(file: CacheFile) -> Self {
+        Self {
+            path: file.path.clone(),
+            upright: true,
+            intermediate: Some(file),
+        }
+    }
+}
+
+fn is_cached(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.len() > 0)
+        .unwrap_or(false)
+}
+
+fn profile_path(
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    profile: &RenditionProfile,
+    key: &str,
+) -> PathBuf {
+    let dir = if profile.name == config.thumbnail.name {
+        &paths.thumbs_dir
+    } else {
+        &paths.previews_dir
+    };
+    entry_path(dir, key, profile.format.extension())
+}
+
+fn embedded_preview(paths: &AppPaths, source: &SourceImage) -> Option<CacheFile> {
+    let key = cache_key(source.content_hash, KIND_EMBEDDED, "", source.orientation);
+    let path = entry_path(&paths.previews_dir, &key, "jpg");
+    let file = CacheFile {
+        key,
+        kind: KIND_EMBEDDED.to_string(),
+        params: String::new(),
+        path,
+    };
+    build_intermediate(file, |partial| extract_embedded(paths, source, partial))
+}
+
+/// Extracts and normalizes the embedded preview into `partial`.
+fn extract_embedded(paths: &AppPaths, source: &SourceImage, partial: &Path) -> Result<bool> {
+    if !exiftool::extract_preview(paths, source.path, partial)? || !partial.exists() {
+        return Ok(false);
+    }
+    // Embedded previews rarely carry the original's ICC profile, so fall back to it.
+    let icc = exiftool::extract_icc_profile(paths, source.path);
+    if let Err(err) = thumbnails::normalize_preview(
+        partial,
+        source.orientation,
+        icc.as_deref(),
+        source.color_space,
+    ) {
+        log::warn!(
+            "Preview normalization failed for {}: {}",
+            source.path.display(),
+            err
+        );
+    }
+    Ok(true)
+}
+
+/// Returns the intermediate `file`, producing it with `build` when it is not cached. `build`
+/// writes to a partial file that is renamed into place once finished, so readers never see a
+/// half-written or not yet normalized file, and runs under the render lock of the file's key so
+/// concurrent renders of one photo extract or decode it once.
+fn build_intermediate(
+    file: CacheFile,
+    build: impl FnOnce(&Path) -> Result<bool>,
+) -> Option<CacheFile> {
+    if is_cached(&file.path) {
+        return Some(file);
+    }
+    let lock = render_lock(&file.key);
+    let built = {
+        let _guard = lock.lock().unwrap();
+        // Another render may have finished it while this one waited.
+        is_cached(&file.path) || {
+            // Keep the real extension last; decoders and ICC readers pick the format by it.
+            let partial = file.path.with_extension("part.jpg");
+            let result = build(&partial).and_then(|built| {
+                if built {
+                    std::fs::rename(&partial, &file.path)?;
+                }
+                Ok(built)
+            });
+            let built = result.unwrap_or_else(|err| {
+                log::warn!("Building {} failed: {}", file.path.display(), err);
+                false
+            });
+            if !built {
+                let _ = std::fs::remove_file(&partial);
+            }
+            built
+        }
+    };
+    release_render_lock(&file.key, &lock);
+    built.then_some(file)
+}
+
+/// Params native decodes are keyed by: the size of the largest profile.
+fn native_params(config: &RenditionConfig) -> String {
+    let max_dim = config.profiles().map(|p| p.max_dim).max().unwrap_or(0);
+    max_dim.to_string()
+}
+
+/// Demosaics a RAW file at the size of the largest profile.
+fn native_decode(
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    source: &SourceImage,
+) -> Option<CacheFile> {
+    let max_dim = config.profiles().map(|p| p.max_dim).max().unwrap_or(0);
+    let params = native_params(config);
+    let key = cache_key(
+        source.content_hash,
+        KIND_NATIVE,
+        &params,
+        source.orientation,
+    );
+    let path = entry_path(&paths.previews_dir, &key, "jpg");
+    let file = CacheFile {
+        key,
+        kind: KIND_NATIVE.to_string(),
+        params,
+        path,
+    };
+    build_intermediate(file, |partial| {
+        let img = raw::decode(source.path, max_dim, source.orientation)?;
+        if let Some(parent) = partial.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        thumbnails::save_jpeg(&img.to_rgb8(), partial, NATIVE_JPEG_QUALITY)?;
+        Ok(true)
+    })
+}
+
+/// Whether an embedded preview of `dimensions` is used as is. With
+/// [`RawDecodeMode::EmbeddedThenNative`] it has to cover the preview profile, since some RAWs
+/// only embed a ~160px thumbnail; a preview whose size cannot be read is not trusted either.
+fn embedded_is_sufficient(
+    raw_mode: Option<RawDecodeMode>,
+    dimensions: Option<(u32, u32)>,
+    config: &RenditionConfig,
+) -> bool {
+    if raw_mode != Some(RawDecodeMode::EmbeddedThenNative) {
+        return true;
+    }
+    dimensions.is_some_and(|(width, height)| width.max(height) >= config.preview.max_dim)
+}
+
+/// Full-resolution source for rendering: the embedded preview when the file has one (or a
+/// native RAW decode, depending on the format's [`RawDecodeMode`]), else the original when it
+/// is a decodable image.
+fn render_source(
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    source: &SourceImage,
+) -> Option<RenderInput> {
+    let raw_mode = if raw::is_raw(source.path) {
+        Some(config.raw_decode_mode(source.path))
+    } else {
+        None
+    };
+    let mut small_embedded = None;
+    if raw_mode != Some(RawDecodeMode::Native) {
+        if let Some(file) = embedded_preview(paths, source) {
+            let dimensions = image::image_dimensions(&file.path).ok();
+            if embedded_is_sufficient(raw_mode, dimensions, config) {
+                return Some(RenderInput::cached(file));
+            }
+            small_embedded = Some(file);
+        }
+    }
+    if matches!(
+        raw_mode,
+        Some(RawDecodeMode::Native | RawDecodeMode::EmbeddedThenNative)
+    ) {
+        if let Some(file) = native_decode(paths, config, source) {
+            return Some(RenderInput::cached(file));
+        }
+    }
+    // A small embedded preview still beats nothing when the native decode fails.
+    if let Some(file) = small_embedded {
+        return Some(RenderInput::cached(file));
+    }
+    if thumbnails::is_supported_image(source.path) {
+        return Some(RenderInput {
+            path: source.path.to_path_buf(),
+            upright: false,
+            intermediate: None,
+        });
+    }
+    log::warn!(
+        "No embedded preview found for {}; skipping preview generation",
+        source.path.display()
+    );
+    None
+}
+
+fn render_profile(
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    profile: &RenditionProfile,
+    (input, upright): (&Path, bool),
+    source: &SourceImage,
+) -> Option<CacheFile> {
+    let params = profile.cache_params();
+    // The key carries the orientation even for upright inputs so that a corrected Orientation
+    // tag produces new renditions.
+    let key = cache_key(
+        source.content_hash,
+        &profile.name,
+        &params,
+        source.orientation,
+    );
+    let path = profile_path(paths, config, profile, &key);
+    if !is_cached(&path) {
+        // Render next to the final path and rename, so readers never see a partial file.
+        let partial = path.with_extension(format!("{}.part", profile.format.extension()));
+        let result = if upright {
+            thumbnails::build_thumbnail(input, &partial, profile)
+        } else {
+            thumbnails::build_preview(
+                input,
+                &partial,
+                profile,
+                source.orientation,
+                source.color_space,
+            )
+        }
+        .and_then(|()| std::fs::rename(&partial, &path).map_err(Into::into));
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&partial);
+            log::warn!(
+                "Rendering '{}' failed for {}: {}",
+                profile.name,
+                input.display(),
+                err
+            );
+            return None;
+        }
+    }
+    Some(CacheFile {
+        key,
+        kind: profile.name.clone(),
+        params,
+        path,
+    })
+}
+
+/// Returns the import-time preview (and thumbnail, when rendered eagerly) for `source`,
+/// rendering only what is not already cached.
+pub fn render_photo(
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    source: &SourceImage,
+) -> Renditions {
+    let Some(input) = render_source(paths, config, source) else {
+        return Renditions::default();
+    };
+    let base = (input.path.as_path(), input.upright);
+    let preview = render_profile(paths, config, &config.preview, base, source);
+    if !config.eager_thumbnails {
+        return Renditions {
+            preview,
+            thumb: None,
+            intermediate: input.intermediate,
+        };
+    }
+    // Thumbnails come from the (small, upright) preview when there is one.
+    let thumb_input = preview
+        .as_ref()
+        .map(|file| (file.path.as_path(), true))
+        .unwrap_or(base);
+    let thumb = render_profile(paths, config, &config.thumbnail, thumb_input, source);
+    Renditions {
+        preview,
+        thumb,
+        intermediate: input.intermediate,
+    }
+}
+
+/// Records rendered files in `cache_entries` so GC and LRU eviction can find them.
+pub fn register(conn: &DbConnection, renditions: &Renditions, content_hash: &str) -> Result<()> {
+    for file in [
+        &renditions.preview,
+        &renditions.thumb,
+        &renditions.intermediate,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        register_file(conn, file, content_hash)?;
+    }
+    Ok(())
+}
+
+fn register_file(conn: &DbConnection, file: &CacheFile, content_hash: &str) -> Result<()> {
+    let size = std::fs::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+    db::register_cache_entry(
+        conn,
+        &file.key,
+        &file.path.to_string_lossy(),
+        &file.kind,
+        &file.params,
+        content_hash,
+        size as i64,
+    )
+}
+
+/// Returns the best cached rendition of a photo for `display_size` pixels, rendering it first
+/// when needed. `None` when the photo is unknown or cannot be rendered.
+pub fn rendition_for_photo(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    photo_id: i64,
+    display_size: u32,
+) -> Result<Option<RenditionInfo>> {
+    rendition_for_profile(conn, paths, config, photo_id, config.best_for(display_size))
+}
+
+fn render_lock(key: &str) -> Arc<Mutex<()>> {
+    RENDER_LOCKS
+        .lock()
+        .unwrap()
+        .entry(key.to_string())
+        .or_default()
+        .clone()
+}
+
+fn release_render_lock(key: &str, lock: &Arc<Mutex<()>>) {
+    let mut locks = RENDER_LOCKS.lock().unwrap();
+    // One reference is held by the map and one by the caller; anything more is a waiter.
+    if Arc::strong_count(lock) <= 2 {
+        locks.remove(key);
+    }
+}
+
+fn rendition_info(photo_id: i64, profile: &RenditionProfile, path: &Path) -> RenditionInfo {
+    RenditionInfo {
+        photo_id,
+        profile: profile.name.clone(),
+        path: path.to_string_lossy().to_string(),
+        max_dim: profile.max_dim,
+        format: profile.format,
+    }
+}
+
+/// Returns the rendition of a photo for `profile` only when it is already cached; never
+/// renders. For callers that must not block on decoding, like the protocol handler.
+pub fn cached_rendition(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    photo_id: i64,
+    profile: &RenditionProfile,
+) -> Result<Option<RenditionInfo>> {
+    let Some((hash, orientation)) = db::get_photo_cache_source(conn, photo_id)? else {
+        return Ok(None);
+    };
+    let key = cache_key(&hash, &profile.name, &profile.cache_params(), orientation);
+    let path = profile_path(paths, config, profile, &key);
+    if !is_cached(&path) {
+        return Ok(None);
+    }
+    record_access(conn, &path)?;
+    Ok(Some(rendition_info(photo_id, profile, &path)))
+}
+
+/// Like [`rendition_for_photo`] for an explicit profile.
+pub fn rendition_for_profile(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    photo_id: i64,
+    profile: &RenditionProfile,
+) -> Result<Option<RenditionInfo>> {
+    if let Some(info) = cached_rendition(conn, paths, config, photo_id, profile)? {
+        return Ok(Some(info));
+    }
+    let Some(photo) = db::get_photo(conn, photo_id)? else {
+        return Ok(None);
+    };
+    let photo = photo.photo;
+    let key = cache_key(
+        &photo.hash,
+        &profile.name,
+        &profile.cache_params(),
+        photo.orientation,
+    );
+    let path = profile_path(paths, config, profile, &key);
+
+    let lock = render_lock(&key);
+    let (rendered, intermediate) = {
+        let _guard = lock.lock().unwrap();
+        if is_cached(&path) {
+            // Another request rendered it while this one waited.
+            let file = CacheFile {
+                key: key.clone(),
+                kind: profile.name.clone(),
+                params: profile.cache_params(),
+                path: path.clone(),
+            };
+            (Some(file), None)
+        } else {
+            // Profiles no larger than the preview are rendered from it; bigger ones need the
+            // full-resolution source.
+            let preview = photo
+                .preview_path
+                .as_deref()
+                .map(PathBuf::from)
+                .filter(|path| is_cached(path));
+            let source = SourceImage {
+                path: Path::new(&photo.path),
+                content_hash: &photo.hash,
+                orientation: photo.orientation,
+                color_space: photo.color_space.as_deref(),
+            };
+            let input = match preview {
+                Some(preview) if profile.max_dim <= config.preview.max_dim => Some(RenderInput {
+                    path: preview,
+                    upright: true,
+                    intermediate: None,
+                }),
+                _ => render_source(paths, config, &source),
+            };
+            match input {
+                Some(input) => {
+                    let base = (input.path.as_path(), input.upright);
+                    let file = render_profile(paths, config, profile, base, &source);
+                    (file, input.intermediate)
+                }
+                None => (None, None),
+            }
+        }
+    };
+    release_render_lock(&key, &lock);
+    if let Some(intermediate) = &intermediate {
+        register_file(conn, intermediate, &photo.hash)?;
+    }
+    let Some(file) = rendered else {
+        return Ok(None);
+    };
+    register_file(conn, &file, &photo.hash)?;
+    Ok(Some(rendition_info(photo_id, profile, &file.path)))
+}
+
+/// Renditions smaller or larger than this on either edge are treated as corrupt.
+const SANE_DIMENSIONS: std::ops::RangeInclusive<u32> = 4..=16384;
+
+/// Decodes a cache file and returns its dimensions. WebP goes through the `webp` crate since
+/// `image` is built without WebP support.
+fn decoded_dimensions(path: &Path) -> std::result::Result<(u32, u32), String> {
+    let is_webp = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("webp"));
+    if is_webp {
+        let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+        let img = webp::Decoder::new(&bytes)
+            .decode()
+            .ok_or_else(|| "not a WebP image".to_string())?;
+        return Ok((img.width(), img.height()));
+    }
+    let img = image::open(path).map_err(|err| err.to_string())?;
+    Ok((img.width(), img.height()))
+}
+
+/// Checks that a rendition exists, decodes and has plausible dimensions.
+fn verify_file(path: &Path) -> std::result::Result<(), String> {
+    if !is_cached(path) {
+        return Err(format!("{} is missing or empty", path.display()));
+    }
+    let (width, height) = decoded_dimensions(path)
+        .map_err(|err| format!("{} does not decode: {err}", path.display()))?;
+    if !SANE_DIMENSIONS.contains(&width) || !SANE_DIMENSIONS.contains(&height) {
+        return Err(format!(
+            "{} has implausible dimensions {width}x{height}",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Problems with a photo's preview, thumbnail and every other cached file of its content
+/// (lazily rendered profiles and intermediates); empty when all are fine. A photo without a
+/// preview is a problem, a missing thumbnail is not since thumbnails may be rendered lazily.
+pub fn verify_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut checked = HashSet::new();
+    match &photo.preview_path {
+        Some(path) => {
+            checked.insert(path.clone());
+            problems.extend(verify_file(Path::new(path)).err());
+        }
+        None => problems.push(format!("{} has no preview", photo.path)),
+    }
+    let entries = db::list_cache_entries_for_hash(conn, &photo.hash)?;
+    let tracked = entries.into_iter().map(|entry| entry.path);
+    for path in photo.thumb_path.iter().cloned().chain(tracked) {
+        if checked.insert(path.clone()) {
+            problems.extend(verify_file(Path::new(&path)).err());
+        }
+    }
+    Ok(problems)
+}
+
+/// Deletes a photo's renditions, the lazily rendered profiles of its content and the
+/// intermediates they were made from, renders the preview (and thumbnail, if it had one) again
+/// from the original and points the photo at the new files. Other profiles are rendered again
+/// on demand. Returns whether a preview could be rendered.
+pub fn rebuild_photo(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    photo: &PhotoRecord,
+) -> Result<bool> {
+    let Some(photo_id) = photo.id else {
+        return Ok(false);
+    };
+    let source = SourceImage {
+        path: Path::new(&photo.path),
+        content_hash: &photo.hash,
+        orientation: photo.orientation,
+        color_space: photo.color_space.as_deref(),
+    };
+    let intermediates = [
+        cache_key(&photo.hash, KIND_EMBEDDED, "", photo.orientation),
+        cache_key(
+            &photo.hash,
+            KIND_NATIVE,
+            &native_params(config),
+            photo.orientation,
+        ),
+    ]
+    .map(|key| entry_path(&paths.previews_dir, &key, "jpg"));
+    let current: Vec<PathBuf> = [&photo.preview_path, &photo.thumb_path]
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    let mut report = CacheGcReport::default();
+    for path in current.iter().chain(intermediates.iter()) {
+        remove_file(path, &mut report);
+    }
+    for entry in db::list_cache_entries_for_hash(conn, &photo.hash)? {
+        remove_file(Path::new(&entry.path), &mut report);
+        db::delete_cache_entry(conn, &entry.key)?;
+    }
+
+    // Only bring the thumbnail back eagerly if the photo had one.
+    let config = RenditionConfig {
+        eager_thumbnails: photo.thumb_path.is_some(),
+        ..config.clone()
+    };
+    let rendered = render_photo(paths, &config, &source);
+    register(conn, &rendered, &photo.hash)?;
+    let path_of = |file: &Option<CacheFile>| {
+        file.as_ref()
+            .map(|file| file.path.to_string_lossy().to_string())
+    };
+    db::set_rendition_paths(
+        conn,
+        photo_id,
+        path_of(&rendered.thumb).as_deref(),
+        path_of(&rendered.preview).as_deref(),
+    )?;
+    Ok(rendered.preview.is_some())
+}
+
+fn remove_file(path: &Path, report: &mut CacheGcReport) {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    match std::fs::remove_file(path) {
+        Ok(()) => {
+            report.files_removed += 1;
+            report.bytes_reclaimed += size;
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => log::warn!("Failed to remove cache file {}: {}", path.display(), err),
+    }
+}
+
+/// Removes cache files nothing needs any more: renditions of content no photo has, renditions
+/// of profiles that were removed or changed, and stray files from older naming schemes. Then
+/// evicts least recently used entries until the cache fits `max_bytes`. Photo previews are never
+/// evicted since tagging and embeddings read them and nothing renders them again; evicted
+/// thumbnails are cleared from their photos and rendered on demand when next shown.
+pub fn collect_garbage(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    max_bytes: Option<u64>,
+) -> Result<CacheGcReport> {
+    let mut report = CacheGcReport::default();
+    flush_access(conn)?;
+    let referenced = db::referenced_cache_paths(conn)?;
+    let previews = db::referenced_preview_paths(conn)?;
+    let live_hashes = db::photo_content_hashes(conn)?;
+    // Intermediates stay as long as their content does, so larger profiles can still be
+    // rendered without extracting or decoding the original again.
+    let profiles: HashSet<(String, String)> = config
+        .profiles()
+        .map(|profile| (profile.name.clone(), profile.cache_params()))
+        .chain([
+            (KIND_EMBEDDED.to_string(), String::new()),
+            (KIND_NATIVE.to_string(), native_params(config)),
+        ])
+        .collect();
+
+    let mut tracked = HashSet::new();
+    for entry in db::list_cache_entries(conn)? {
+        let live = referenced.contains(&entry.path)
+            || (live_hashes.contains(&entry.content_hash)
+                && profiles.contains(&(entry.kind, entry.params)));
+        if live {
+            tracked.insert(entry.path);
+        } else {
+            remove_file(Path::new(&entry.path), &mut report);
+            db::delete_cache_entry(conn, &entry.key)?;
+        }
+    }
+
+    let mut total: u64 = 0;
+    for dir in [&paths.thumbs_dir, &paths.previews_dir] {
+        for entry in WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let path_str = entry.path().to_string_lossy().to_string();
+            if referenced.contains(&path_str) || tracked.contains(&path_str) {
+                total += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            } else {
+                remove_file(entry.path(), &mut report);
+            }
+        }
+    }
+
+    if let Some(max_bytes) = max_bytes {
+        if total > max_bytes {
+            // list_cache_entries is ordered by last access, oldest first.
+            for entry in db::list_cache_entries(conn)? {
+                if total <= max_bytes {
+                    break;
+                }
+                if previews.contains(&entry.path) {
+                    continue;
+                }
+                let before = report.bytes_reclaimed;
+                remove_file(Path::new(&entry.path), &mut report);
+                total = total.saturating_sub(report.bytes_reclaimed - before);
+                db::clear_cache_path_refs(conn, &entry.path)?;
+                db::delete_cache_entry(conn, &entry.key)?;
+                report.evicted += 1;
+            }
+        }
+    }
+    report.remaining_bytes = total;
+    log::info!(
+        "Cache GC removed {} file(s), reclaimed {} bytes, {} bytes remain",
+        report.files_removed,
+        report.bytes_reclaimed,
+        report.remaining_bytes
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_depends_on_render_params() {
+        let base = cache_key("abc", "thumb", "320:jpg:85", Some(1));
+        assert_eq!(base, cache_key("abc", "thumb", "320:jpg:85", None));
+        assert_ne!(base, cache_key("abc", "thumb", "640:jpg:85", Some(1)));
+        assert_ne!(base, cache_key("abc", "thumb", "320:webp:85", Some(1)));
+        assert_ne!(base, cache_key("abc", "preview", "320:jpg:85", Some(1)));
+        assert_ne!(base, cache_key("abd", "thumb", "320:jpg:85", Some(1)));
+        assert_ne!(base, cache_key("abc", "thumb", "320:jpg:85", Some(6)));
+    }
+
+    #[test]
+    fn entry_path_is_sharded() {
+        let path = entry_path(Path::new("thumbs"), "abcdef", "jpg");
+        assert_eq!(path, Path::new("thumbs").join("ab").join("abcdef.jpg"));
+    }
+
+    #[test]
+    fn best_profile_covers_display_size() {
+        let config = RenditionConfig::default();
+        assert_eq!(config.best_for(200).name, "thumb");
+        assert_eq!(config.best_for(320).name, "thumb");
+        assert_eq!(config.best_for(500).name, "thumb_hidpi");
+        assert_eq!(config.best_for(1920).name, "cull");
+        assert_eq!(config.best_for(8000).name, "cull");
+    }
+
+    #[test]
+    fn small_embedded_previews_fall_back_to_native() {
+        let config = RenditionConfig::default();
+        let full = config.preview.max_dim;
+        let fallback = Some(RawDecodeMode::EmbeddedThenNative);
+        assert!(!embedded_is_sufficient(fallback, Some((160, 120)), &config));
+        assert!(!embedded_is_sufficient(fallback, None, &config));
+        assert!(embedded_is_sufficient(
+            fallback,
+            Some((full, full / 2)),
+            &config
+        ));
+        assert!(embedded_is_sufficient(
+            fallback,
+            Some((full / 2, full)),
+            &config
+        ));
+        assert!(embedded_is_sufficient(
+            Some(RawDecodeMode::Embedded),
+            Some((160, 120)),
+            &config
+        ));
+        assert!(embedded_is_sufficient(None, Some((160, 120)), &config));
+    }
+
+    #[test]
+    fn verification_covers_lazily_rendered_profiles() {
+        let dir = std::env::temp_dir().join(format!("pt_cache_verify_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let preview = dir.join("preview.jpg");
+        let cull = dir.join("cull.jpg");
+        thumbnails::save_jpeg(&image::RgbImage::new(16, 16), &preview, 80).unwrap();
+        std::fs::write(&cull, b"not a jpeg").unwrap();
+        let conn = db::tests::test_conn();
+        for (key, path) in [("preview", &preview), ("cull", &cull)] {
+            let path = path.to_string_lossy();
+            db::register_cache_entry(&conn, key, &path, key, "", "abc", 16).unwrap();
+        }
+        let photo = PhotoRecord {
+            path: "a.raw".into(),
+            hash: "abc".into(),
+            preview_path: Some(preview.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let problems = verify_photo(&conn, &photo).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("cull.jpg"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
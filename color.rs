@@ -0,0 +1,160 @@
+use crate::error::Result;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::{DynamicImage, ImageDecoder};
+use qcms::{DataType, Intent, Profile, Transform};
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub const SRGB: &str = "sRGB";
+pub const ADOBE_RGB: &str = "Adobe RGB";
+pub const DISPLAY_P3: &str = "Display P3";
+pub const PROPHOTO_RGB: &str = "ProPhoto RGB";
+
+/// Maps an ICC profile description onto the names stored in `photos.color_space`.
+fn normalize_profile_name(description: &str) -> String {
+    let lower = description.to_ascii_lowercase();
+    if lower.contains("srgb") || lower.contains("iec61966") {
+        SRGB.to_string()
+    } else if lower.contains("adobe rgb") || lower.contains("adobergb") {
+        ADOBE_RGB.to_string()
+    } else if lower.contains("p3") {
+        DISPLAY_P3.to_string()
+    } else if lower.contains("prophoto") || lower.contains("romm") {
+        PROPHOTO_RGB.to_string()
+    } else {
+        description.trim().to_string()
+    }
+}
+
+/// Source color space from an ungrouped ExifTool view: the ICC profile description when there
+/// is one, else the EXIF ColorSpace tag (with the DCF "R03" interop index meaning Adobe RGB).
+pub fn detect_color_space(entry: &Value) -> Option<String> {
+    if let Some(description) = entry.get("ProfileDescription").and_then(Value::as_str) {
+        if !description.trim().is_empty() {
+            return Some(normalize_profile_name(description));
+        }
+    }
+    let interop = entry.get("InteropIndex").and_then(Value::as_str);
+    match entry.get("ColorSpace").and_then(Value::as_i64) {
+        Some(1) => Some(SRGB.to_string()),
+        Some(2) => Some(ADOBE_RGB.to_string()),
+        Some(0xFFFF) if interop == Some("R03") => Some(ADOBE_RGB.to_string()),
+        Some(0xFFFF) => Some("Uncalibrated".to_string()),
+        _ => None,
+    }
+}
+
+fn is_srgb(color_space: Option<&str>) -> bool {
+    matches!(color_space, None | Some(SRGB) | Some("Uncalibrated"))
+}
+
+/// Adobe RGB (1998) built from its primaries, for files that only signal it through EXIF.
+fn adobe_rgb_profile() -> Option<Box<Profile>> {
+    let white = qcms::CIE_xyY {
+        x: 0.3127,
+        y: 0.3290,
+        Y: 1.0,
+    };
+    let primaries = qcms::CIE_xyYTRIPLE {
+        red: qcms::CIE_xyY {
+            x: 0.64,
+            y: 0.33,
+            Y: 1.0,
+        },
+        green: qcms::CIE_xyY {
+            x: 0.21,
+            y: 0.71,
+            Y: 1.0,
+        },
+        blue: qcms::CIE_xyY {
+            x: 0.15,
+            y: 0.06,
+            Y: 1.0,
+        },
+    };
+    Profile::new_rgb_with_gamma(white, primaries, 563.0 / 256.0)
+}
+
+fn source_profile(icc: Option<&[u8]>, color_space: Option<&str>) -> Option<Box<Profile>> {
+    if let Some(profile) = icc.and_then(|icc| Profile::new_from_slice(icc, false)) {
+        return Some(profile);
+    }
+    match color_space {
+        Some(ADOBE_RGB) => adobe_rgb_profile(),
+        _ => None,
+    }
+}
+
+/// Whether converting with these inputs would change anything.
+pub fn needs_conversion(icc: Option<&[u8]>, color_space: Option<&str>) -> bool {
+    icc.is_some() || !is_srgb(color_space)
+}
+
+/// Converts `img` to sRGB using its embedded ICC profile, or the recorded color space when the
+/// file has none. Images that are already sRGB (or unknown) come back untouched.
+pub fn to_srgb(img: DynamicImage, icc: Option<&[u8]>, color_space: Option<&str>) -> DynamicImage {
+    if !needs_conversion(icc, color_space) {
+        return img;
+    }
+    let Some(input) = source_profile(icc, color_space) else {
+        return img;
+    };
+    let mut output = Profile::new_sRGB();
+    output.precache_output_transform();
+    let Some(transform) = Transform::new(&input, &output, DataType::RGB8, Intent::Perceptual)
+    else {
+        log::warn!("Unsupported ICC profile; keeping original colors");
+        return img;
+    };
+    let mut rgb = img.to_rgb8();
+    transform.apply(&mut rgb);
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Reads the ICC profile embedded in a JPEG, PNG or TIFF file.
+pub fn read_icc_profile(path: &Path) -> Option<Vec<u8>> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    let reader = BufReader::new(File::open(path).ok()?);
+    match ext.as_str() {
+        "jpg" | "jpeg" => JpegDecoder::new(reader).ok()?.icc_profile(),
+        "png" => PngDecoder::new(reader).ok()?.icc_profile(),
+        "tif" | "tiff" => TiffDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Decodes `path` and converts it to sRGB. `color_space` is the fallback for untagged files.
+pub fn open_srgb(path: &Path, color_space: Option<&str>) -> Result<DynamicImage> {
+    let img = image::open(path)?;
+    let icc = read_icc_profile(path);
+    Ok(to_srgb(img, icc.as_deref(), color_space))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn detects_color_space_from_icc_and_exif() {
+        let icc = json!({ "ProfileDescription": "Display P3", "ColorSpace": 65535 });
+        assert_eq!(detect_color_space(&icc).as_deref(), Some(DISPLAY_P3));
+        let adobe = json!({ "ColorSpace": 65535, "InteropIndex": "R03" });
+        assert_eq!(detect_color_space(&adobe).as_deref(), Some(ADOBE_RGB));
+        let srgb = json!({ "ColorSpace": 1 });
+        assert_eq!(detect_color_space(&srgb).as_deref(), Some(SRGB));
+        assert_eq!(detect_color_space(&json!({})), None);
+    }
+
+    #[test]
+    fn srgb_images_are_left_alone() {
+        let img =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([10, 200, 30])));
+        let out = to_srgb(img.clone(), None, Some(SRGB));
+        assert_eq!(out.to_rgb8(), img.to_rgb8());
+    }
+}
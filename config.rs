@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tauri::api::path::{app_data_dir, resource_dir};
 use tauri::{Config, Env, PackageInfo};
@@ -212,15 +213,313 @@ fn copy_dir_recursive(src: &Path, dest: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RenditionFormat {
+    Jpeg,
+    Webp,
+}
+
+impl RenditionFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+/// One cached output size. `max_dim` bounds the longer edge; `quality` is 1-100.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RenditionProfile {
+    pub name: String,
+    pub max_dim: u32,
+    pub format: RenditionFormat,
+    pub quality: u8,
+}
+
+impl RenditionProfile {
+    fn new(name: &str, max_dim: u32, format: RenditionFormat, quality: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            max_dim,
+            format,
+            quality,
+        }
+    }
+
+    /// Everything about the profile that changes the output pixels or encoding.
+    pub fn cache_params(&self) -> String {
+        format!("{}:{}:{}", self.max_dim, self.format.extension(), self.quality)
+    }
+}
+
+/// How previews are obtained for a RAW format.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RawDecodeMode {
+    /// Only use the JPEG embedded by the camera.
+    Embedded,
+    /// Use the embedded JPEG, demosaic the sensor data when there is none or it is smaller
+    /// than the preview profile.
+    EmbeddedThenNative,
+    /// Always demosaic the sensor data.
+    Native,
+}
+
+impl Default for RawDecodeMode {
+    fn default() -> Self {
+        Self::EmbeddedThenNative
+    }
+}
+
+/// Rendition profiles. `preview` is rendered during import (tagging and embeddings run on it),
+/// `thumbnail` too when `eager_thumbnails` is set; everything else is rendered on first request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Settings {
-    pub tagging: TaggingConfig,
+pub struct RenditionConfig {
+    pub thumbnail: RenditionProfile,
+    pub preview: RenditionProfile,
+    #[serde(default)]
+    pub extra: Vec<RenditionProfile>,
+    #[serde(default = "default_eager_thumbnails")]
+    pub eager_thumbnails: bool,
+    /// Per-extension RAW handling (lowercase, without the dot); unlisted formats use the default.
+    #[serde(default)]
+    pub raw_decode: HashMap<String, RawDecodeMode>,
+}
+
+fn default_eager_thumbnails() -> bool {
+    false
+}
+
+impl Default for RenditionConfig {
+    fn default() -> Self {
+        Self {
+            thumbnail: RenditionProfile::new("thumb", 320, RenditionFormat::Jpeg, 85),
+            preview: RenditionProfile::new("preview", 1600, RenditionFormat::Jpeg, 90),
+            extra: vec![
+                RenditionProfile::new("thumb_hidpi", 640, RenditionFormat::Jpeg, 85),
+                RenditionProfile::new("cull", 2560, RenditionFormat::Jpeg, 90),
+            ],
+            eager_thumbnails: default_eager_thumbnails(),
+            raw_decode: HashMap::new(),
+        }
+    }
+}
+
+impl RenditionConfig {
+    pub fn profiles(&self) -> impl Iterator<Item = &RenditionProfile> {
+        [&self.thumbnail, &self.preview]
+            .into_iter()
+            .chain(self.extra.iter())
+    }
+
+    pub fn raw_decode_mode(&self, path: &Path) -> RawDecodeMode {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.raw_decode.get(&ext.to_ascii_lowercase()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&RenditionProfile> {
+        self.profiles().find(|profile| profile.name == name)
+    }
+
+    /// Smallest profile that covers `display_size` pixels, or the largest one when none does.
+    pub fn best_for(&self, display_size: u32) -> &RenditionProfile {
+        let mut profiles: Vec<&RenditionProfile> = self.profiles().collect();
+        profiles.sort_by_key(|profile| profile.max_dim);
+        profiles
+            .iter()
+            .find(|profile| profile.max_dim >= display_size)
+            .or_else(|| profiles.last())
+            .copied()
+            .unwrap_or(&self.preview)
+    }
+
+    pub fn validate(&self) -> Result<(), crate::error::Error> {
+        let mut names = std::collections::HashSet::new();
+        for profile in self.profiles() {
+            if profile.name.trim().is_empty() || !names.insert(profile.name.as_str()) {
+                return Err(crate::error::Error::Validation(format!(
+                    "Rendition profile names must be unique and non-empty: '{}'",
+                    profile.name
+                )));
+            }
+            if !(64..=8192).contains(&profile.max_dim) {
+                return Err(crate::error::Error::Validation(format!(
+                    "Rendition profile '{}' size must be between 64 and 8192 px",
+                    profile.name
+                )));
+            }
+            if !(1..=100).contains(&profile.quality) {
+                return Err(crate::error::Error::Validation(format!(
+                    "Rendition profile '{}' quality must be between 1 and 100",
+                    profile.name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Limits for the rendition cache under `thumbs_dir`/`previews_dir`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// When set, least recently used renditions are evicted after each import until the cache
+    /// fits. `None` keeps everything that is still referenced.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// Library snapshots. `dir` defaults to `<app data>/backups`; an `interval_hours` of `None`
+/// turns scheduled backups off. Only the newest `keep` snapshots are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupConfig {
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    #[serde(default = "default_backup_interval_hours")]
+    pub interval_hours: Option<u64>,
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            interval_hours: default_backup_interval_hours(),
+            keep: default_backup_keep(),
+        }
+    }
+}
+
+impl BackupConfig {
+    pub fn resolve_dir(&self, paths: &AppPaths) -> PathBuf {
+        self.dir
+            .clone()
+            .unwrap_or_else(|| paths.root.join("backups"))
+    }
+
+    pub fn validate(&self) -> Result<(), crate::error::Error> {
+        if self.keep == 0 {
+            return Err(crate::error::Error::Validation(
+                "At least one backup must be kept".to_string(),
+            ));
+        }
+        if self.interval_hours == Some(0) {
+            return Err(crate::error::Error::Validation(
+                "Backup interval must be at least one hour".to_string(),
+            ));
+        }
+        if let Some(dir) = &self.dir {
+            if !dir.is_absolute() {
+                return Err(crate::error::Error::Validation(format!(
+                    "Backup folder must be an absolute path: {}",
+                    dir.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_backup_interval_hours() -> Option<u64> {
+    Some(24)
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
+/// Whether title and caption are read from IPTC/XMP at import and written to XMP sidecars when
+/// edited. Edits made in the app are kept over the file's values either way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataSyncConfig {
+    #[serde(default = "default_metadata_sync")]
+    pub enabled: bool,
 }
 
-impl Default for Settings {
+impl Default for MetadataSyncConfig {
     fn default() -> Self {
         Self {
-            tagging: TaggingConfig::default(),
+            enabled: default_metadata_sync(),
         }
     }
 }
+
+fn default_metadata_sync() -> bool {
+    true
+}
+
+/// Settings changed from the UI, persisted to `settings.json` in the app data dir. Tagging is
+/// not part of it since model paths are resolved from the environment at startup.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub renditions: RenditionConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub metadata_sync: MetadataSyncConfig,
+}
+
+/// Serializes read-modify-write cycles on the settings file.
+static SETTINGS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+impl Settings {
+    fn path(paths: &AppPaths) -> PathBuf {
+        paths.root.join("settings.json")
+    }
+
+    /// Missing or unreadable settings fall back to the defaults.
+    pub fn load(paths: &AppPaths) -> Self {
+        let path = Self::path(paths);
+        let Ok(bytes) = std::fs::read(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_slice::<Self>(&bytes) {
+            Ok(settings) => settings.checked(),
+            Err(err) => {
+                log::warn!("Ignoring unreadable settings {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    /// Resets sections that no longer validate, e.g. after a hand edit.
+    fn checked(mut self) -> Self {
+        if let Err(err) = self.renditions.validate() {
+            log::warn!("Ignoring saved rendition profiles: {}", err);
+            self.renditions = RenditionConfig::default();
+        }
+        if let Err(err) = self.backup.validate() {
+            log::warn!("Ignoring saved backup settings: {}", err);
+            self.backup = BackupConfig::default();
+        }
+        self
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a truncated file behind.
+    fn save(&self, paths: &AppPaths) -> Result<(), crate::error::Error> {
+        let path = Self::path(paths);
+        let partial = path.with_extension("json.part");
+        std::fs::write(&partial, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Applies `change` to the saved settings and writes them back.
+    pub fn update(
+        paths: &AppPaths,
+        change: impl FnOnce(&mut Settings),
+    ) -> Result<(), crate::error::Error> {
+        let _guard = SETTINGS_LOCK.lock().unwrap();
+        let mut settings = Self::load(paths);
+        change(&mut settings);
+        settings.save(paths)
+    }
+}
@@ -1,14 +1,21 @@
 use crate::config::AppPaths;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::embedding;
+use crate::filter::{self, FilterClause};
+use crate::history;
+use crate::metadata;
 use crate::models::{
-    CsvExportRow, DuplicateGroup, DuplicatePhoto, ExifMetadata, PhotoRecord, PhotoWithTags,
-    QueryFilters, SimilarPhoto, SmartViewCounts, TagRecord, TaggingResult,
+    Album, ColorLabel, CsvExportRow, DuplicateGroup, DuplicatePhoto, ExifMetadata, FacetCounts,
+    FacetValue, Keyword, KeywordInput, MapBounds, PhotoPage, PhotoRecord, PhotoTextRequest,
+    PhotoWithTags, PlaceInfo, QueryFilters, SimilarPhoto, SmartCollection, SmartCollectionInput,
+    SmartViewCounts, TagEditReport, TagRecord, TagUsage, TaggingResult, TimeShiftItem,
+    TimeShiftPreview, TimeShiftRequest,
 };
 use crate::schema;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{params, types::Value, Connection, OptionalExtension};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
 pub type DbConnection = r2d2::PooledConnection<SqliteConnectionManager>;
@@ -22,16 +29,44 @@ pub fn init_database(paths: &AppPaths) -> Result<DbPool> {
         std::fs::create_dir_all(parent)?;
     }
 
-    let manager = SqliteConnectionManager::file(db_path);
+    let manager = SqliteConnectionManager::file(db_path)
+        .with_init(|conn| metadata::register_sql_functions(conn));
     let pool = r2d2::Pool::new(manager)?;
     let conn = pool.get()?;
     run_migrations(&conn)?;
+    history::prune(&conn, history::MAX_AGE_SECS)?;
 
     Ok(pool)
 }
 
+/// Every schema version this build knows, oldest first.
+pub(crate) const MIGRATIONS: &[(&str, &str)] = &[
+    ("0001", schema::MIGRATION_0001),
+    ("0002", schema::MIGRATION_0002),
+    ("0003", schema::MIGRATION_0003),
+    ("0004", schema::MIGRATION_0004),
+    ("0005", schema::MIGRATION_0005),
+    ("0006", schema::MIGRATION_0006),
+    ("0007", schema::MIGRATION_0007),
+    ("0008", schema::MIGRATION_0008),
+    ("0009", schema::MIGRATION_0009),
+    ("0010", schema::MIGRATION_0010),
+    ("0011", schema::MIGRATION_0011),
+    ("0012", schema::MIGRATION_0012),
+    ("0013", schema::MIGRATION_0013),
+    ("0014", schema::MIGRATION_0014),
+    ("0015", schema::MIGRATION_0015),
+    ("0016", schema::MIGRATION_0016),
+    ("0017", schema::MIGRATION_0017),
+    ("0018", schema::MIGRATION_0018),
+    ("0019", schema::MIGRATION_0019),
+    ("0020", schema::MIGRATION_0020),
+    ("0021", schema::MIGRATION_0021),
+    ("0022", schema::MIGRATION_0022),
+];
+
 /// Applies all pending database migrations.
-fn run_migrations(connection: &DbConnection) -> Result<()> {
+pub(crate) fn run_migrations(connection: &DbConnection) -> Result<()> {
     let connection: &Connection = &*connection;
 
     log::info!("Running database migrations...");
@@ -49,15 +84,7 @@ fn run_migrations(connection: &DbConnection) -> Result<()> {
         applied.insert(row?);
     }
 
-    let migrations = [
-        ("0001", schema::MIGRATION_0001),
-        ("0002", schema::MIGRATION_0002),
-        ("0003", schema::MIGRATION_0003),
-        ("0004", schema::MIGRATION_0004),
-        ("0005", schema::MIGRATION_0005),
-    ];
-
-    for (version, migration) in migrations {
+    for &(version, migration) in MIGRATIONS {
         if !applied.contains(version) {
             log::info!("Applying migration {version}...");
             if version == "0003" {
@@ -66,6 +93,24 @@ fn run_migrations(connection: &DbConnection) -> Result<()> {
                 apply_migration_0004(connection)?;
             } else if version == "0005" {
                 apply_migration_0005(connection)?;
+            } else if version == "0007" {
+                apply_migration_0007(connection)?;
+            } else if version == "0008" {
+                apply_migration_0008(connection)?;
+            } else if version == "0009" {
+                apply_migration_0009(connection)?;
+            } else if version == "0012" {
+                apply_migration_0012(connection)?;
+            } else if version == "0013" {
+                apply_migration_0013(connection)?;
+            } else if version == "0019" {
+                apply_migration_0019(connection)?;
+            } else if version == "0020" {
+                apply_migration_0020(connection)?;
+            } else if version == "0021" {
+                apply_migration_0021(connection)?;
+            } else if version == "0022" {
+                apply_migration_0022(connection)?;
             } else {
                 connection.execute_batch(migration)?;
             }
@@ -163,6 +208,83 @@ fn apply_migration_0005(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+fn apply_migration_0007(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "photos", "orientation")? {
+        conn.execute("ALTER TABLE photos ADD COLUMN orientation INTEGER", [])?;
+    }
+    Ok(())
+}
+
+fn apply_migration_0008(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "photos", "date_taken_original")? {
+        conn.execute("ALTER TABLE photos ADD COLUMN date_taken_original INTEGER", [])?;
+    }
+    Ok(())
+}
+
+fn apply_migration_0009(conn: &Connection) -> Result<()> {
+    for column in ["place_country", "place_region", "place_city"] {
+        if !column_exists(conn, "photos", column)? {
+            conn.execute(&format!("ALTER TABLE photos ADD COLUMN {column} TEXT"), [])?;
+        }
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_photos_place ON photos (place_country, place_region, place_city)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn apply_migration_0012(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "cache_entries", "params")? {
+        conn.execute_batch(schema::MIGRATION_0012)?;
+    }
+    Ok(())
+}
+
+fn apply_migration_0013(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "photos", "color_space")? {
+        conn.execute("ALTER TABLE photos ADD COLUMN color_space TEXT", [])?;
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_photos_color_space ON photos (color_space)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn apply_migration_0019(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "photos", "color_label")? {
+        conn.execute("ALTER TABLE photos ADD COLUMN color_label TEXT", [])?;
+    }
+    conn.execute_batch(schema::MIGRATION_0019)?;
+    Ok(())
+}
+
+fn apply_migration_0020(conn: &Connection) -> Result<()> {
+    for column in ["title", "caption", "notes"] {
+        if !column_exists(conn, "photos", column)? {
+            conn.execute(&format!("ALTER TABLE photos ADD COLUMN {column} TEXT"), [])?;
+        }
+    }
+    conn.execute_batch(schema::MIGRATION_0020)?;
+    Ok(())
+}
+
+fn apply_migration_0021(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "keywords", "is_category")? {
+        conn.execute_batch(schema::MIGRATION_0021)?;
+    }
+    Ok(())
+}
+
+fn apply_migration_0022(conn: &Connection) -> Result<()> {
+    if !column_exists(conn, "photos", "title_edited")? {
+        conn.execute_batch(schema::MIGRATION_0022)?;
+    }
+    Ok(())
+}
+
 pub fn upsert_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<i64> {
     // Check existing record
     let existing: Option<(i64, i64, i64)> = conn
@@ -209,12 +331,14 @@ pub fn upsert_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<i64> {
             preview_path,
             dhash,
             import_batch_id,
+            orientation,
+            color_space,
             created_at,
             updated_at,
             last_modified
         )
         VALUES (
-            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23,
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25,
             strftime('%s','now'),
             strftime('%s','now'),
             strftime('%s','now')
@@ -230,7 +354,15 @@ pub fn upsert_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<i64> {
             make = excluded.make,
             model = excluded.model,
             lens = excluded.lens,
-            date_taken = excluded.date_taken,
+            date_taken = CASE
+                WHEN photos.date_taken_original IS NULL OR excluded.date_taken IS NULL
+                    THEN excluded.date_taken
+                ELSE excluded.date_taken + (photos.date_taken - photos.date_taken_original)
+            END,
+            date_taken_original = CASE
+                WHEN photos.date_taken_original IS NULL OR excluded.date_taken IS NULL THEN NULL
+                ELSE excluded.date_taken
+            END,
             iso = excluded.iso,
             fnumber = excluded.fnumber,
             focal_length = excluded.focal_length,
@@ -241,6 +373,8 @@ pub fn upsert_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<i64> {
             thumb_path = excluded.thumb_path,
             preview_path = excluded.preview_path,
             dhash = excluded.dhash,
+            orientation = excluded.orientation,
+            color_space = excluded.color_space,
             updated_at = strftime('%s','now'),
             last_modified = strftime('%s','now')",
         params![
@@ -267,6 +401,8 @@ pub fn upsert_photo(conn: &DbConnection, photo: &PhotoRecord) -> Result<i64> {
             photo.preview_path,
             photo.dhash,
             photo.import_batch_id,
+            photo.orientation,
+            photo.color_space,
         ],
     )?;
 
@@ -292,7 +428,15 @@ pub fn replace_auto_tags(
         params![photo_id],
     )?;
 
+    // Several labels can map onto the same keyword; keep the most confident.
+    let mut canonical: HashMap<String, f32> = HashMap::new();
     for (tag, confidence) in tagging.tags {
+        let entry = canonical
+            .entry(canonical_tag(conn, &tag)?)
+            .or_insert(confidence);
+        *entry = entry.max(confidence);
+    }
+    for (tag, confidence) in canonical {
         conn.execute(
             "INSERT OR IGNORE INTO tags (photo_id, tag, confidence, source, locked, created_at) VALUES (?1, ?2, ?3, 'auto', 0, strftime('%s','now'))",
             params![photo_id, tag, confidence],
@@ -301,6 +445,429 @@ pub fn replace_auto_tags(
     Ok(())
 }
 
+/// The keyword a tag stands for, by name or alias, spelled as the keyword; otherwise the tag.
+pub fn canonical_tag(conn: &DbConnection, tag: &str) -> Result<String> {
+    let name: Option<String> = conn
+        .query_row(
+            "SELECT name FROM keywords WHERE name = ?1
+             UNION ALL
+             SELECT k.name FROM keyword_aliases a JOIN keywords k ON k.id = a.keyword_id
+             WHERE a.alias = ?1
+             LIMIT 1",
+            params![tag],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(name.unwrap_or_else(|| tag.to_string()))
+}
+
+pub fn list_keywords(conn: &DbConnection) -> Result<Vec<Keyword>> {
+    let mut aliases: HashMap<i64, Vec<String>> = HashMap::new();
+    {
+        let mut stmt =
+            conn.prepare("SELECT keyword_id, alias FROM keyword_aliases ORDER BY alias")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (id, alias) = row?;
+            aliases.entry(id).or_default().push(alias);
+        }
+    }
+    let mut stmt = conn.prepare(
+        "SELECT id, name, parent_id, is_category FROM keywords ORDER BY name COLLATE NOCASE",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Keyword {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            aliases: Vec::new(),
+            is_category: row.get::<_, i64>(3)? == 1,
+        })
+    })?;
+    let mut keywords = Vec::new();
+    for row in rows {
+        let mut keyword = row?;
+        keyword.aliases = aliases.remove(&keyword.id).unwrap_or_default();
+        keywords.push(keyword);
+    }
+    Ok(keywords)
+}
+
+/// Fails when `parent_id` is `id` or one of its descendants.
+fn check_keyword_parent(
+    conn: &DbConnection,
+    id: Option<i64>,
+    parent_id: Option<i64>,
+) -> Result<()> {
+    let mut current = parent_id;
+    while let Some(node) = current {
+        if Some(node) == id {
+            return Err(Error::Validation(
+                "A keyword cannot be nested under itself".into(),
+            ));
+        }
+        current = conn
+            .query_row(
+                "SELECT parent_id FROM keywords WHERE id = ?1",
+                params![node],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("Keyword {node}")))?;
+    }
+    Ok(())
+}
+
+/// Returns the id of the keyword called `name`, creating it if needed, and puts it under
+/// `parent_id`, marked as a category or not.
+pub fn ensure_keyword(
+    conn: &DbConnection,
+    name: &str,
+    parent_id: Option<i64>,
+    is_category: bool,
+) -> Result<i64> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Validation("Keyword name cannot be empty".into()));
+    }
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM keywords WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    check_keyword_parent(conn, existing, parent_id)?;
+    match existing {
+        Some(id) => {
+            conn.execute(
+                "UPDATE keywords SET parent_id = ?1, is_category = ?2 WHERE id = ?3",
+                params![parent_id, is_category, id],
+            )?;
+            Ok(id)
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO keywords (name, parent_id, is_category) VALUES (?1, ?2, ?3)",
+                params![name, parent_id, is_category],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }
+    }
+}
+
+/// Points `alias` at a keyword, taking it from any keyword that had it before. Tags spelled
+/// like the alias, in any case, are merged into the keyword's name.
+pub fn set_keyword_alias(conn: &DbConnection, alias: &str, keyword_id: i64) -> Result<()> {
+    let alias = alias.trim();
+    if alias.is_empty() {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO keyword_aliases (alias, keyword_id) VALUES (?1, ?2)
+         ON CONFLICT(alias) DO UPDATE SET keyword_id = excluded.keyword_id",
+        params![alias, keyword_id],
+    )?;
+    let name: String = conn.query_row(
+        "SELECT name FROM keywords WHERE id = ?1",
+        params![keyword_id],
+        |row| row.get(0),
+    )?;
+    let spellings = {
+        let mut stmt =
+            conn.prepare("SELECT DISTINCT tag FROM tags WHERE tag = ?1 COLLATE NOCASE")?;
+        let rows = stmt.query_map(params![alias], |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    merge_tag_rows(conn, &spellings, &name, true)?;
+    Ok(())
+}
+
+/// Creates (`id == None`) or updates a keyword, replacing its aliases.
+pub fn save_keyword(conn: &DbConnection, id: Option<i64>, input: &KeywordInput) -> Result<Keyword> {
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err(Error::Validation("Keyword name cannot be empty".into()));
+    }
+    let tx = conn.unchecked_transaction()?;
+    let id = match id {
+        Some(id) => {
+            check_keyword_parent(conn, Some(id), input.parent_id)?;
+            let updated = conn.execute(
+                "UPDATE keywords SET name = ?1, parent_id = ?2 WHERE id = ?3",
+                params![name, input.parent_id, id],
+            )?;
+            if updated == 0 {
+                return Err(Error::NotFound(format!("Keyword {id}")));
+            }
+            id
+        }
+        None => {
+            check_keyword_parent(conn, None, input.parent_id)?;
+            conn.execute(
+                "INSERT INTO keywords (name, parent_id) VALUES (?1, ?2)",
+                params![name, input.parent_id],
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+    conn.execute(
+        "DELETE FROM keyword_aliases WHERE keyword_id = ?1",
+        params![id],
+    )?;
+    for alias in &input.aliases {
+        if !alias.trim().eq_ignore_ascii_case(name) {
+            set_keyword_alias(conn, alias, id)?;
+        }
+    }
+    tx.commit()?;
+    list_keywords(conn)?
+        .into_iter()
+        .find(|keyword| keyword.id == id)
+        .ok_or_else(|| Error::NotFound(format!("Keyword {id}")))
+}
+
+/// Deletes a keyword and its aliases; its children move up to its parent. Tags are kept.
+pub fn delete_keyword(conn: &DbConnection, id: i64) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE keywords SET parent_id = (SELECT parent_id FROM keywords WHERE id = ?1)
+         WHERE parent_id = ?1",
+        params![id],
+    )?;
+    tx.execute(
+        "DELETE FROM keyword_aliases WHERE keyword_id = ?1",
+        params![id],
+    )?;
+    tx.execute("DELETE FROM keywords WHERE id = ?1", params![id])?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn upsert_raw_metadata(conn: &DbConnection, photo_id: i64, raw: &serde_json::Value) -> Result<()> {
+    let blob = metadata::compress_json(raw)?;
+    conn.execute(
+        "INSERT INTO photo_metadata (photo_id, raw, updated_at) VALUES (?1, ?2, strftime('%s','now'))
+         ON CONFLICT(photo_id) DO UPDATE SET raw = excluded.raw, updated_at = strftime('%s','now')",
+        params![photo_id, blob],
+    )?;
+    Ok(())
+}
+
+pub fn get_raw_metadata(conn: &DbConnection, photo_id: i64) -> Result<Option<serde_json::Value>> {
+    let blob: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT raw FROM photo_metadata WHERE photo_id = ?1",
+            params![photo_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    blob.map(|blob| metadata::decompress_json(&blob)).transpose()
+}
+
+pub struct CacheEntry {
+    pub key: String,
+    pub path: String,
+    pub kind: String,
+    pub params: String,
+    pub content_hash: String,
+}
+
+pub fn register_cache_entry(
+    conn: &DbConnection,
+    key: &str,
+    path: &str,
+    kind: &str,
+    params: &str,
+    content_hash: &str,
+    size: i64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO cache_entries (key, path, kind, params, content_hash, size, created_at, last_accessed)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%s','now'), strftime('%s','now'))
+         ON CONFLICT(key) DO UPDATE SET
+            path = excluded.path,
+            size = excluded.size,
+            last_accessed = excluded.last_accessed",
+        params![key, path, kind, params, content_hash, size],
+    )?;
+    Ok(())
+}
+
+/// Marks cache files as used so LRU eviction keeps them.
+pub fn touch_cache_entries<'a>(
+    conn: &DbConnection,
+    paths: impl IntoIterator<Item = &'a String>,
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE cache_entries SET last_accessed = strftime('%s','now') WHERE path = ?1",
+        )?;
+        for path in paths {
+            stmt.execute(params![path])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Content hash and orientation of a photo, which is all its cache keys depend on.
+pub fn get_photo_cache_source(
+    conn: &DbConnection,
+    photo_id: i64,
+) -> Result<Option<(String, Option<i64>)>> {
+    let source = conn
+        .query_row(
+            "SELECT hash, orientation FROM photos WHERE id = ?1",
+            params![photo_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(source)
+}
+
+/// All tracked cache entries, least recently used first.
+pub fn list_cache_entries(conn: &DbConnection) -> Result<Vec<CacheEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT key, path, kind, params, content_hash FROM cache_entries
+         ORDER BY last_accessed ASC, created_at ASC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(CacheEntry {
+            key: row.get(0)?,
+            path: row.get(1)?,
+            kind: row.get(2)?,
+            params: row.get(3)?,
+            content_hash: row.get(4)?,
+        })
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+/// Tracked cache entries made from content with this hash.
+pub fn list_cache_entries_for_hash(
+    conn: &DbConnection,
+    content_hash: &str,
+) -> Result<Vec<CacheEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT key, path, kind, params, content_hash FROM cache_entries
+         WHERE content_hash = ?1 ORDER BY key",
+    )?;
+    let rows = stmt.query_map(params![content_hash], |row| {
+        Ok(CacheEntry {
+            key: row.get(0)?,
+            path: row.get(1)?,
+            kind: row.get(2)?,
+            params: row.get(3)?,
+            content_hash: row.get(4)?,
+        })
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+/// Thumbnail and preview paths still referenced by photos.
+pub fn referenced_cache_paths(conn: &DbConnection) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT thumb_path FROM photos WHERE thumb_path IS NOT NULL
+         UNION SELECT preview_path FROM photos WHERE preview_path IS NOT NULL",
+    )?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut paths = HashSet::new();
+    for row in rows {
+        paths.insert(row?);
+    }
+    Ok(paths)
+}
+
+/// Paths photos use as their preview; see `cache::collect_garbage`.
+pub fn referenced_preview_paths(conn: &DbConnection) -> Result<HashSet<String>> {
+    let mut stmt =
+        conn.prepare("SELECT DISTINCT preview_path FROM photos WHERE preview_path IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut paths = HashSet::new();
+    for row in rows {
+        paths.insert(row?);
+    }
+    Ok(paths)
+}
+
+pub fn photo_content_hashes(conn: &DbConnection) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT hash FROM photos")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut hashes = HashSet::new();
+    for row in rows {
+        hashes.insert(row?);
+    }
+    Ok(hashes)
+}
+
+pub fn delete_cache_entry(conn: &DbConnection, key: &str) -> Result<()> {
+    conn.execute("DELETE FROM cache_entries WHERE key = ?1", params![key])?;
+    Ok(())
+}
+
+/// Drops references to an evicted cache file from every photo using it.
+pub fn clear_cache_path_refs(conn: &DbConnection, path: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET thumb_path = NULL WHERE thumb_path = ?1",
+        params![path],
+    )?;
+    conn.execute(
+        "UPDATE photos SET preview_path = NULL WHERE preview_path = ?1",
+        params![path],
+    )?;
+    Ok(())
+}
+
+/// The fields the cache check needs for every photo: identity, source and rendition paths.
+pub fn list_rendition_targets(conn: &DbConnection) -> Result<Vec<PhotoRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, path, hash, orientation, color_space, thumb_path, preview_path
+         FROM photos ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PhotoRecord {
+            id: Some(row.get(0)?),
+            path: row.get(1)?,
+            hash: row.get(2)?,
+            orientation: row.get(3)?,
+            color_space: row.get(4)?,
+            thumb_path: row.get(5)?,
+            preview_path: row.get(6)?,
+            ..Default::default()
+        })
+    })?;
+    let mut photos = Vec::new();
+    for row in rows {
+        photos.push(row?);
+    }
+    Ok(photos)
+}
+
+pub fn set_rendition_paths(
+    conn: &DbConnection,
+    photo_id: i64,
+    thumb_path: Option<&str>,
+    preview_path: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET thumb_path = ?1, preview_path = ?2 WHERE id = ?3",
+        params![thumb_path, preview_path, photo_id],
+    )?;
+    Ok(())
+}
+
 pub fn get_photo_status(conn: &DbConnection, path: &str) -> Result<Option<(i64, i64)>> {
     conn.query_row(
         "SELECT mtime, size FROM photos WHERE path = ?1",
@@ -336,6 +903,9 @@ fn resolve_sort_column(sort_by: Option<&str>) -> &'static str {
         Some("rejected") => "rejected",
         Some("last_modified") => "last_modified",
         Some("import_batch_id") => "import_batch_id",
+        Some("color_label") => {
+            "(SELECT position FROM color_labels WHERE color = photos.color_label)"
+        }
         _ => "date_taken",
     }
 }
@@ -347,7 +917,7 @@ fn resolve_sort_dir(sort_dir: Option<&str>) -> &'static str {
     }
 }
 
-fn latest_import_batch_id(conn: &DbConnection) -> Result<Option<String>> {
+pub(crate) fn latest_import_batch_id(conn: &DbConnection) -> Result<Option<String>> {
     conn.query_row(
         "SELECT import_batch_id FROM photos WHERE import_batch_id IS NOT NULL ORDER BY created_at DESC LIMIT 1",
         [],
@@ -358,254 +928,1539 @@ fn latest_import_batch_id(conn: &DbConnection) -> Result<Option<String>> {
 }
 
 pub fn add_manual_tag(conn: &DbConnection, photo_id: i64, tag: &str) -> Result<()> {
+    let tag = canonical_tag(conn, tag)?;
     conn.execute(
-        "INSERT OR REPLACE INTO tags (id, photo_id, tag, confidence, source, locked, created_at) VALUES ((SELECT id FROM tags WHERE photo_id = ?1 AND tag = ?2), ?1, ?2, 1.0, 'manual', 1, strftime('%s','now'))",
+        "INSERT OR REPLACE INTO tags (id, photo_id, tag, confidence, source, locked, created_at) VALUES ((SELECT id FROM tags WHERE photo_id = ?1 AND tag = ?2 COLLATE NOCASE), ?1, ?2, 1.0, 'manual', 1, strftime('%s','now'))",
         params![photo_id, tag],
     )?;
     Ok(())
 }
 
-pub fn remove_tag(conn: &DbConnection, photo_id: i64, tag: &str) -> Result<()> {
-    conn.execute(
-        "DELETE FROM tags WHERE photo_id = ?1 AND tag = ?2 AND source = 'manual'",
-        params![photo_id, tag],
-    )?;
-    Ok(())
+/// Every tag in the library with usage counts, most used first.
+pub fn list_tag_usage(conn: &DbConnection) -> Result<Vec<TagUsage>> {
+    let mut stmt =
+        conn.prepare("SELECT tag, source, COUNT(*), SUM(locked) FROM tags GROUP BY tag, source")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, i64>(3)?,
+        ))
+    })?;
+    let mut usage: HashMap<String, TagUsage> = HashMap::new();
+    for row in rows {
+        let (tag, source, count, locked) = row?;
+        let entry = usage.entry(tag.clone()).or_insert_with(|| TagUsage {
+            tag,
+            ..Default::default()
+        });
+        // (photo_id, tag) is unique, so per-source counts add up to the photo count.
+        entry.photo_count += count;
+        entry.locked_count += locked;
+        *entry.by_source.entry(source).or_default() += count;
+    }
+    let mut usage: Vec<TagUsage> = usage.into_values().collect();
+    usage.sort_by(|a, b| {
+        b.photo_count
+            .cmp(&a.photo_count)
+            .then_with(|| a.tag.to_lowercase().cmp(&b.tag.to_lowercase()))
+    });
+    Ok(usage)
 }
 
-pub fn set_rating(conn: &DbConnection, photo_id: i64, rating: Option<i64>) -> Result<()> {
-    conn.execute(
-        "UPDATE photos SET rating = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
-        params![rating, photo_id],
-    )?;
-    Ok(())
+/// Merges `sources` into `target` across the library (a rename when there is one source). Where a
+/// photo ends up with several rows for the target, they collapse into one carrying the highest
+/// confidence and the source of that row, locked if any was. Locked rows, source or target, are
+/// only rewritten with `include_locked`. Tags match regardless of case, so other spellings of the
+/// target are folded into it too.
+pub fn merge_tags(
+    conn: &DbConnection,
+    sources: &[String],
+    target: &str,
+    include_locked: bool,
+) -> Result<TagEditReport> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Err(Error::Validation("Tag name cannot be empty".into()));
+    }
+    let tx = conn.unchecked_transaction()?;
+    let report = merge_tag_rows(&tx, sources, target, include_locked)?;
+    tx.commit()?;
+    Ok(report)
 }
 
-pub fn set_picked(conn: &DbConnection, photo_id: i64, picked: bool) -> Result<()> {
-    conn.execute(
-        "UPDATE photos SET picked = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
-        params![picked as i64, photo_id],
-    )?;
-    Ok(())
-}
+/// [`merge_tags`] within the caller's transaction.
+fn merge_tag_rows(
+    tx: &Connection,
+    sources: &[String],
+    target: &str,
+    include_locked: bool,
+) -> Result<TagEditReport> {
+    let sources: Vec<&String> = sources
+        .iter()
+        .filter(|tag| tag.as_str() != target)
+        .collect();
+    let mut report = TagEditReport::default();
+    if sources.is_empty() {
+        return Ok(report);
+    }
+    let placeholders = vec!["?"; sources.len()].join(",");
+    let source_params: Vec<Value> = sources
+        .iter()
+        .map(|tag| Value::from((*tag).clone()))
+        .collect();
 
-pub fn set_rejected(conn: &DbConnection, photo_id: i64, rejected: bool) -> Result<()> {
-    conn.execute(
-        "UPDATE photos SET rejected = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
+    if !include_locked {
+        let mut params = source_params.clone();
+        params.push(target.to_string().into());
+        report.skipped_locked = tx.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM tags
+                 WHERE locked = 1 AND tag COLLATE NOCASE IN ({placeholders}) AND tag <> ?"
+            ),
+            rusqlite::params_from_iter(params),
+            |row| row.get::<_, i64>(0),
+        )? as usize;
+    }
+
+    let mut candidates: HashMap<i64, Vec<MergeCandidate>> = HashMap::new();
+    {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT id, photo_id, confidence, source, locked, tag = ? COLLATE NOCASE FROM tags
+             WHERE (tag COLLATE NOCASE IN ({placeholders}) AND tag <> ? AND (locked = 0 OR ?))
+                OR (tag = ? COLLATE NOCASE AND photo_id IN (
+                    SELECT photo_id FROM tags
+                    WHERE tag COLLATE NOCASE IN ({placeholders}) AND tag <> ?
+                      AND (locked = 0 OR ?)
+                ))"
+        ))?;
+        let mut params: Vec<Value> = vec![target.to_string().into()];
+        for _ in 0..2 {
+            params.extend(source_params.iter().cloned());
+            params.push(target.to_string().into());
+            params.push(include_locked.into());
+        }
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, i64>(1)?,
+                MergeCandidate {
+                    id: row.get(0)?,
+                    confidence: row.get::<_, Option<f64>>(2)?.unwrap_or(1.0),
+                    source: row.get(3)?,
+                    locked: row.get::<_, i64>(4)? == 1,
+                    is_target: row.get(5)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (photo_id, candidate) = row?;
+            candidates.entry(photo_id).or_default().push(candidate);
+        }
+    }
+
+    for rows in candidates.values() {
+        let Some(best) = rows
+            .iter()
+            .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+        else {
+            continue;
+        };
+        // An existing target row keeps its id; otherwise the best row is renamed.
+        let kept = rows.iter().find(|row| row.is_target).unwrap_or(best);
+        let locked = rows.iter().any(|row| row.locked);
+        for row in rows.iter().filter(|row| row.id != kept.id) {
+            tx.execute("DELETE FROM tags WHERE id = ?1", params![row.id])?;
+            report.removed += 1;
+        }
+        // A locked target row stays as it is; only the duplicates folded into it go away.
+        if kept.locked && !include_locked {
+            continue;
+        }
+        // Duplicates are deleted first so the rename cannot hit UNIQUE(photo_id, tag).
+        tx.execute(
+            "UPDATE tags SET tag = ?1, confidence = ?2, source = ?3, locked = ?4 WHERE id = ?5",
+            params![target, best.confidence, best.source, locked, kept.id],
+        )?;
+        report.updated += 1;
+    }
+    Ok(report)
+}
+
+struct MergeCandidate {
+    id: i64,
+    confidence: f64,
+    source: String,
+    locked: bool,
+    is_target: bool,
+}
+
+/// Removes a tag, in any case, from every photo; locked rows only with `include_locked`.
+pub fn delete_tag_everywhere(
+    conn: &DbConnection,
+    tag: &str,
+    include_locked: bool,
+) -> Result<TagEditReport> {
+    let skipped_locked = if include_locked {
+        0
+    } else {
+        conn.query_row(
+            "SELECT COUNT(*) FROM tags WHERE tag = ?1 COLLATE NOCASE AND locked = 1",
+            params![tag],
+            |row| row.get::<_, i64>(0),
+        )? as usize
+    };
+    let removed = conn.execute(
+        "DELETE FROM tags WHERE tag = ?1 COLLATE NOCASE AND (locked = 0 OR ?2)",
+        params![tag, include_locked],
+    )?;
+    Ok(TagEditReport {
+        updated: 0,
+        removed,
+        skipped_locked,
+    })
+}
+
+pub fn remove_tag(conn: &DbConnection, photo_id: i64, tag: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM tags WHERE photo_id = ?1 AND tag = ?2 COLLATE NOCASE AND source = 'manual'",
+        params![photo_id, tag],
+    )?;
+    Ok(())
+}
+
+pub fn set_rating(conn: &DbConnection, photo_id: i64, rating: Option<i64>) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET rating = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
+        params![rating, photo_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_picked(conn: &DbConnection, photo_id: i64, picked: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET picked = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
+        params![picked as i64, photo_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_rejected(conn: &DbConnection, photo_id: i64, rejected: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET rejected = ?1, last_modified = strftime('%s','now') WHERE id = ?2",
         params![rejected as i64, photo_id],
     )?;
-    Ok(())
+    Ok(())
+}
+
+pub fn batch_update_cull(
+    conn: &DbConnection,
+    photo_ids: &[i64],
+    rating: Option<Option<i64>>,
+    picked: Option<bool>,
+    rejected: Option<bool>,
+    color_label: Option<Option<&str>>,
+) -> Result<usize> {
+    if photo_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let mut sets: Vec<String> = Vec::new();
+    let mut params: Vec<Value> = Vec::new();
+
+    if let Some(rating_value) = rating {
+        sets.push("rating = ?".into());
+        match rating_value {
+            Some(v) => params.push(v.into()),
+            None => params.push(Value::Null),
+        }
+    }
+    if let Some(p) = picked {
+        sets.push("picked = ?".into());
+        params.push((p as i64).into());
+    }
+    if let Some(r) = rejected {
+        sets.push("rejected = ?".into());
+        params.push((r as i64).into());
+    }
+    if let Some(label) = color_label {
+        if let Some(color) = label {
+            check_color_label(conn, color)?;
+        }
+        sets.push("color_label = ?".into());
+        params.push(label.map(str::to_string).into());
+    }
+
+    if sets.is_empty() {
+        return Ok(0);
+    }
+
+    sets.push("last_modified = strftime('%s','now')".into());
+
+    let mut sql = format!("UPDATE photos SET {} WHERE id IN (", sets.join(", "));
+    for (idx, _) in photo_ids.iter().enumerate() {
+        if idx > 0 {
+            sql.push(',');
+        }
+        sql.push('?');
+    }
+    sql.push(')');
+
+    for id in photo_ids {
+        params.push((*id).into());
+    }
+
+    let updated = conn.execute(&sql, rusqlite::params_from_iter(params))?;
+    Ok(updated)
+}
+
+/// Stores the title and caption read from a file's IPTC/XMP. Fields edited in the app are kept,
+/// so an edited or cleared caption does not come back when the file is imported again.
+pub fn set_imported_text(
+    conn: &DbConnection,
+    photo_id: i64,
+    title: Option<&str>,
+    caption: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE photos SET
+            title = CASE WHEN title_edited = 1 THEN title ELSE ?2 END,
+            caption = CASE WHEN caption_edited = 1 THEN caption ELSE ?3 END
+         WHERE id = ?1
+           AND ((title_edited = 0 AND title IS NOT ?2) OR (caption_edited = 0 AND caption IS NOT ?3))",
+        params![photo_id, title, caption],
+    )?;
+    Ok(())
+}
+
+/// Sets title, caption and notes on the selection; `None` leaves a field alone and a blank
+/// string clears it. Title and caption are marked as edited so imports keep them. Returns the
+/// paths of the updated photos.
+pub fn set_photo_text(conn: &DbConnection, request: &PhotoTextRequest) -> Result<Vec<String>> {
+    let ids = resolve_target_ids(conn, &request.photo_ids, request.filters.as_ref())?;
+    let fields = [
+        ("title", &request.title),
+        ("caption", &request.caption),
+        ("notes", &request.notes),
+    ];
+    let mut sets: Vec<String> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    for (column, field) in fields {
+        if let Some(text) = field {
+            let text = text.trim();
+            sets.push(format!("{column} = ?"));
+            values.push((!text.is_empty()).then(|| text.to_string()).into());
+            if column != "notes" {
+                sets.push(format!("{column}_edited = 1"));
+            }
+        }
+    }
+    if ids.is_empty() || sets.is_empty() {
+        return Ok(Vec::new());
+    }
+    sets.push("last_modified = strftime('%s','now')".into());
+
+    let tx = conn.unchecked_transaction()?;
+    let mut paths = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(500) {
+        let mut params = values.clone();
+        params.extend(chunk.iter().map(|id| Value::from(*id)));
+        let sql = format!(
+            "UPDATE photos SET {} WHERE id IN ({}) RETURNING path",
+            sets.join(", "),
+            placeholders(chunk.len())
+        );
+        let mut stmt = tx.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| row.get(0))?;
+        for row in rows {
+            paths.push(row?);
+        }
+    }
+    tx.commit()?;
+    Ok(paths)
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(",")
+}
+
+/// Resolves a selection: explicit ids win, otherwise every photo matching the filters.
+fn resolve_target_ids(
+    conn: &DbConnection,
+    photo_ids: &[i64],
+    filters: Option<&QueryFilters>,
+) -> Result<Vec<i64>> {
+    if !photo_ids.is_empty() {
+        return Ok(photo_ids.to_vec());
+    }
+    let Some(filters) = filters else {
+        return Ok(Vec::new());
+    };
+    query_photo_ids(conn, filters)
+}
+
+fn resolve_time_shift_offset(conn: &DbConnection, request: &TimeShiftRequest) -> Result<i64> {
+    if let (Some(reference_id), Some(reference_time)) =
+        (request.reference_photo_id, request.reference_time)
+    {
+        let taken: Option<i64> = conn
+            .query_row(
+                "SELECT date_taken FROM photos WHERE id = ?1",
+                params![reference_id],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()?
+            .flatten();
+        let taken =
+            taken.ok_or_else(|| Error::Validation("Reference photo has no capture time".into()))?;
+        return Ok(reference_time - taken);
+    }
+    request
+        .offset_seconds
+        .ok_or_else(|| Error::Validation("No time offset or reference photo given".into()))
+}
+
+/// A targeted photo with the fields needed for capture-time based operations.
+pub struct TimeTarget {
+    pub id: i64,
+    pub path: String,
+    pub file_name: String,
+    pub date_taken: Option<i64>,
+    pub has_gps: bool,
+}
+
+pub fn list_time_targets(
+    conn: &DbConnection,
+    photo_ids: &[i64],
+    filters: Option<&QueryFilters>,
+) -> Result<Vec<TimeTarget>> {
+    let ids = resolve_target_ids(conn, photo_ids, filters)?;
+    let mut targets = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(500) {
+        let sql = format!(
+            "SELECT id, path, file_name, date_taken, gps_lat IS NOT NULL AND gps_lng IS NOT NULL
+             FROM photos WHERE id IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+            Ok(TimeTarget {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                file_name: row.get(2)?,
+                date_taken: row.get(3)?,
+                has_gps: row.get::<_, i64>(4)? == 1,
+            })
+        })?;
+        for row in rows {
+            targets.push(row?);
+        }
+    }
+    Ok(targets)
+}
+
+pub fn preview_time_shift(
+    conn: &DbConnection,
+    request: &TimeShiftRequest,
+) -> Result<TimeShiftPreview> {
+    let offset = resolve_time_shift_offset(conn, request)?;
+    let targets = list_time_targets(conn, &request.photo_ids, request.filters.as_ref())?;
+    let mut items = Vec::with_capacity(targets.len());
+    let mut skipped = 0;
+    for target in targets {
+        match target.date_taken {
+            Some(date_taken) => items.push(TimeShiftItem {
+                id: target.id,
+                path: target.path,
+                file_name: target.file_name,
+                date_taken,
+                shifted: date_taken + offset,
+            }),
+            None => skipped += 1,
+        }
+    }
+    items.sort_by_key(|item| item.date_taken);
+    Ok(TimeShiftPreview {
+        offset_seconds: offset,
+        items,
+        skipped,
+    })
+}
+
+/// Shifts `date_taken` for the targeted photos, keeping the first pre-shift value in
+/// `date_taken_original`. Returns the rows that changed.
+pub fn apply_time_shift(conn: &DbConnection, request: &TimeShiftRequest) -> Result<Vec<TimeShiftItem>> {
+    let preview = preview_time_shift(conn, request)?;
+    if preview.offset_seconds == 0 {
+        return Ok(Vec::new());
+    }
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE photos SET date_taken_original = COALESCE(date_taken_original, date_taken),
+                date_taken = ?1, last_modified = strftime('%s','now')
+             WHERE id = ?2",
+        )?;
+        for item in &preview.items {
+            stmt.execute(params![item.shifted, item.id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(preview.items)
+}
+
+/// Restores `date_taken_original` for any shifted photos in the selection.
+pub fn revert_time_shift(conn: &DbConnection, photo_ids: &[i64]) -> Result<Vec<TimeShiftItem>> {
+    let mut items = Vec::new();
+    for chunk in photo_ids.chunks(500) {
+        let sql = format!(
+            "SELECT id, path, file_name, date_taken, date_taken_original FROM photos
+             WHERE date_taken_original IS NOT NULL AND id IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+            Ok(TimeShiftItem {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                file_name: row.get(2)?,
+                date_taken: row.get::<_, Option<i64>>(3)?.unwrap_or_default(),
+                shifted: row.get(4)?,
+            })
+        })?;
+        for row in rows {
+            items.push(row?);
+        }
+    }
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE photos SET date_taken = date_taken_original, date_taken_original = NULL,
+                last_modified = strftime('%s','now')
+             WHERE id = ?1",
+        )?;
+        for item in &items {
+            stmt.execute(params![item.id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(items)
+}
+
+pub fn set_gps_positions(conn: &DbConnection, updates: &[(i64, f64, f64)]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE photos SET gps_lat = ?1, gps_lng = ?2, last_modified = strftime('%s','now') WHERE id = ?3",
+        )?;
+        for (id, lat, lng) in updates {
+            stmt.execute(params![lat, lng, id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn list_gps_positions(
+    conn: &DbConnection,
+    photo_ids: &[i64],
+) -> Result<Vec<(i64, Option<f64>, Option<f64>)>> {
+    let mut positions = Vec::with_capacity(photo_ids.len());
+    for chunk in photo_ids.chunks(500) {
+        let sql = format!(
+            "SELECT id, gps_lat, gps_lng FROM photos WHERE id IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        for row in rows {
+            positions.push(row?);
+        }
+    }
+    Ok(positions)
+}
+
+/// Geotagged photos that have not been reverse geocoded yet (or all of them when `all` is set).
+pub fn list_geocode_candidates(conn: &DbConnection, all: bool) -> Result<Vec<i64>> {
+    let sql = if all {
+        "SELECT id FROM photos WHERE gps_lat IS NOT NULL AND gps_lng IS NOT NULL"
+    } else {
+        "SELECT id FROM photos WHERE gps_lat IS NOT NULL AND gps_lng IS NOT NULL AND place_country IS NULL"
+    };
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+/// Stores the resolved place and replaces the photo's `place` tags with it.
+pub fn set_photo_place(conn: &DbConnection, photo_id: i64, place: &PlaceInfo) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE photos SET place_country = ?1, place_region = ?2, place_city = ?3 WHERE id = ?4",
+        params![place.country, place.region, place.city, photo_id],
+    )?;
+    tx.execute(
+        "DELETE FROM tags WHERE photo_id = ?1 AND source = 'place'",
+        params![photo_id],
+    )?;
+    for name in [&place.country, &place.region, &place.city].into_iter().flatten() {
+        tx.execute(
+            "INSERT OR IGNORE INTO tags (photo_id, tag, confidence, source, locked, created_at) VALUES (?1, ?2, 1.0, 'place', 0, strftime('%s','now'))",
+            params![photo_id, name],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// A geotagged photo with the fields map clustering needs.
+pub struct GeoPoint {
+    pub id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub thumb_path: Option<String>,
+    pub rating: Option<i64>,
+    pub picked: bool,
+    pub date_taken: Option<i64>,
+}
+
+/// Geotagged photos inside the bounding box that also match `filters`. Handles boxes that
+/// cross the antimeridian (`west > east`).
+pub fn query_geo_points(
+    conn: &DbConnection,
+    bounds: &MapBounds,
+    filters: &QueryFilters,
+) -> Result<Vec<GeoPoint>> {
+    let mut sql = "SELECT id, gps_lat, gps_lng, thumb_path, rating, picked, date_taken FROM photos
+        WHERE id IN (SELECT id FROM photo_geo WHERE min_lat >= ? AND max_lat <= ?"
+        .to_string();
+    let mut params: Vec<Value> = vec![bounds.south.into(), bounds.north.into()];
+    if bounds.west <= bounds.east {
+        sql.push_str(" AND min_lng >= ? AND max_lng <= ?)");
+    } else {
+        sql.push_str(" AND (min_lng >= ? OR max_lng <= ?))");
+    }
+    params.push(bounds.west.into());
+    params.push(bounds.east.into());
+    FilterClause::build(conn, filters)?.push_to(&mut sql, &mut params);
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+        Ok(GeoPoint {
+            id: row.get(0)?,
+            lat: row.get(1)?,
+            lng: row.get(2)?,
+            thumb_path: row.get(3)?,
+            rating: row.get(4)?,
+            picked: row.get::<_, i64>(5)? == 1,
+            date_taken: row.get(6)?,
+        })
+    })?;
+    let mut points = Vec::new();
+    for row in rows {
+        points.push(row?);
+    }
+    Ok(points)
+}
+
+pub fn get_smart_view_counts(conn: &DbConnection) -> Result<SmartViewCounts> {
+    let unsorted = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE rating IS NULL AND picked = 0 AND rejected = 0",
+        [],
+        |row| row.get(0),
+    )?;
+    let picks = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE picked = 1 AND rejected = 0",
+        [],
+        |row| row.get(0),
+    )?;
+    let rejects = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE rejected = 1",
+        [],
+        |row| row.get(0),
+    )?;
+
+    let mut last_import = 0;
+    if let Some(batch_id) = latest_import_batch_id(conn)? {
+        last_import = conn.query_row(
+            "SELECT COUNT(*) FROM photos WHERE import_batch_id = ?1",
+            params![batch_id],
+            |row| row.get(0),
+        )?;
+    }
+
+    let all = conn.query_row("SELECT COUNT(*) FROM photos", [], |row| row.get(0))?;
+
+    Ok(SmartViewCounts {
+        unsorted,
+        picks,
+        rejects,
+        last_import,
+        all,
+        color_labels: list_color_labels(conn)?,
+    })
+}
+
+/// The color labels in display order with the number of photos carrying each.
+pub fn list_color_labels(conn: &DbConnection) -> Result<Vec<ColorLabel>> {
+    let mut stmt = conn.prepare(
+        "SELECT l.color, l.name,
+                (SELECT COUNT(*) FROM photos WHERE color_label = l.color)
+         FROM color_labels l ORDER BY l.position",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ColorLabel {
+            color: row.get(0)?,
+            name: row.get(1)?,
+            count: row.get(2)?,
+        })
+    })?;
+    let mut labels = Vec::new();
+    for row in rows {
+        labels.push(row?);
+    }
+    Ok(labels)
+}
+
+pub fn rename_color_label(conn: &DbConnection, color: &str, name: &str) -> Result<()> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Validation("Label name cannot be empty".into()));
+    }
+    let updated = conn.execute(
+        "UPDATE color_labels SET name = ?1 WHERE color = ?2",
+        params![name, color],
+    )?;
+    if updated == 0 {
+        return Err(Error::Validation(format!(
+            "Unknown color label '{}'",
+            color
+        )));
+    }
+    Ok(())
+}
+
+fn check_color_label(conn: &DbConnection, color: &str) -> Result<()> {
+    let known: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM color_labels WHERE color = ?1)",
+        params![color],
+        |row| row.get(0),
+    )?;
+    if !known {
+        return Err(Error::Validation(format!(
+            "Unknown color label '{}'",
+            color
+        )));
+    }
+    Ok(())
+}
+
+fn map_album(row: &rusqlite::Row) -> rusqlite::Result<Album> {
+    Ok(Album {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        cover_photo_id: row.get("cover_photo_id")?,
+        item_count: row.get("item_count")?,
+        created_at: row.get("created_at")?,
+        updated_at: row.get("updated_at")?,
+    })
+}
+
+const ALBUM_SELECT: &str = "SELECT a.id, a.name, a.created_at, a.updated_at,
+        coalesce(a.cover_photo_id, (
+            SELECT photo_id FROM album_items WHERE album_id = a.id ORDER BY position LIMIT 1
+        )) AS cover_photo_id,
+        (SELECT COUNT(*) FROM album_items WHERE album_id = a.id) AS item_count
+     FROM albums a";
+
+pub fn list_albums(conn: &DbConnection) -> Result<Vec<Album>> {
+    let mut stmt = conn.prepare(&format!(
+        "{ALBUM_SELECT} ORDER BY a.name COLLATE NOCASE, a.id"
+    ))?;
+    let rows = stmt.query_map([], map_album)?;
+    let mut albums = Vec::new();
+    for row in rows {
+        albums.push(row?);
+    }
+    Ok(albums)
+}
+
+pub fn get_album(conn: &DbConnection, album_id: i64) -> Result<Album> {
+    conn.query_row(
+        &format!("{ALBUM_SELECT} WHERE a.id = ?1"),
+        params![album_id],
+        map_album,
+    )
+    .optional()?
+    .ok_or_else(|| Error::NotFound(format!("Album {album_id}")))
+}
+
+fn album_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Validation("Album name cannot be empty".into()));
+    }
+    Ok(name)
+}
+
+pub fn create_album(conn: &DbConnection, name: &str) -> Result<Album> {
+    conn.execute(
+        "INSERT INTO albums (name) VALUES (?1)",
+        params![album_name(name)?],
+    )?;
+    get_album(conn, conn.last_insert_rowid())
+}
+
+pub fn rename_album(conn: &DbConnection, album_id: i64, name: &str) -> Result<Album> {
+    conn.execute(
+        "UPDATE albums SET name = ?1, updated_at = strftime('%s','now') WHERE id = ?2",
+        params![album_name(name)?, album_id],
+    )?;
+    get_album(conn, album_id)
+}
+
+pub fn delete_album(conn: &DbConnection, album_id: i64) -> Result<()> {
+    conn.execute("DELETE FROM albums WHERE id = ?1", params![album_id])?;
+    Ok(())
+}
+
+/// Photo ids of an album in album order.
+pub fn album_photo_ids(conn: &DbConnection, album_id: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT photo_id FROM album_items WHERE album_id = ?1 ORDER BY position, added_at",
+    )?;
+    let rows = stmt.query_map(params![album_id], |row| row.get::<_, i64>(0))?;
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+/// Rewrites the album's items to exactly `order`, numbering positions from 0.
+fn write_album_order(conn: &DbConnection, album_id: i64, order: &[i64]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        tx.execute(
+            "DELETE FROM album_items
+             WHERE album_id = ?1 AND photo_id NOT IN (SELECT value FROM json_each(?2))",
+            params![album_id, serde_json::to_string(order)?],
+        )?;
+        let mut upsert = tx.prepare(
+            "INSERT INTO album_items (album_id, photo_id, position) VALUES (?1, ?2, ?3)
+             ON CONFLICT(album_id, photo_id) DO UPDATE SET position = excluded.position",
+        )?;
+        for (position, photo_id) in order.iter().enumerate() {
+            upsert.execute(params![album_id, photo_id, position as i64])?;
+        }
+        tx.execute(
+            "UPDATE albums SET updated_at = strftime('%s','now') WHERE id = ?1",
+            params![album_id],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Adds photos at `index` (the end when `None`). Photos already in the album stay where they are.
+pub fn add_to_album(
+    conn: &DbConnection,
+    album_id: i64,
+    photo_ids: &[i64],
+    index: Option<usize>,
+) -> Result<Album> {
+    get_album(conn, album_id)?;
+    let mut order = album_photo_ids(conn, album_id)?;
+    let present: HashSet<i64> = order.iter().copied().collect();
+    let mut seen = HashSet::new();
+    let mut added = Vec::new();
+    for &photo_id in photo_ids {
+        if present.contains(&photo_id) || !seen.insert(photo_id) {
+            continue;
+        }
+        let exists: Option<i64> = conn
+            .query_row(
+                "SELECT id FROM photos WHERE id = ?1",
+                params![photo_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if exists.is_some() {
+            added.push(photo_id);
+        }
+    }
+    let at = index.unwrap_or(order.len()).min(order.len());
+    order.splice(at..at, added);
+    write_album_order(conn, album_id, &order)?;
+    get_album(conn, album_id)
+}
+
+pub fn remove_from_album(conn: &DbConnection, album_id: i64, photo_ids: &[i64]) -> Result<Album> {
+    let removed: HashSet<i64> = photo_ids.iter().copied().collect();
+    let order: Vec<i64> = album_photo_ids(conn, album_id)?
+        .into_iter()
+        .filter(|id| !removed.contains(id))
+        .collect();
+    write_album_order(conn, album_id, &order)?;
+    conn.execute(
+        "UPDATE albums SET cover_photo_id = NULL
+         WHERE id = ?1 AND cover_photo_id NOT IN (SELECT photo_id FROM album_items WHERE album_id = ?1)",
+        params![album_id],
+    )?;
+    get_album(conn, album_id)
+}
+
+/// Puts `photo_ids` first, in that order; album photos not listed follow in their current order.
+pub fn reorder_album(conn: &DbConnection, album_id: i64, photo_ids: &[i64]) -> Result<Album> {
+    let current = album_photo_ids(conn, album_id)?;
+    let members: HashSet<i64> = current.iter().copied().collect();
+    let mut seen = HashSet::new();
+    let mut order: Vec<i64> = photo_ids
+        .iter()
+        .copied()
+        .filter(|id| members.contains(id) && seen.insert(*id))
+        .collect();
+    order.extend(current.into_iter().filter(|id| !seen.contains(id)));
+    write_album_order(conn, album_id, &order)?;
+    get_album(conn, album_id)
+}
+
+/// Sets the cover photo, or goes back to the first photo when `photo_id` is `None`.
+pub fn set_album_cover(conn: &DbConnection, album_id: i64, photo_id: Option<i64>) -> Result<Album> {
+    if let Some(photo_id) = photo_id {
+        let member: Option<i64> = conn
+            .query_row(
+                "SELECT 1 FROM album_items WHERE album_id = ?1 AND photo_id = ?2",
+                params![album_id, photo_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if member.is_none() {
+            return Err(Error::Validation(format!(
+                "Photo {photo_id} is not in album {album_id}"
+            )));
+        }
+    }
+    conn.execute(
+        "UPDATE albums SET cover_photo_id = ?1, updated_at = strftime('%s','now') WHERE id = ?2",
+        params![photo_id, album_id],
+    )?;
+    get_album(conn, album_id)
+}
+
+/// Copies an album with its order and cover. The copy is called "<name> copy" unless named.
+pub fn duplicate_album(conn: &DbConnection, album_id: i64, name: Option<&str>) -> Result<Album> {
+    let source = get_album(conn, album_id)?;
+    let name = match name {
+        Some(name) => album_name(name)?.to_string(),
+        None => format!("{} copy", source.name),
+    };
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO albums (name, cover_photo_id)
+         SELECT ?1, cover_photo_id FROM albums WHERE id = ?2",
+        params![name, album_id],
+    )?;
+    let copy_id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO album_items (album_id, photo_id, position)
+         SELECT ?1, photo_id, position FROM album_items WHERE album_id = ?2",
+        params![copy_id, album_id],
+    )?;
+    tx.commit()?;
+    get_album(conn, copy_id)
+}
+
+/// `smart_view` values of the form `collection:<id>` select a saved smart collection.
+pub const COLLECTION_VIEW_PREFIX: &str = "collection:";
+
+pub(crate) fn collection_id(smart_view: &str) -> Option<i64> {
+    smart_view
+        .strip_prefix(COLLECTION_VIEW_PREFIX)
+        .and_then(|id| id.trim().parse().ok())
+}
+
+/// Saved filters of a (non-folder) collection. References to other collections are dropped so
+/// collections can never recurse into each other.
+pub(crate) fn collection_filters(conn: &DbConnection, id: i64) -> Result<Option<QueryFilters>> {
+    let json: Option<String> = conn
+        .query_row(
+            "SELECT filters FROM smart_collections WHERE id = ?1 AND is_folder = 0",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    let Some(json) = json else {
+        return Ok(None);
+    };
+    Ok(Some(without_collection_ref(serde_json::from_str(&json)?)))
+}
+
+fn without_collection_ref(mut filters: QueryFilters) -> QueryFilters {
+    if filters
+        .smart_view
+        .as_deref()
+        .and_then(collection_id)
+        .is_some()
+    {
+        filters.smart_view = None;
+    }
+    filters
+}
+
+fn count_matching(conn: &DbConnection, filters: &QueryFilters) -> Result<i64> {
+    let mut sql = "SELECT COUNT(*) FROM photos WHERE 1=1".to_string();
+    let mut params: Vec<Value> = Vec::new();
+    FilterClause::build(conn, filters)?.push_to(&mut sql, &mut params);
+    Ok(conn.query_row(&sql, rusqlite::params_from_iter(params), |row| row.get(0))?)
+}
+
+/// Every collection and folder in display order (by parent, then position), with live counts.
+pub fn list_smart_collections(conn: &DbConnection) -> Result<Vec<SmartCollection>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, parent_id, name, is_folder, filters, position FROM smart_collections
+         ORDER BY parent_id IS NOT NULL, parent_id, position, id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<i64>>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, i64>(3)? == 1,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, i64>(5)?,
+        ))
+    })?;
+    let mut collections = Vec::new();
+    for row in rows {
+        let (id, parent_id, name, is_folder, filters, position) = row?;
+        let filters = match filters {
+            Some(json) => Some(serde_json::from_str::<QueryFilters>(&json)?),
+            None => None,
+        };
+        let count = match (&filters, is_folder) {
+            (Some(saved), false) => {
+                match count_matching(conn, &without_collection_ref(saved.clone())) {
+                    Ok(count) => Some(count),
+                    Err(err) => {
+                        log::warn!("Counting smart collection '{}' failed: {}", name, err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        collections.push(SmartCollection {
+            id,
+            parent_id,
+            name,
+            is_folder,
+            filters,
+            position,
+            count,
+        });
+    }
+    Ok(collections)
+}
+
+pub fn get_smart_collection(conn: &DbConnection, id: i64) -> Result<Option<SmartCollection>> {
+    Ok(list_smart_collections(conn)?
+        .into_iter()
+        .find(|collection| collection.id == id))
+}
+
+/// Checks `parent_id` is a folder and is not `id` itself or one of its descendants.
+fn check_collection_parent(conn: &DbConnection, id: Option<i64>, parent_id: i64) -> Result<()> {
+    let mut current = Some(parent_id);
+    let mut first = true;
+    while let Some(node) = current {
+        if Some(node) == id {
+            return Err(Error::Validation(
+                "A folder cannot be moved into itself".into(),
+            ));
+        }
+        let row: Option<(Option<i64>, i64)> = conn
+            .query_row(
+                "SELECT parent_id, is_folder FROM smart_collections WHERE id = ?1",
+                params![node],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((parent, is_folder)) = row else {
+            return Err(Error::NotFound(format!("Folder {node}")));
+        };
+        if first && is_folder == 0 {
+            return Err(Error::Validation(
+                "Collections can only be nested in folders".into(),
+            ));
+        }
+        first = false;
+        current = parent;
+    }
+    Ok(())
+}
+
+/// Validates an input and returns the filters JSON to store.
+fn collection_filters_json(
+    conn: &DbConnection,
+    id: Option<i64>,
+    is_folder: bool,
+    input: &SmartCollectionInput,
+) -> Result<Option<String>> {
+    if input.name.trim().is_empty() {
+        return Err(Error::Validation("Collection name cannot be empty".into()));
+    }
+    if let Some(parent_id) = input.parent_id {
+        check_collection_parent(conn, id, parent_id)?;
+    }
+    if is_folder {
+        return Ok(None);
+    }
+    let Some(filters) = input.filters.as_ref() else {
+        return Err(Error::Validation("A smart collection needs filters".into()));
+    };
+    if filters
+        .smart_view
+        .as_deref()
+        .and_then(collection_id)
+        .is_some()
+    {
+        return Err(Error::Validation(
+            "A smart collection cannot be based on another collection".into(),
+        ));
+    }
+    // Building the clauses parses the query expression, so bad input is rejected here.
+    FilterClause::build(conn, filters)?;
+    let filters = QueryFilters {
+        limit: None,
+        offset: None,
+        cursor: None,
+        ..filters.clone()
+    };
+    Ok(Some(serde_json::to_string(&filters)?))
+}
+
+pub fn create_smart_collection(
+    conn: &DbConnection,
+    input: &SmartCollectionInput,
+) -> Result<SmartCollection> {
+    let filters = collection_filters_json(conn, None, input.is_folder, input)?;
+    conn.execute(
+        "INSERT INTO smart_collections (parent_id, name, is_folder, filters, position)
+         VALUES (?1, ?2, ?3, ?4,
+            (SELECT coalesce(MAX(position), -1) + 1 FROM smart_collections WHERE parent_id IS ?1))",
+        params![
+            input.parent_id,
+            input.name.trim(),
+            input.is_folder as i64,
+            filters
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    get_smart_collection(conn, id)?.ok_or_else(|| Error::NotFound(format!("Smart collection {id}")))
+}
+
+/// Renames, moves or re-targets a collection. Whether it is a folder cannot change.
+pub fn update_smart_collection(
+    conn: &DbConnection,
+    id: i64,
+    input: &SmartCollectionInput,
+) -> Result<SmartCollection> {
+    let is_folder: Option<i64> = conn
+        .query_row(
+            "SELECT is_folder FROM smart_collections WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(is_folder) = is_folder else {
+        return Err(Error::NotFound(format!("Smart collection {id}")));
+    };
+    let filters = collection_filters_json(conn, Some(id), is_folder == 1, input)?;
+    conn.execute(
+        "UPDATE smart_collections SET
+            position = CASE WHEN parent_id IS ?1 THEN position
+                ELSE (SELECT coalesce(MAX(position), -1) + 1 FROM smart_collections WHERE parent_id IS ?1)
+            END,
+            parent_id = ?1, name = ?2, filters = ?3, updated_at = strftime('%s','now')
+         WHERE id = ?4",
+        params![input.parent_id, input.name.trim(), filters, id],
+    )?;
+    get_smart_collection(conn, id)?.ok_or_else(|| Error::NotFound(format!("Smart collection {id}")))
 }
 
-pub fn batch_update_cull(
-    conn: &DbConnection,
-    photo_ids: &[i64],
-    rating: Option<Option<i64>>,
-    picked: Option<bool>,
-    rejected: Option<bool>,
-) -> Result<usize> {
-    if photo_ids.is_empty() {
-        return Ok(0);
-    }
-
-    let mut sets: Vec<String> = Vec::new();
-    let mut params: Vec<Value> = Vec::new();
+/// Deletes a collection, or a folder with everything in it.
+pub fn delete_smart_collection(conn: &DbConnection, id: i64) -> Result<usize> {
+    Ok(conn.execute(
+        "WITH RECURSIVE subtree(id) AS (
+            SELECT ?1
+            UNION ALL
+            SELECT c.id FROM smart_collections c JOIN subtree s ON c.parent_id = s.id
+         )
+         DELETE FROM smart_collections WHERE id IN subtree",
+        params![id],
+    )?)
+}
 
-    if let Some(rating_value) = rating {
-        sets.push("rating = ?".into());
-        match rating_value {
-            Some(v) => params.push(v.into()),
-            None => params.push(Value::Null),
+/// Places `ids` under `parent_id` (the top level when `None`) in the given order.
+pub fn reorder_smart_collections(
+    conn: &DbConnection,
+    parent_id: Option<i64>,
+    ids: &[i64],
+) -> Result<()> {
+    if let Some(parent_id) = parent_id {
+        for id in ids {
+            check_collection_parent(conn, Some(*id), parent_id)?;
         }
     }
-    if let Some(p) = picked {
-        sets.push("picked = ?".into());
-        params.push((p as i64).into());
-    }
-    if let Some(r) = rejected {
-        sets.push("rejected = ?".into());
-        params.push((r as i64).into());
-    }
-
-    if sets.is_empty() {
-        return Ok(0);
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE smart_collections SET parent_id = ?1, position = ?2,
+                updated_at = strftime('%s','now')
+             WHERE id = ?3",
+        )?;
+        for (position, id) in ids.iter().enumerate() {
+            stmt.execute(params![parent_id, position as i64, id])?;
+        }
     }
+    tx.commit()?;
+    Ok(())
+}
 
-    sets.push("last_modified = strftime('%s','now')".into());
-
-    let mut sql = format!("UPDATE photos SET {} WHERE id IN (", sets.join(", "));
-    for (idx, _) in photo_ids.iter().enumerate() {
-        if idx > 0 {
-            sql.push(',');
+/// Facets `facet_counts` understands, in the order they are returned by default.
+pub const FACETS: &[&str] = &[
+    "camera", "make", "lens", "tag", "year", "iso", "rating", "label", "country",
+];
+
+const ISO_BUCKET_STOPS: &[i64] = &[200, 400, 800, 1600, 3200, 6400];
+
+/// `(value, sort key)` expressions grouping a facet over the `matched` photos, or the whole
+/// SELECT for facets that are not photo columns.
+fn facet_select(facet: &str) -> Option<String> {
+    let (value, sort_key) = match facet {
+        "camera" => ("model".to_string(), "NULL"),
+        "make" => ("make".to_string(), "NULL"),
+        "lens" => ("lens".to_string(), "NULL"),
+        "country" => ("place_country".to_string(), "NULL"),
+        "label" => (
+            "color_label".to_string(),
+            "MIN((SELECT position FROM color_labels WHERE color = matched.color_label))",
+        ),
+        "year" => (
+            "strftime('%Y', date_taken, 'unixepoch')".to_string(),
+            "MIN(date_taken)",
+        ),
+        "rating" => ("CAST(rating AS TEXT)".to_string(), "MIN(rating)"),
+        "iso" => {
+            let mut case = "CASE WHEN iso IS NULL THEN NULL".to_string();
+            let mut low: Option<i64> = None;
+            for stop in ISO_BUCKET_STOPS {
+                let label = match low {
+                    Some(low) => format!("{low}..{}", stop - 1),
+                    None => format!("..{}", stop - 1),
+                };
+                case.push_str(&format!(" WHEN iso < {stop} THEN '{label}'"));
+                low = Some(*stop);
+            }
+            if let Some(low) = low {
+                case.push_str(&format!(" ELSE '{low}..'"));
+            }
+            case.push_str(" END");
+            (case, "MIN(iso)")
         }
-        sql.push('?');
+        "tag" => {
+            // (photo_id, tag) is unique, so rows per tag are photos per tag.
+            return Some(
+                "SELECT 'tag', tag, NULL, COUNT(*) FROM tags
+                 WHERE photo_id IN (SELECT id FROM matched) GROUP BY tag"
+                    .to_string(),
+            );
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "SELECT '{facet}', {value}, {sort_key}, COUNT(*) FROM matched GROUP BY 2"
+    ))
+}
+
+/// Counts the photos matching `filters` per value of each facet (all of [`FACETS`] when
+/// `facets` is empty), in one statement. Values come most frequent first, except for years,
+/// ISO buckets and ratings which are in ascending order; `limit` caps the values per facet.
+pub fn facet_counts(
+    conn: &DbConnection,
+    filters: &QueryFilters,
+    facets: &[String],
+    limit: Option<usize>,
+) -> Result<Vec<FacetCounts>> {
+    let facets: Vec<&str> = if facets.is_empty() {
+        FACETS.to_vec()
+    } else {
+        facets.iter().map(String::as_str).collect()
+    };
+    let mut selects = Vec::new();
+    for facet in &facets {
+        let select = facet_select(facet)
+            .ok_or_else(|| Error::Validation(format!("Unknown facet '{}'", facet)))?;
+        selects.push(select);
     }
-    sql.push(')');
 
-    for id in photo_ids {
-        params.push((*id).into());
+    let mut sql = "WITH matched AS (SELECT * FROM photos WHERE 1=1".to_string();
+    let mut params: Vec<Value> = Vec::new();
+    FilterClause::build(conn, filters)?.push_to(&mut sql, &mut params);
+    sql.push_str(&format!(
+        ") SELECT * FROM ({}) ORDER BY 1, 2 IS NULL, 3, 4 DESC, 2",
+        selects.join(" UNION ALL ")
+    ));
+
+    let mut grouped: HashMap<String, Vec<FacetValue>> = HashMap::new();
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            FacetValue {
+                value: row.get(1)?,
+                count: row.get(3)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (facet, value) = row?;
+        grouped.entry(facet).or_default().push(value);
     }
 
-    let updated = conn.execute(&sql, rusqlite::params_from_iter(params))?;
-    Ok(updated)
+    let mut seen = HashSet::new();
+    Ok(facets
+        .into_iter()
+        .filter(|facet| seen.insert(*facet))
+        .map(|facet| {
+            let mut values = grouped.remove(facet).unwrap_or_default();
+            if let Some(limit) = limit {
+                values.truncate(limit);
+            }
+            FacetCounts {
+                facet: facet.to_string(),
+                values,
+            }
+        })
+        .collect())
 }
 
-pub fn get_smart_view_counts(conn: &DbConnection) -> Result<SmartViewCounts> {
-    let unsorted = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE rating IS NULL AND picked = 0 AND rejected = 0",
-        [],
-        |row| row.get(0),
-    )?;
-    let picks = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE picked = 1 AND rejected = 0",
-        [],
-        |row| row.get(0),
-    )?;
-    let rejects = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE rejected = 1",
-        [],
-        |row| row.get(0),
-    )?;
-
-    let mut last_import = 0;
-    if let Some(batch_id) = latest_import_batch_id(conn)? {
-        last_import = conn.query_row(
-            "SELECT COUNT(*) FROM photos WHERE import_batch_id = ?1",
-            params![batch_id],
-            |row| row.get(0),
-        )?;
+/// Ids of every photo matching `filters`, ignoring sorting and paging.
+pub fn query_photo_ids(conn: &DbConnection, filters: &QueryFilters) -> Result<Vec<i64>> {
+    let mut sql = "SELECT id FROM photos WHERE 1=1".to_string();
+    let mut params: Vec<Value> = Vec::new();
+    FilterClause::build(conn, filters)?.push_to(&mut sql, &mut params);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| row.get::<_, i64>(0))?;
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
     }
+    Ok(ids)
+}
 
-    let all = conn.query_row("SELECT COUNT(*) FROM photos", [], |row| row.get(0))?;
+pub fn query_photos(conn: &DbConnection, filters: QueryFilters) -> Result<Vec<PhotoWithTags>> {
+    Ok(fetch_photos(conn, &filters)?.0)
+}
 
-    Ok(SmartViewCounts {
-        unsorted,
-        picks,
-        rejects,
-        last_import,
-        all,
+/// One page of `query_photos` with the total number of matches and, when the page is full,
+/// the cursor for the next one.
+pub fn query_photo_page(conn: &DbConnection, filters: QueryFilters) -> Result<PhotoPage> {
+    let (photos, next_cursor) = fetch_photos(conn, &filters)?;
+    let total = count_matching(conn, &filters)?;
+    Ok(PhotoPage {
+        photos,
+        total,
+        next_cursor,
     })
 }
 
-pub fn query_photos(conn: &DbConnection, filters: QueryFilters) -> Result<Vec<PhotoWithTags>> {
-    let mut sql = "SELECT * FROM photos WHERE 1=1".to_string();
-    let mut params: Vec<rusqlite::types::Value> = Vec::new();
+/// Position after the last photo of a page: the sort order it belongs to and that photo's sort
+/// key and id. Handed out hex-encoded so clients treat it as opaque.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct PageCursor {
+    order: String,
+    key: serde_json::Value,
+    id: i64,
+}
 
-    if let Some(search) = filters.search {
-        sql.push_str(" AND (file_name LIKE ? OR make LIKE ? OR model LIKE ? OR lens LIKE ?)");
-        let pattern = format!("%{}%", search);
-        for _ in 0..4 {
-            params.push(pattern.clone().into());
-        }
-    }
-    if let Some(make) = filters.camera_make {
-        sql.push_str(" AND make = ?");
-        params.push(make.into());
-    }
-    if let Some(model) = filters.camera_model {
-        sql.push_str(" AND model = ?");
-        params.push(model.into());
+impl PageCursor {
+    fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(self)?;
+        Ok(json.iter().map(|byte| format!("{byte:02x}")).collect())
     }
-    if let Some(lens) = filters.lens {
-        sql.push_str(" AND lens = ?");
-        params.push(lens.into());
-    }
-    if let Some(min_iso) = filters.iso_min {
-        sql.push_str(" AND iso >= ?");
-        params.push(min_iso.into());
-    }
-    if let Some(max_iso) = filters.iso_max {
-        sql.push_str(" AND iso <= ?");
-        params.push(max_iso.into());
-    }
-    if let Some(min_ap) = filters.aperture_min {
-        sql.push_str(" AND fnumber >= ?");
-        params.push(min_ap.into());
-    }
-    if let Some(max_ap) = filters.aperture_max {
-        sql.push_str(" AND fnumber <= ?");
-        params.push(max_ap.into());
-    }
-    if let Some(min_focal) = filters.focal_min {
-        sql.push_str(" AND focal_length >= ?");
-        params.push(min_focal.into());
-    }
-    if let Some(max_focal) = filters.focal_max {
-        sql.push_str(" AND focal_length <= ?");
-        params.push(max_focal.into());
-    }
-    if let Some(date_from) = filters.date_from {
-        sql.push_str(" AND date_taken >= ?");
-        params.push(date_from.into());
-    }
-    if let Some(date_to) = filters.date_to {
-        sql.push_str(" AND date_taken <= ?");
-        params.push(date_to.into());
-    }
-    if let Some(has_gps) = filters.has_gps {
-        if has_gps {
-            sql.push_str(" AND gps_lat IS NOT NULL AND gps_lng IS NOT NULL");
-        } else {
-            sql.push_str(" AND (gps_lat IS NULL OR gps_lng IS NULL)");
+
+    fn decode(cursor: &str) -> Result<Self> {
+        let invalid = || Error::Validation("Invalid page cursor".into());
+        if cursor.len() % 2 != 0 || !cursor.is_ascii() {
+            return Err(invalid());
         }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
     }
+}
 
-    if !filters.tags.is_empty() {
-        sql.push_str(" AND id IN (SELECT photo_id FROM tags WHERE tag IN (");
-        for (i, tag) in filters.tags.iter().enumerate() {
-            if i > 0 {
-                sql.push(',');
-            }
-            sql.push('?');
-            params.push(tag.clone().into());
-        }
-        sql.push_str("))");
+/// Tag rows as aggregated by `json_group_array` in `fetch_photos`.
+#[derive(Deserialize)]
+struct TagJson {
+    id: i64,
+    tag: String,
+    confidence: Option<f32>,
+    source: String,
+    locked: i64,
+    created_at: Option<i64>,
+}
+
+fn sql_value_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Integer(i) => i.into(),
+        Value::Real(f) => f.into(),
+        Value::Text(s) => s.into(),
+        Value::Null | Value::Blob(_) => serde_json::Value::Null,
     }
+}
 
-    if let Some(smart_view) = filters.smart_view.as_deref() {
-        match smart_view {
-            "UNSORTED" => {
-                sql.push_str(" AND rating IS NULL AND picked = 0 AND rejected = 0");
-            }
-            "PICKS" => {
-                sql.push_str(" AND picked = 1 AND rejected = 0");
-            }
-            "REJECTS" => {
-                sql.push_str(" AND rejected = 1");
-            }
-            "LAST_IMPORT" => {
-                if let Some(batch_id) = latest_import_batch_id(conn)? {
-                    sql.push_str(" AND import_batch_id = ?");
-                    params.push(batch_id.into());
-                } else {
-                    sql.push_str(" AND 0");
-                }
+/// Runs a photo listing: the filtered photos ordered by a single sort key with the id as tie
+/// breaker, so a cursor can resume right after the last row. Tags come in the same statement.
+fn fetch_photos(
+    conn: &DbConnection,
+    filters: &QueryFilters,
+) -> Result<(Vec<PhotoWithTags>, Option<String>)> {
+    // Searches are ranked by relevance unless another sort order is asked for. bm25 weights
+    // favour file names and tags over folders, captions and camera fields.
+    let ranking = filters
+        .search
+        .as_deref()
+        .and_then(filter::search_match_expression)
+        .filter(|_| match filters.sort_by.as_deref() {
+            Some("relevance") => true,
+            None => filters.album_id.is_none(),
+            _ => false,
+        });
+    let album_order = filters
+        .album_id
+        .filter(|_| matches!(filters.sort_by.as_deref(), None | Some("album")));
+
+    let mut params: Vec<Value> = Vec::new();
+    // (sort key expression, descending, name of the order for cursors)
+    let (sort_key, descending, order) = if ranking.is_some() {
+        // bm25 scores are lower for better matches.
+        (
+            "ranked.search_rank".to_string(),
+            false,
+            "relevance".to_string(),
+        )
+    } else if let Some(album_id) = album_order {
+        params.push(album_id.into());
+        (
+            "(SELECT position FROM album_items WHERE album_id = ? AND photo_id = photos.id)"
+                .to_string(),
+            false,
+            format!("album:{album_id}"),
+        )
+    } else {
+        let column = if filters.sort_by.is_none() {
+            if matches!(filters.mode.as_deref(), Some(mode) if mode.eq_ignore_ascii_case("cull")) {
+                "last_modified"
+            } else {
+                "date_taken"
             }
-            _ => {}
+        } else {
+            resolve_sort_column(filters.sort_by.as_deref())
+        };
+        let dir = resolve_sort_dir(filters.sort_dir.as_deref());
+        (column.to_string(), dir == "DESC", format!("{column}:{dir}"))
+    };
+
+    let mut sql = format!(
+        "SELECT page.*,
+                (SELECT json_group_array(json_object(
+                    'id', id, 'tag', tag, 'confidence', confidence, 'source', source,
+                    'locked', locked, 'created_at', created_at))
+                 FROM tags WHERE photo_id = page.id) AS tags_json
+         FROM (SELECT photos.*, {sort_key} AS sort_key FROM photos"
+    );
+    match ranking {
+        Some(expr) => {
+            sql.push_str(
+                " JOIN (
+                    SELECT rowid AS search_id,
+                           bm25(photo_search, 4.0, 2.0, 3.0, 1.0, 1.0, 2.0) AS search_rank
+                    FROM photo_search WHERE photo_search MATCH ?
+                 ) ranked ON ranked.search_id = photos.id",
+            );
+            params.push(expr.into());
         }
+        None => {}
     }
-
-    let sort_by = if filters.sort_by.is_none() {
-        if matches!(filters.mode.as_deref(), Some(mode) if mode.eq_ignore_ascii_case("cull")) {
-            "last_modified"
+    sql.push_str(" WHERE 1=1");
+    FilterClause::build(conn, filters)?.push_to(&mut sql, &mut params);
+    sql.push_str(") page");
+
+    // NULL keys sort first ascending and last descending, as SQLite orders them.
+    let cursor = filters
+        .cursor
+        .as_deref()
+        .map(PageCursor::decode)
+        .transpose()?;
+    if let Some(cursor) = &cursor {
+        if cursor.order != order {
+            return Err(Error::Validation(
+                "The page cursor belongs to a different sort order".into(),
+            ));
+        }
+        let key = filter::json_to_sql_value(&cursor.key);
+        let (after, rest) = if descending {
+            ("<", " OR sort_key IS NULL")
+        } else {
+            (">", "")
+        };
+        if key == Value::Null {
+            if descending {
+                sql.push_str(" WHERE sort_key IS NULL AND id < ?");
+            } else {
+                sql.push_str(" WHERE (sort_key IS NULL AND id > ?) OR sort_key IS NOT NULL");
+            }
         } else {
-            "date_taken"
+            sql.push_str(&format!(
+                " WHERE sort_key {after} ? OR (sort_key = ? AND id {after} ?){rest}"
+            ));
+            params.push(key.clone());
+            params.push(key);
         }
+        params.push(cursor.id.into());
+    }
+
+    let dir = if descending { "DESC" } else { "ASC" };
+    sql.push_str(&format!(
+        " ORDER BY sort_key {dir}, id {dir} LIMIT ? OFFSET ?"
+    ));
+    // A negative limit means no limit; the cursor replaces the offset.
+    let limit = filters.limit.filter(|limit| *limit >= 0);
+    params.push(limit.unwrap_or(-1).into());
+    let offset = if cursor.is_some() {
+        0
     } else {
-        resolve_sort_column(filters.sort_by.as_deref())
+        filters.offset.unwrap_or(0).max(0)
     };
-    let sort_dir = resolve_sort_dir(filters.sort_dir.as_deref());
-    sql.push_str(&format!(" ORDER BY {} {}", sort_by, sort_dir));
-    if let Some(limit) = filters.limit {
-        sql.push_str(&format!(" LIMIT {}", limit));
-    }
-    if let Some(offset) = filters.offset {
-        sql.push_str(&format!(" OFFSET {}", offset));
-    }
+    params.push(offset.into());
 
     let mut stmt = conn.prepare(&sql)?;
     let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
     let mut results = Vec::new();
+    let mut last_key = None;
     while let Some(row) = rows.next()? {
         let photo = PhotoRecord {
             id: Some(row.get("id")?),
@@ -621,6 +2476,7 @@ pub fn query_photos(conn: &DbConnection, filters: QueryFilters) -> Result<Vec<Ph
             model: row.get("model")?,
             lens: row.get("lens")?,
             date_taken: row.get("date_taken")?,
+            date_taken_original: row.get("date_taken_original")?,
             iso: row.get("iso")?,
             fnumber: row.get("fnumber")?,
             focal_length: row.get("focal_length")?,
@@ -628,22 +2484,57 @@ pub fn query_photos(conn: &DbConnection, filters: QueryFilters) -> Result<Vec<Ph
             exposure_comp: row.get("exposure_comp")?,
             gps_lat: row.get("gps_lat")?,
             gps_lng: row.get("gps_lng")?,
+            place_country: row.get("place_country")?,
+            place_region: row.get("place_region")?,
+            place_city: row.get("place_city")?,
             thumb_path: row.get("thumb_path")?,
             preview_path: row.get("preview_path")?,
+            orientation: row.get("orientation")?,
+            color_space: row.get("color_space")?,
             dhash: row.get("dhash")?,
             rating: row.get("rating")?,
             picked: row.get::<_, i64>("picked")? == 1,
             rejected: row.get::<_, i64>("rejected")? == 1,
+            color_label: row.get("color_label")?,
+            title: row.get("title")?,
+            caption: row.get("caption")?,
+            notes: row.get("notes")?,
             last_modified: row.get("last_modified")?,
             import_batch_id: row.get("import_batch_id")?,
             created_at: row.get("created_at")?,
             updated_at: row.get("updated_at")?,
         };
-        let tags = query_tags(conn, photo.id.unwrap())?;
+        let photo_id = row.get::<_, i64>("id")?;
+        let tags_json: String = row.get("tags_json")?;
+        let tags = serde_json::from_str::<Vec<TagJson>>(&tags_json)?
+            .into_iter()
+            .map(|tag| TagRecord {
+                id: Some(tag.id),
+                photo_id,
+                tag: tag.tag,
+                confidence: tag.confidence,
+                source: tag.source,
+                locked: tag.locked == 1,
+                created_at: tag.created_at,
+            })
+            .collect();
+        last_key = Some((row.get::<_, Value>("sort_key")?, photo_id));
         results.push(PhotoWithTags { photo, tags });
     }
 
-    Ok(results)
+    let page_full = matches!(limit, Some(limit) if limit > 0 && results.len() as i64 == limit);
+    let next_cursor = match last_key.filter(|_| page_full) {
+        Some((key, id)) => Some(
+            PageCursor {
+                order,
+                key: sql_value_to_json(key),
+                id,
+            }
+            .encode()?,
+        ),
+        None => None,
+    };
+    Ok((results, next_cursor))
 }
 
 pub fn query_tags(conn: &DbConnection, photo_id: i64) -> Result<Vec<TagRecord>> {
@@ -682,6 +2573,7 @@ pub fn get_photo(conn: &DbConnection, photo_id: i64) -> Result<Option<PhotoWithT
             model: row.get("model")?,
             lens: row.get("lens")?,
             date_taken: row.get("date_taken")?,
+            date_taken_original: row.get("date_taken_original")?,
             iso: row.get("iso")?,
             fnumber: row.get("fnumber")?,
             focal_length: row.get("focal_length")?,
@@ -689,12 +2581,21 @@ pub fn get_photo(conn: &DbConnection, photo_id: i64) -> Result<Option<PhotoWithT
             exposure_comp: row.get("exposure_comp")?,
             gps_lat: row.get("gps_lat")?,
             gps_lng: row.get("gps_lng")?,
+            place_country: row.get("place_country")?,
+            place_region: row.get("place_region")?,
+            place_city: row.get("place_city")?,
             thumb_path: row.get("thumb_path")?,
             preview_path: row.get("preview_path")?,
+            orientation: row.get("orientation")?,
+            color_space: row.get("color_space")?,
             dhash: row.get("dhash")?,
             rating: row.get("rating")?,
             picked: row.get::<_, i64>("picked")? == 1,
             rejected: row.get::<_, i64>("rejected")? == 1,
+            color_label: row.get("color_label")?,
+            title: row.get("title")?,
+            caption: row.get("caption")?,
+            notes: row.get("notes")?,
             last_modified: row.get("last_modified")?,
             import_batch_id: row.get("import_batch_id")?,
             created_at: row.get("created_at")?,
@@ -721,6 +2622,9 @@ pub fn export_csv(conn: &DbConnection, filters: QueryFilters) -> Result<Vec<CsvE
             fnumber: p.photo.fnumber,
             focal: p.photo.focal_length,
             shutter: p.photo.exposure_time,
+            title: p.photo.title.clone(),
+            caption: p.photo.caption.clone(),
+            notes: p.photo.notes.clone(),
             tags: p.tags.iter().map(|t| t.tag.clone()).collect(),
         })
         .collect();
@@ -937,9 +2841,106 @@ impl UnionFind {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
+    /// A migrated in-memory library on a single-connection pool.
+    pub(crate) fn test_conn() -> DbConnection {
+        let manager = SqliteConnectionManager::memory()
+            .with_init(|conn| metadata::register_sql_functions(conn));
+        let pool = r2d2::Pool::builder().max_size(1).build(manager).unwrap();
+        let conn = pool.get().unwrap();
+        run_migrations(&conn).unwrap();
+        conn
+    }
+
+    fn test_photo(path: &str, date_taken: Option<i64>, mtime: i64) -> PhotoRecord {
+        PhotoRecord {
+            path: path.to_string(),
+            hash: format!("hash-{path}"),
+            file_name: path.to_string(),
+            ext: "jpg".to_string(),
+            mtime,
+            date_taken,
+            ..Default::default()
+        }
+    }
+
+    fn capture_times(conn: &DbConnection, id: i64) -> (Option<i64>, Option<i64>) {
+        conn.query_row(
+            "SELECT date_taken, date_taken_original FROM photos WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn time_shift_applies_reverts_and_survives_reimport() {
+        let conn = test_conn();
+        let id = upsert_photo(&conn, &test_photo("a.jpg", Some(1_000), 1)).unwrap();
+        let shift = TimeShiftRequest {
+            photo_ids: vec![id],
+            offset_seconds: Some(3_600),
+            ..Default::default()
+        };
+        let items = apply_time_shift(&conn, &shift).unwrap();
+        assert_eq!((items[0].date_taken, items[0].shifted), (1_000, 4_600));
+        assert_eq!(capture_times(&conn, id), (Some(4_600), Some(1_000)));
+
+        // A second shift stacks on top and keeps the first original.
+        apply_time_shift(&conn, &shift).unwrap();
+        assert_eq!(capture_times(&conn, id), (Some(8_200), Some(1_000)));
+
+        let by_reference = TimeShiftRequest {
+            photo_ids: vec![id],
+            reference_photo_id: Some(id),
+            reference_time: Some(8_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            preview_time_shift(&conn, &by_reference)
+                .unwrap()
+                .offset_seconds,
+            -200
+        );
+
+        // A changed file brings a new camera time; the shift is carried over onto it.
+        upsert_photo(&conn, &test_photo("a.jpg", Some(2_000), 2)).unwrap();
+        assert_eq!(capture_times(&conn, id), (Some(9_200), Some(2_000)));
+
+        let reverted = revert_time_shift(&conn, &[id]).unwrap();
+        assert_eq!(
+            (reverted[0].date_taken, reverted[0].shifted),
+            (9_200, 2_000)
+        );
+        assert_eq!(capture_times(&conn, id), (Some(2_000), None));
+        assert!(revert_time_shift(&conn, &[id]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn iso_facet_buckets_use_range_syntax() {
+        let select = facet_select("iso").unwrap();
+        assert!(select.contains("WHEN iso < 200 THEN '..199'"));
+        assert!(select.contains("WHEN iso < 800 THEN '400..799'"));
+        assert!(select.contains("ELSE '6400..'"));
+        assert!(facet_select("shoe_size").is_none());
+    }
+
+    #[test]
+    fn page_cursor_round_trips_and_rejects_garbage() {
+        let cursor = PageCursor {
+            order: "date_taken:DESC".into(),
+            key: serde_json::json!(1_700_000_000),
+            id: 42,
+        };
+        let encoded = cursor.encode().unwrap();
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(PageCursor::decode(&encoded).unwrap(), cursor);
+        assert!(PageCursor::decode("zz").is_err());
+        assert!(PageCursor::decode("7b7d").is_err());
+    }
+
     #[test]
     fn hamming_distance_counts_bits() {
         assert_eq!(hamming_distance(0b1010, 0b0011), 3);
@@ -970,4 +2971,477 @@ mod tests {
         assert!(score_same > 0.99);
         assert!(score_orthogonal.abs() < 0.01);
     }
+
+    #[test]
+    fn cache_source_and_batched_touches() {
+        let conn = test_conn();
+        let mut photo = test_photo("a.jpg", None, 1);
+        photo.orientation = Some(6);
+        let id = upsert_photo(&conn, &photo).unwrap();
+        assert_eq!(
+            get_photo_cache_source(&conn, id).unwrap(),
+            Some(("hash-a.jpg".to_string(), Some(6)))
+        );
+        assert_eq!(get_photo_cache_source(&conn, id + 1).unwrap(), None);
+
+        for key in ["k1", "k2"] {
+            register_cache_entry(&conn, key, key, "thumb", "", "hash-a.jpg", 1).unwrap();
+        }
+        conn.execute("UPDATE cache_entries SET last_accessed = 0", [])
+            .unwrap();
+        touch_cache_entries(&conn, &["k2".to_string()]).unwrap();
+        let order: Vec<String> = list_cache_entries(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(order, ["k1", "k2"]);
+        let touched: i64 = conn
+            .query_row(
+                "SELECT last_accessed FROM cache_entries WHERE key = 'k2'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(touched > 0);
+    }
+
+    fn folder(conn: &DbConnection, name: &str, parent_id: Option<i64>) -> i64 {
+        let input = SmartCollectionInput {
+            name: name.to_string(),
+            parent_id,
+            is_folder: true,
+            filters: None,
+        };
+        create_smart_collection(conn, &input).unwrap().id
+    }
+
+    fn collection(conn: &DbConnection, name: &str, parent_id: Option<i64>) -> SmartCollection {
+        let input = SmartCollectionInput {
+            name: name.to_string(),
+            parent_id,
+            is_folder: false,
+            filters: Some(QueryFilters::default()),
+        };
+        create_smart_collection(conn, &input).unwrap()
+    }
+
+    fn positions(conn: &DbConnection, parent_id: Option<i64>) -> Vec<(String, i64)> {
+        list_smart_collections(conn)
+            .unwrap()
+            .into_iter()
+            .filter(|collection| collection.parent_id == parent_id)
+            .map(|collection| (collection.name, collection.position))
+            .collect()
+    }
+
+    #[test]
+    fn collections_nest_in_folders_without_cycles() {
+        let conn = test_conn();
+        let outer = folder(&conn, "Outer", None);
+        let inner = folder(&conn, "Inner", Some(outer));
+        let saved = collection(&conn, "Saved", Some(inner));
+        assert_eq!(saved.parent_id, Some(inner));
+        assert_eq!(saved.count, Some(0));
+
+        let move_outer = |parent_id| SmartCollectionInput {
+            name: "Outer".into(),
+            parent_id: Some(parent_id),
+            is_folder: true,
+            filters: None,
+        };
+        assert!(matches!(
+            update_smart_collection(&conn, outer, &move_outer(inner)),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            update_smart_collection(&conn, outer, &move_outer(outer)),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            update_smart_collection(&conn, outer, &move_outer(saved.id)),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            update_smart_collection(&conn, outer, &move_outer(999)),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            reorder_smart_collections(&conn, Some(inner), &[outer]),
+            Err(Error::Validation(_))
+        ));
+
+        let referencing = SmartCollectionInput {
+            name: "Nested".into(),
+            parent_id: None,
+            is_folder: false,
+            filters: Some(QueryFilters {
+                smart_view: Some(format!("{COLLECTION_VIEW_PREFIX}{}", saved.id)),
+                ..Default::default()
+            }),
+        };
+        assert!(matches!(
+            create_smart_collection(&conn, &referencing),
+            Err(Error::Validation(_))
+        ));
+
+        assert_eq!(delete_smart_collection(&conn, outer).unwrap(), 3);
+        assert!(list_smart_collections(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn collection_positions_are_renumbered() {
+        let conn = test_conn();
+        let a = collection(&conn, "A", None).id;
+        let b = collection(&conn, "B", None).id;
+        let c = collection(&conn, "C", None).id;
+        assert_eq!(
+            positions(&conn, None),
+            [("A".into(), 0), ("B".into(), 1), ("C".into(), 2)]
+        );
+
+        reorder_smart_collections(&conn, None, &[c, a, b]).unwrap();
+        assert_eq!(
+            positions(&conn, None),
+            [("C".into(), 0), ("A".into(), 1), ("B".into(), 2)]
+        );
+
+        // Moving into a folder appends; renaming in place keeps the position.
+        let folder_id = folder(&conn, "Folder", None);
+        let existing = collection(&conn, "Existing", Some(folder_id));
+        assert_eq!(existing.position, 0);
+        let moved = SmartCollectionInput {
+            name: "A moved".into(),
+            parent_id: Some(folder_id),
+            is_folder: false,
+            filters: Some(QueryFilters::default()),
+        };
+        let updated = update_smart_collection(&conn, a, &moved).unwrap();
+        assert_eq!(updated.position, 1);
+        let renamed = SmartCollectionInput {
+            name: "A renamed".into(),
+            ..moved
+        };
+        let updated = update_smart_collection(&conn, a, &renamed).unwrap();
+        assert_eq!(updated.position, 1);
+
+        reorder_smart_collections(&conn, Some(folder_id), &[a, existing.id]).unwrap();
+        assert_eq!(
+            positions(&conn, Some(folder_id)),
+            [("A renamed".into(), 0), ("Existing".into(), 1)]
+        );
+    }
+
+    fn photo_ids(conn: &DbConnection, count: usize) -> Vec<i64> {
+        (0..count)
+            .map(|i| {
+                let path = format!("{i}.jpg");
+                upsert_photo(conn, &test_photo(&path, Some(i as i64), 1)).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn album_items_keep_their_order() {
+        let conn = test_conn();
+        let ids = photo_ids(&conn, 4);
+        let album = create_album(&conn, "  Trip ").unwrap();
+        assert_eq!(album.name, "Trip");
+        assert!(matches!(
+            create_album(&conn, " "),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(get_album(&conn, 999), Err(Error::NotFound(_))));
+
+        // Duplicates, photos already present and unknown ids are skipped.
+        add_to_album(&conn, album.id, &[ids[0], ids[1], ids[0], 999], None).unwrap();
+        let album = add_to_album(&conn, album.id, &[ids[1], ids[2], ids[3]], Some(1)).unwrap();
+        assert_eq!(album.item_count, 4);
+        assert_eq!(album.cover_photo_id, Some(ids[0]));
+        assert_eq!(
+            album_photo_ids(&conn, album.id).unwrap(),
+            [ids[0], ids[2], ids[3], ids[1]]
+        );
+
+        reorder_album(&conn, album.id, &[ids[1], 999, ids[3]]).unwrap();
+        assert_eq!(
+            album_photo_ids(&conn, album.id).unwrap(),
+            [ids[1], ids[3], ids[0], ids[2]]
+        );
+
+        let album = set_album_cover(&conn, album.id, Some(ids[2])).unwrap();
+        assert_eq!(album.cover_photo_id, Some(ids[2]));
+        let copy = duplicate_album(&conn, album.id, None).unwrap();
+        assert_eq!(copy.name, "Trip copy");
+        assert_eq!(copy.cover_photo_id, Some(ids[2]));
+        assert_eq!(
+            album_photo_ids(&conn, copy.id).unwrap(),
+            album_photo_ids(&conn, album.id).unwrap()
+        );
+
+        // Removing the cover falls back to the first photo.
+        let album = remove_from_album(&conn, album.id, &[ids[2]]).unwrap();
+        assert_eq!(album.item_count, 3);
+        assert_eq!(album.cover_photo_id, Some(ids[1]));
+        assert!(matches!(
+            set_album_cover(&conn, album.id, Some(ids[2])),
+            Err(Error::Validation(_))
+        ));
+        assert_eq!(album_photo_ids(&conn, copy.id).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn album_order_binds_its_parameter_before_the_filters() {
+        let conn = test_conn();
+        let ids = photo_ids(&conn, 3);
+        let album = create_album(&conn, "Picks").unwrap();
+        add_to_album(&conn, album.id, &[ids[2], ids[0], ids[1]], None).unwrap();
+
+        // The date filter binds a parameter of its own; if the sort key took it instead of the
+        // album id, every position would be NULL and the photos would come back in id order.
+        let filters = QueryFilters {
+            album_id: Some(album.id),
+            date_from: Some(0),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query_photo_page(&conn, filters.clone()).unwrap();
+        let page_ids = |page: &PhotoPage| -> Vec<i64> {
+            page.photos
+                .iter()
+                .map(|photo| photo.photo.id.unwrap())
+                .collect()
+        };
+        assert_eq!(first.total, 3);
+        assert_eq!(page_ids(&first), [ids[2], ids[0]]);
+
+        let second = query_photo_page(
+            &conn,
+            QueryFilters {
+                cursor: first.next_cursor,
+                ..filters
+            },
+        )
+        .unwrap();
+        assert_eq!(page_ids(&second), [ids[1]]);
+        assert_eq!(second.next_cursor, None);
+    }
+
+    fn tagged_ids(conn: &DbConnection, tag: &str) -> Vec<i64> {
+        let filters = QueryFilters {
+            tags: vec![tag.to_string()],
+            sort_dir: Some("asc".into()),
+            ..Default::default()
+        };
+        query_photos(conn, filters)
+            .unwrap()
+            .into_iter()
+            .map(|photo| photo.photo.id.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tag_filters_match_keyword_subtrees_ignoring_case() {
+        let conn = test_conn();
+        let animal = ensure_keyword(&conn, "Animal", None, false).unwrap();
+        let dog = ensure_keyword(&conn, "Dog", Some(animal), false).unwrap();
+        set_keyword_alias(&conn, "hound", dog).unwrap();
+        let ids = photo_ids(&conn, 3);
+        add_manual_tag(&conn, ids[0], "DOG").unwrap();
+        add_manual_tag(&conn, ids[1], "Hound").unwrap();
+        add_manual_tag(&conn, ids[2], "cat").unwrap();
+
+        assert_eq!(tagged_ids(&conn, "animal"), [ids[0], ids[1]]);
+        assert_eq!(tagged_ids(&conn, "HOUND"), [ids[0], ids[1]]);
+        assert_eq!(tagged_ids(&conn, "Cat"), [ids[2]]);
+    }
+
+    fn photo_tags(conn: &DbConnection, photo_id: i64) -> Vec<(String, String)> {
+        let mut stmt = conn
+            .prepare("SELECT tag, source FROM tags WHERE photo_id = ?1 ORDER BY tag")
+            .unwrap();
+        stmt.query_map(params![photo_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn aliases_canonicalize_manual_and_existing_tags() {
+        let conn = test_conn();
+        let dog = ensure_keyword(&conn, "Dog", None, false).unwrap();
+        let ids = photo_ids(&conn, 3);
+        add_manual_tag(&conn, ids[0], "hound").unwrap();
+        add_manual_tag(&conn, ids[1], "dog").unwrap();
+        conn.execute(
+            "INSERT INTO tags (photo_id, tag, confidence, source, locked)
+             VALUES (?1, 'Hound', 0.4, 'auto', 0)",
+            params![ids[1]],
+        )
+        .unwrap();
+        assert_eq!(
+            photo_tags(&conn, ids[0]),
+            [("hound".into(), "manual".into())]
+        );
+        assert_eq!(photo_tags(&conn, ids[1]).len(), 2);
+
+        set_keyword_alias(&conn, "hound", dog).unwrap();
+        assert_eq!(photo_tags(&conn, ids[0]), [("Dog".into(), "manual".into())]);
+        assert_eq!(photo_tags(&conn, ids[1]), [("Dog".into(), "manual".into())]);
+
+        add_manual_tag(&conn, ids[2], "HOUND").unwrap();
+        assert_eq!(photo_tags(&conn, ids[2]), [("Dog".into(), "manual".into())]);
+    }
+
+    #[test]
+    fn category_keywords_are_exported_in_brackets() {
+        let conn = test_conn();
+        let places = ensure_keyword(&conn, "Places", None, true).unwrap();
+        ensure_keyword(&conn, "Kyoto", Some(places), false).unwrap();
+        let keywords = list_keywords(&conn).unwrap();
+        assert_eq!(
+            crate::keywords::format_keyword_list(&crate::keywords::keyword_tree(&keywords)),
+            "[Places]\n\tKyoto\n"
+        );
+    }
+
+    fn tag_rows(conn: &DbConnection, photo_id: i64) -> Vec<(String, f64, String, bool)> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT tag, confidence, source, locked FROM tags WHERE photo_id = ?1 ORDER BY tag",
+            )
+            .unwrap();
+        stmt.query_map(params![photo_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect()
+    }
+
+    fn insert_tag(conn: &DbConnection, photo_id: i64, tag: &str, confidence: f64, locked: bool) {
+        conn.execute(
+            "INSERT INTO tags (photo_id, tag, confidence, source, locked) VALUES (?1, ?2, ?3, 'auto', ?4)",
+            params![photo_id, tag, confidence, locked],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn merging_tags_keeps_one_row_with_the_highest_confidence() {
+        let conn = test_conn();
+        let ids = photo_ids(&conn, 3);
+        insert_tag(&conn, ids[0], "kitty", 0.9, false);
+        insert_tag(&conn, ids[0], "cat", 0.6, false);
+        insert_tag(&conn, ids[1], "kitty", 0.3, false);
+        insert_tag(&conn, ids[1], "cat", 0.7, false);
+        insert_tag(&conn, ids[2], "kitty", 0.5, false);
+        add_manual_tag(&conn, ids[2], "kitten").unwrap();
+
+        let sources = ["kitty".to_string(), "kitten".to_string()];
+        let report = merge_tags(&conn, &sources, "cat", true).unwrap();
+        assert_eq!(report.removed, 3);
+        assert_eq!(report.updated, 3);
+        assert_eq!(
+            tag_rows(&conn, ids[0]),
+            [("cat".into(), 0.9, "auto".into(), false)]
+        );
+        assert_eq!(
+            tag_rows(&conn, ids[1]),
+            [("cat".into(), 0.7, "auto".into(), false)]
+        );
+        // The manual row wins and, being locked, keeps the merged tag locked.
+        assert_eq!(
+            tag_rows(&conn, ids[2]),
+            [("cat".into(), 1.0, "manual".into(), true)]
+        );
+    }
+
+    #[test]
+    fn merging_tags_leaves_locked_rows_alone() {
+        let conn = test_conn();
+        let ids = photo_ids(&conn, 2);
+        insert_tag(&conn, ids[0], "cat", 0.5, true);
+        insert_tag(&conn, ids[0], "kitty", 0.9, false);
+        insert_tag(&conn, ids[1], "kitty", 0.8, true);
+
+        let report = merge_tags(&conn, &["kitty".to_string()], "cat", false).unwrap();
+        assert_eq!(
+            (report.updated, report.removed, report.skipped_locked),
+            (0, 1, 1)
+        );
+        assert_eq!(
+            tag_rows(&conn, ids[0]),
+            [("cat".into(), 0.5, "auto".into(), true)]
+        );
+        assert_eq!(
+            tag_rows(&conn, ids[1]),
+            [("kitty".into(), 0.8, "auto".into(), true)]
+        );
+
+        let report = merge_tags(&conn, &["kitty".to_string()], "cat", true).unwrap();
+        assert_eq!((report.updated, report.removed), (1, 0));
+        assert_eq!(
+            tag_rows(&conn, ids[1]),
+            [("cat".into(), 0.8, "auto".into(), true)]
+        );
+    }
+
+    #[test]
+    fn tag_edits_ignore_case() {
+        let conn = test_conn();
+        let ids = photo_ids(&conn, 3);
+        insert_tag(&conn, ids[0], "Kitty", 0.4, false);
+        insert_tag(&conn, ids[0], "CAT", 0.6, false);
+        insert_tag(&conn, ids[1], "kitty", 0.8, false);
+        add_manual_tag(&conn, ids[2], "Dog").unwrap();
+
+        let report = merge_tags(&conn, &["kitty".to_string()], "cat", false).unwrap();
+        assert_eq!((report.updated, report.removed), (2, 1));
+        assert_eq!(
+            tag_rows(&conn, ids[0]),
+            [("cat".into(), 0.6, "auto".into(), false)]
+        );
+        assert_eq!(
+            tag_rows(&conn, ids[1]),
+            [("cat".into(), 0.8, "auto".into(), false)]
+        );
+
+        remove_tag(&conn, ids[2], "dog").unwrap();
+        assert!(tag_rows(&conn, ids[2]).is_empty());
+
+        let report = delete_tag_everywhere(&conn, "Cat", false).unwrap();
+        assert_eq!(report.removed, 2);
+        assert!(tag_rows(&conn, ids[0]).is_empty());
+    }
+
+    fn photo_text(conn: &DbConnection, id: i64) -> (Option<String>, Option<String>) {
+        conn.query_row(
+            "SELECT title, caption FROM photos WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn imported_text_follows_the_file_until_edited() {
+        let conn = test_conn();
+        let id = photo_ids(&conn, 1)[0];
+        set_imported_text(&conn, id, Some("Harbor"), Some("Boats at dawn")).unwrap();
+        set_imported_text(&conn, id, Some("Harbour"), Some("Boats at dawn")).unwrap();
+        assert_eq!(
+            photo_text(&conn, id),
+            (Some("Harbour".into()), Some("Boats at dawn".into()))
+        );
+
+        let request = PhotoTextRequest {
+            photo_ids: vec![id],
+            caption: Some(" ".into()),
+            ..Default::default()
+        };
+        set_photo_text(&conn, &request).unwrap();
+        set_imported_text(&conn, id, Some("Port"), Some("Boats at dawn")).unwrap();
+        assert_eq!(photo_text(&conn, id), (Some("Port".into()), None));
+    }
 }
@@ -27,4 +27,15 @@ pub enum Error {
 
     #[error("Initialization Failed: {0}")]
     Init(String),
+
+    /// Input the user can fix, e.g. an empty name; the message is shown as is.
+    #[error("{0}")]
+    Validation(String),
+
+    /// A library item that does not exist (anymore), e.g. "Album 3".
+    #[error("{0} not found")]
+    NotFound(String),
+
+    #[error("Query Error: {0}")]
+    Query(#[from] crate::query::QueryError),
 }
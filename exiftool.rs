@@ -1,8 +1,10 @@
+use crate::color;
 use crate::config::AppPaths;
 use crate::error::{Error, Result};
+use crate::metadata;
 use crate::models::ExifMetadata;
 use serde_json::Value;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 fn parse_datetime(value: &Option<String>) -> Option<i64> {
@@ -17,10 +19,53 @@ fn parse_datetime(value: &Option<String>) -> Option<i64> {
     })
 }
 
+/// Inverse of `parse_datetime`: capture times are stored as naive local time encoded as UTC.
+pub fn format_datetime(timestamp: i64) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.naive_utc().format("%Y:%m:%d %H:%M:%S").to_string())
+}
+
+/// Lightroom-style sidecar location: `IMG_0001.CR2` -> `IMG_0001.xmp`.
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("xmp")
+}
+
+/// Writes tags into the photo's XMP sidecar, creating it if needed. Tag names take ExifTool
+/// syntax, e.g. `XMP-exif:DateTimeOriginal`.
+pub fn write_sidecar_tags(
+    paths: &AppPaths,
+    file_path: &Path,
+    tags: &[(&str, String)],
+) -> Result<PathBuf> {
+    let sidecar = sidecar_path(file_path);
+    if tags.is_empty() {
+        return Ok(sidecar);
+    }
+    let exe = paths.resolve_bin("exiftool.exe");
+    let mut command = Command::new(exe);
+    command.arg("-overwrite_original");
+    for (tag, value) in tags {
+        command.arg(format!("-{tag}={value}"));
+    }
+    let output = command
+        .arg(&sidecar)
+        .output()
+        .map_err(|e| Error::Init(format!("Failed to execute ExifTool: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::Init(format!(
+            "ExifTool failed to write {:?}: {}",
+            sidecar,
+            stderr.trim()
+        )));
+    }
+    Ok(sidecar)
+}
+
 pub fn read_metadata(paths: &AppPaths, file_path: &Path) -> Result<ExifMetadata> {
     let exe = paths.resolve_bin("exiftool.exe");
     let output = Command::new(exe)
-        .args(["-json", "-n"])
+        .args(["-json", "-n", "-G"])
         .arg(file_path)
         .output()
         .map_err(|e| Error::Init(format!("Failed to execute ExifTool: {e}")))?;
@@ -33,7 +78,8 @@ pub fn read_metadata(paths: &AppPaths, file_path: &Path) -> Result<ExifMetadata>
     }
 
     let entries: Vec<Value> = serde_json::from_slice(&output.stdout)?;
-    let entry = entries.get(0).cloned().unwrap_or(Value::Null);
+    let raw = entries.get(0).cloned().unwrap_or(Value::Null);
+    let entry = metadata::ungrouped_view(&raw);
 
     let lens_value = get_string(&entry, "LensModel")
         .or_else(|| get_string(&entry, "Lens"))
@@ -43,6 +89,11 @@ pub fn read_metadata(paths: &AppPaths, file_path: &Path) -> Result<ExifMetadata>
     let make = get_string(&entry, "Make");
     let model = normalize_model(&make, get_string(&entry, "Model"));
 
+    // Title and caption edits land in the XMP sidecar (see `write_sidecar_tags`), so its values
+    // win over the ones embedded in the file.
+    let (file_title, file_caption) = text_fields(&entry);
+    let (sidecar_title, sidecar_caption) = read_sidecar_text(paths, file_path);
+
     Ok(ExifMetadata {
         make,
         model,
@@ -60,9 +111,59 @@ pub fn read_metadata(paths: &AppPaths, file_path: &Path) -> Result<ExifMetadata>
         gps_lng: get_f64(&entry, "GPSLongitude"),
         width: get_i64(&entry, "ImageWidth"),
         height: get_i64(&entry, "ImageHeight"),
+        orientation: get_i64(&entry, "Orientation"),
+        color_space: color::detect_color_space(&entry),
+        title: sidecar_title.or(file_title),
+        caption: sidecar_caption.or(file_caption),
+        raw: (!raw.is_null()).then_some(raw),
     })
 }
 
+/// Title and caption from an ungrouped ExifTool entry, XMP first, then IPTC and EXIF.
+fn text_fields(entry: &Value) -> (Option<String>, Option<String>) {
+    let title = get_text(entry, "Title").or_else(|| get_text(entry, "ObjectName"));
+    let caption = get_text(entry, "Description")
+        .or_else(|| get_text(entry, "Caption-Abstract"))
+        .or_else(|| get_text(entry, "ImageDescription"));
+    (title, caption)
+}
+
+/// Title and caption from the photo's XMP sidecar, when it has one. An unreadable sidecar is
+/// logged and treated as absent so the import still gets the embedded values.
+fn read_sidecar_text(paths: &AppPaths, file_path: &Path) -> (Option<String>, Option<String>) {
+    let sidecar = sidecar_path(file_path);
+    if sidecar == file_path || !sidecar.is_file() {
+        return (None, None);
+    }
+    let exe = paths.resolve_bin("exiftool.exe");
+    let output = match Command::new(exe)
+        .args(["-json", "-XMP-dc:Title", "-XMP-dc:Description"])
+        .arg(&sidecar)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(_) => {
+            log::warn!("ExifTool could not read sidecar {}", sidecar.display());
+            return (None, None);
+        }
+        Err(err) => {
+            log::warn!("Failed to execute ExifTool: {err}");
+            return (None, None);
+        }
+    };
+    match serde_json::from_slice::<Vec<Value>>(&output.stdout) {
+        Ok(entries) => entries.first().map(text_fields).unwrap_or_default(),
+        Err(err) => {
+            log::warn!(
+                "Unreadable ExifTool output for {}: {}",
+                sidecar.display(),
+                err
+            );
+            (None, None)
+        }
+    }
+}
+
 fn normalize_model(make: &Option<String>, model: Option<String>) -> Option<String> {
     let model = model?;
     let model_trim = model.trim();
@@ -112,6 +213,13 @@ fn get_string(entry: &Value, key: &str) -> Option<String> {
     })
 }
 
+/// Like `get_string`, but trimmed and `None` when blank.
+fn get_text(entry: &Value, key: &str) -> Option<String> {
+    get_string(entry, key)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
 fn get_i64(entry: &Value, key: &str) -> Option<i64> {
     entry.get(key).and_then(|v| match v {
         Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
@@ -128,6 +236,17 @@ fn get_f64(entry: &Value, key: &str) -> Option<f64> {
     })
 }
 
+/// Returns the file's embedded ICC profile, if any.
+pub fn extract_icc_profile(paths: &AppPaths, file_path: &Path) -> Option<Vec<u8>> {
+    let exe = paths.resolve_bin("exiftool.exe");
+    let output = Command::new(exe)
+        .args(["-b", "-ICC_Profile"])
+        .arg(file_path)
+        .output()
+        .ok()?;
+    (!output.stdout.is_empty()).then_some(output.stdout)
+}
+
 pub fn extract_preview(paths: &AppPaths, file_path: &Path, out_path: &Path) -> Result<bool> {
     let ext = file_path
         .extension()
@@ -172,3 +291,20 @@ pub fn extract_preview(paths: &AppPaths, file_path: &Path, out_path: &Path) -> R
 
     Ok(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_datetime_round_trips_with_parse_datetime() {
+        let value = Some("2023:07:14 18:05:09".to_string());
+        let timestamp = parse_datetime(&value).unwrap();
+        assert_eq!(format_datetime(timestamp), value);
+        assert_eq!(
+            format_datetime(timestamp + 3_600 * 24).as_deref(),
+            Some("2023:07:15 18:05:09")
+        );
+        assert_eq!(format_datetime(0).as_deref(), Some("1970:01:01 00:00:00"));
+    }
+}
@@ -0,0 +1,420 @@
+use crate::db::{self, DbConnection};
+use crate::error::Result;
+use crate::keywords;
+use crate::metadata;
+use crate::models::{MetaFilter, QueryFilters};
+use crate::query;
+use rusqlite::types::Value;
+use std::collections::HashSet;
+
+/// The WHERE conditions for a [`QueryFilters`] (everything except sorting and paging): a run of
+/// `AND ...` clauses with their positional parameters. Column names are unqualified, so the
+/// statement they are appended to must select from `photos` directly.
+#[derive(Debug, Clone, Default)]
+pub struct FilterClause {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+impl FilterClause {
+    pub fn build(conn: &DbConnection, filters: &QueryFilters) -> Result<Self> {
+        let mut clause = Self::default();
+        push_filter_clauses(conn, filters, &mut clause.sql, &mut clause.params)?;
+        Ok(clause)
+    }
+
+    /// Appends the clauses to a statement ending in a WHERE condition, e.g. `WHERE 1=1`.
+    pub fn push_to(self, sql: &mut String, params: &mut Vec<Value>) {
+        sql.push_str(&self.sql);
+        params.extend(self.params);
+    }
+}
+
+/// Turns free text into an FTS5 query: every word must match, as a prefix, in any indexed
+/// column. Words are quoted so FTS5 operators and punctuation in the input are taken literally.
+pub fn search_match_expression(search: &str) -> Option<String> {
+    let terms: Vec<String> = search
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+fn push_filter_clauses(
+    conn: &DbConnection,
+    filters: &QueryFilters,
+    sql: &mut String,
+    params: &mut Vec<Value>,
+) -> Result<()> {
+    if let Some(expr) = filters.search.as_deref().and_then(search_match_expression) {
+        sql.push_str(" AND id IN (SELECT rowid FROM photo_search WHERE photo_search MATCH ?)");
+        params.push(expr.into());
+    }
+    if let Some(text) = filters.query.as_deref().filter(|q| !q.trim().is_empty()) {
+        let expr = query::parse(text)?;
+        sql.push_str(" AND ");
+        query::push_sql(&expr, sql, params);
+    }
+    if let Some(make) = filters.camera_make.as_ref() {
+        sql.push_str(" AND make = ?");
+        params.push(make.clone().into());
+    }
+    if let Some(model) = filters.camera_model.as_ref() {
+        sql.push_str(" AND model = ?");
+        params.push(model.clone().into());
+    }
+    if let Some(lens) = filters.lens.as_ref() {
+        sql.push_str(" AND lens = ?");
+        params.push(lens.clone().into());
+    }
+    if let Some(min_iso) = filters.iso_min {
+        sql.push_str(" AND iso >= ?");
+        params.push(min_iso.into());
+    }
+    if let Some(max_iso) = filters.iso_max {
+        sql.push_str(" AND iso <= ?");
+        params.push(max_iso.into());
+    }
+    if let Some(min_ap) = filters.aperture_min {
+        sql.push_str(" AND fnumber >= ?");
+        params.push(min_ap.into());
+    }
+    if let Some(max_ap) = filters.aperture_max {
+        sql.push_str(" AND fnumber <= ?");
+        params.push(max_ap.into());
+    }
+    if let Some(min_focal) = filters.focal_min {
+        sql.push_str(" AND focal_length >= ?");
+        params.push(min_focal.into());
+    }
+    if let Some(max_focal) = filters.focal_max {
+        sql.push_str(" AND focal_length <= ?");
+        params.push(max_focal.into());
+    }
+    if let Some(date_from) = filters.date_from {
+        sql.push_str(" AND date_taken >= ?");
+        params.push(date_from.into());
+    }
+    if let Some(date_to) = filters.date_to {
+        sql.push_str(" AND date_taken <= ?");
+        params.push(date_to.into());
+    }
+    if let Some(has_gps) = filters.has_gps {
+        if has_gps {
+            sql.push_str(" AND gps_lat IS NOT NULL AND gps_lng IS NOT NULL");
+        } else {
+            sql.push_str(" AND (gps_lat IS NULL OR gps_lng IS NULL)");
+        }
+    }
+
+    for (column, value) in [
+        ("place_country", filters.place_country.as_ref()),
+        ("place_region", filters.place_region.as_ref()),
+        ("place_city", filters.place_city.as_ref()),
+    ] {
+        if let Some(value) = value {
+            sql.push_str(&format!(" AND {column} = ?"));
+            params.push(value.clone().into());
+        }
+    }
+
+    if let Some(color_space) = filters.color_space.as_deref() {
+        // "unknown" selects photos without any color space information.
+        if color_space.eq_ignore_ascii_case("unknown") {
+            sql.push_str(" AND color_space IS NULL");
+        } else {
+            sql.push_str(" AND color_space = ? COLLATE NOCASE");
+            params.push(color_space.to_string().into());
+        }
+    }
+
+    if !filters.color_labels.is_empty() {
+        let colors: Vec<&String> = filters
+            .color_labels
+            .iter()
+            .filter(|color| !color.eq_ignore_ascii_case("none"))
+            .collect();
+        let mut alternatives = Vec::new();
+        if !colors.is_empty() {
+            alternatives.push(format!(
+                "color_label IN ({})",
+                vec!["?"; colors.len()].join(",")
+            ));
+            params.extend(colors.into_iter().map(|color| Value::from(color.clone())));
+        }
+        if filters
+            .color_labels
+            .iter()
+            .any(|color| color.eq_ignore_ascii_case("none"))
+        {
+            alternatives.push("color_label IS NULL".to_string());
+        }
+        sql.push_str(&format!(" AND ({})", alternatives.join(" OR ")));
+    }
+
+    for meta in &filters.meta {
+        push_meta_clause(sql, params, meta);
+    }
+
+    push_tag_clauses(filters, sql, params);
+
+    if let Some(album_id) = filters.album_id {
+        sql.push_str(" AND id IN (SELECT photo_id FROM album_items WHERE album_id = ?)");
+        params.push(album_id.into());
+    }
+
+    if let Some(smart_view) = filters.smart_view.as_deref() {
+        match smart_view {
+            "UNSORTED" => {
+                sql.push_str(" AND rating IS NULL AND picked = 0 AND rejected = 0");
+            }
+            "PICKS" => {
+                sql.push_str(" AND picked = 1 AND rejected = 0");
+            }
+            "REJECTS" => {
+                sql.push_str(" AND rejected = 1");
+            }
+            "LAST_IMPORT" => {
+                if let Some(batch_id) = db::latest_import_batch_id(conn)? {
+                    sql.push_str(" AND import_batch_id = ?");
+                    params.push(batch_id.into());
+                } else {
+                    sql.push_str(" AND 0");
+                }
+            }
+            other => {
+                if let Some(id) = db::collection_id(other) {
+                    match db::collection_filters(conn, id)? {
+                        Some(saved) => push_filter_clauses(conn, &saved, sql, params)?,
+                        None => sql.push_str(" AND 0"),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Conditions on a `tags` row from the source and confidence filters, each starting with AND.
+fn tag_row_conditions(filters: &QueryFilters) -> (String, Vec<Value>) {
+    let mut sql = String::new();
+    let mut params: Vec<Value> = Vec::new();
+    if !filters.tag_sources.is_empty() {
+        let sources: Vec<&String> = filters
+            .tag_sources
+            .iter()
+            .filter(|source| source.as_str() != "locked")
+            .collect();
+        let mut alternatives = Vec::new();
+        if !sources.is_empty() {
+            alternatives.push(format!(
+                "source IN ({})",
+                vec!["?"; sources.len()].join(",")
+            ));
+            params.extend(
+                sources
+                    .into_iter()
+                    .map(|source| Value::from(source.clone())),
+            );
+        }
+        if filters.tag_sources.iter().any(|source| source == "locked") {
+            alternatives.push("locked = 1".to_string());
+        }
+        sql.push_str(&format!(" AND ({})", alternatives.join(" OR ")));
+    }
+    if let Some(min_confidence) = filters.tag_min_confidence {
+        sql.push_str(" AND coalesce(confidence, 1.0) >= ?");
+        params.push(f64::from(min_confidence).into());
+    }
+    (sql, params)
+}
+
+/// Tag filters: every tag in `tags_all`, at least one of `tags_any` (and the legacy `tags`),
+/// none of `tags_exclude`. A keyword also matches the keywords below it and their aliases.
+/// Only tag rows passing the source and confidence filters count; with no tag lists those
+/// filters select photos having any such tag.
+fn push_tag_clauses(filters: &QueryFilters, sql: &mut String, params: &mut Vec<Value>) {
+    let (row_sql, row_params) = tag_row_conditions(filters);
+    let any: Vec<&String> = filters.tags.iter().chain(&filters.tags_any).collect();
+    let all: Vec<&String> = {
+        let mut seen = HashSet::new();
+        filters
+            .tags_all
+            .iter()
+            .filter(|tag| seen.insert(tag.as_str()))
+            .collect()
+    };
+    let exclude: Vec<&String> = filters.tags_exclude.iter().collect();
+
+    for tag in &all {
+        sql.push_str(" AND id IN (SELECT photo_id FROM tags WHERE ");
+        keywords::push_tag_match(sql, params, &[tag]);
+        sql.push_str(&row_sql);
+        params.extend(row_params.iter().cloned());
+        sql.push(')');
+    }
+    if !any.is_empty() {
+        sql.push_str(" AND id IN (SELECT photo_id FROM tags WHERE ");
+        keywords::push_tag_match(sql, params, &any);
+        sql.push_str(&row_sql);
+        params.extend(row_params.iter().cloned());
+        sql.push(')');
+    }
+    if !exclude.is_empty() {
+        sql.push_str(" AND id NOT IN (SELECT photo_id FROM tags WHERE ");
+        keywords::push_tag_match(sql, params, &exclude);
+        sql.push_str(&row_sql);
+        params.extend(row_params.iter().cloned());
+        sql.push(')');
+    }
+    if all.is_empty() && any.is_empty() && exclude.is_empty() && !row_sql.is_empty() {
+        sql.push_str(" AND id IN (SELECT photo_id FROM tags WHERE 1=1");
+        sql.push_str(&row_sql);
+        params.extend(row_params);
+        sql.push(')');
+    }
+}
+
+pub(crate) fn json_to_sql_value(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+fn push_meta_clause(sql: &mut String, params: &mut Vec<Value>, filter: &MetaFilter) {
+    let key = filter.key.trim();
+    if key.is_empty() {
+        return;
+    }
+    sql.push_str(&format!(
+        " AND id IN (SELECT m.photo_id FROM photo_metadata m, json_each({}(m.raw)) j WHERE ",
+        metadata::META_JSON_FN
+    ));
+    // Bare tag names match in any group: substr() drops everything up to the first ':'.
+    if key.contains(':') {
+        sql.push_str("j.key = ?");
+    } else {
+        sql.push_str("substr(j.key, instr(j.key, ':') + 1) = ?");
+    }
+    params.push(key.to_string().into());
+
+    let value = filter.value.as_ref().filter(|v| !v.is_null());
+    let op = filter
+        .op
+        .as_deref()
+        .unwrap_or("=")
+        .trim()
+        .to_ascii_lowercase();
+    match (op.as_str(), value) {
+        ("exists", _) | (_, None) => {}
+        ("contains", Some(value)) => {
+            let text = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            sql.push_str(" AND CAST(j.value AS TEXT) LIKE ?");
+            params.push(format!("%{}%", text).into());
+        }
+        (op, Some(value)) => {
+            let op = match op {
+                "!=" | "<>" => "!=",
+                "<" => "<",
+                "<=" => "<=",
+                ">" => ">",
+                ">=" => ">=",
+                _ => "=",
+            };
+            sql.push_str(&format!(" AND j.value {op} ?"));
+            params.push(json_to_sql_value(value));
+        }
+    }
+    sql.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PhotoRecord;
+
+    #[test]
+    fn search_terms_become_quoted_prefixes() {
+        assert_eq!(
+            search_match_expression("Kyoto temple").as_deref(),
+            Some("\"Kyoto\"* \"temple\"*")
+        );
+        assert_eq!(
+            search_match_expression("70-200 \"OR\"").as_deref(),
+            Some("\"70\"* \"200\"* \"OR\"*")
+        );
+        assert_eq!(search_match_expression("  ** "), None);
+    }
+
+    #[test]
+    fn tag_filters_combine_all_any_and_exclude() {
+        let conn = db::tests::test_conn();
+        let tags: [&[(&str, &str, bool)]; 5] = [
+            &[("portrait", "manual", false), ("Street", "manual", false)],
+            &[("portrait", "manual", false), ("street", "auto", false)],
+            &[("Portrait", "auto", true), ("street", "manual", false)],
+            &[
+                ("portrait", "manual", false),
+                ("street", "manual", false),
+                ("BLURRY", "manual", false),
+            ],
+            &[
+                ("portrait", "manual", false),
+                ("street", "manual", false),
+                ("blurry", "auto", false),
+            ],
+        ];
+        let mut ids = Vec::new();
+        for (i, rows) in tags.iter().enumerate() {
+            let photo = PhotoRecord {
+                path: format!("{i}.jpg"),
+                hash: format!("hash-{i}"),
+                ..Default::default()
+            };
+            let id = db::upsert_photo(&conn, &photo).unwrap();
+            for (tag, source, locked) in rows.iter() {
+                conn.execute(
+                    "INSERT INTO tags (photo_id, tag, confidence, source, locked) VALUES (?1, ?2, 1.0, ?3, ?4)",
+                    rusqlite::params![id, tag, source, locked],
+                )
+                .unwrap();
+            }
+            ids.push(id);
+        }
+
+        let filters = QueryFilters {
+            tags_all: vec!["portrait".into(), "street".into()],
+            tags_exclude: vec!["blurry".into()],
+            tag_sources: vec!["manual".into(), "locked".into()],
+            ..Default::default()
+        };
+        let mut sql = String::from("SELECT id FROM photos WHERE 1=1");
+        let mut params = Vec::new();
+        push_tag_clauses(&filters, &mut sql, &mut params);
+        sql.push_str(" ORDER BY id");
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let matched: Vec<i64> = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| row.get(0))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        // Auto rows only count when locked, and an auto "blurry" does not exclude.
+        assert_eq!(matched, [ids[0], ids[2], ids[4]]);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::config::AppPaths;
+use crate::db::{self, DbConnection};
+use crate::error::{Error, Result};
+use crate::models::PlaceInfo;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// GeoNames dump layout under the models directory. Any `citiesN.txt` export works; the
+/// admin1 and country tables are optional and only used to turn codes into names.
+pub const GEONAMES_DIR: &str = "geonames";
+const CITY_FILES: &[&str] = &[
+    "cities1000.txt",
+    "cities5000.txt",
+    "cities15000.txt",
+    "cities500.txt",
+];
+const ADMIN1_FILE: &str = "admin1CodesASCII.txt";
+const COUNTRY_FILE: &str = "countryInfo.txt";
+
+/// Photos farther than this from every known place get no place tags.
+const MAX_DISTANCE_KM: f64 = 100.0;
+const CELL_DEG: f64 = 1.0;
+/// Length of a degree of latitude (and of longitude at the equator).
+const KM_PER_DEGREE: f64 = 111.2;
+
+lazy_static! {
+    static ref GEOCODER: Mutex<Option<Arc<Geocoder>>> = Mutex::new(None);
+}
+
+static MISSING_WARNED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone)]
+struct Place {
+    lat: f64,
+    lng: f64,
+    city: String,
+    country_code: String,
+    admin1_code: String,
+}
+
+/// Nearest-place lookup over a 1-degree grid.
+pub struct Geocoder {
+    places: Vec<Place>,
+    grid: HashMap<(i32, i32), Vec<usize>>,
+    regions: HashMap<String, String>,
+    countries: HashMap<String, String>,
+}
+
+fn cell(lat: f64, lng: f64) -> (i32, i32) {
+    (
+        (lat / CELL_DEG).floor() as i32,
+        (lng / CELL_DEG).floor() as i32,
+    )
+}
+
+/// Cells to search on each side of the query's cell, as `(latitude, longitude)` rings. A degree
+/// of longitude shrinks with the cosine of the latitude, so more cells are needed towards the
+/// poles to reach MAX_DISTANCE_KM; the ring around the query's cell absorbs its position in it.
+fn search_rings(lat: f64) -> (i32, i32) {
+    let lat_span = MAX_DISTANCE_KM / KM_PER_DEGREE;
+    // Measured at the poleward edge of the search area, where longitude degrees are shortest.
+    let cos = (lat.abs() + lat_span).min(90.0).to_radians().cos();
+    let lng_span = if cos > 1e-6 { lat_span / cos } else { 360.0 };
+    let rings = |span: f64| (span / CELL_DEG).ceil() as i32 + 1;
+    let max_lng_rings = (180.0 / CELL_DEG) as i32;
+    (rings(lat_span), rings(lng_span).min(max_lng_rings))
+}
+
+fn haversine_km(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlng = (lng2 - lng1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().min(1.0).asin()
+}
+
+impl Geocoder {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let cities = CITY_FILES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                Error::Init(format!(
+                    "No GeoNames cities file found in {}",
+                    dir.display()
+                ))
+            })?;
+        let places = parse_cities(&std::fs::read_to_string(&cities)?);
+        let regions = std::fs::read_to_string(dir.join(ADMIN1_FILE))
+            .map(|text| parse_code_names(&text, 1))
+            .unwrap_or_default();
+        let countries = std::fs::read_to_string(dir.join(COUNTRY_FILE))
+            .map(|text| parse_code_names(&text, 4))
+            .unwrap_or_default();
+        log::info!(
+            "Loaded {} place(s) for reverse geocoding from {}",
+            places.len(),
+            cities.display()
+        );
+        Ok(Self::from_places(places, regions, countries))
+    }
+
+    fn from_places(
+        places: Vec<Place>,
+        regions: HashMap<String, String>,
+        countries: HashMap<String, String>,
+    ) -> Self {
+        let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (idx, place) in places.iter().enumerate() {
+            grid.entry(cell(place.lat, place.lng))
+                .or_default()
+                .push(idx);
+        }
+        Self {
+            places,
+            grid,
+            regions,
+            countries,
+        }
+    }
+
+    pub fn lookup(&self, lat: f64, lng: f64) -> Option<PlaceInfo> {
+        if !lat.is_finite() || !lng.is_finite() {
+            return None;
+        }
+        let (cy, cx) = cell(lat, lng);
+        let (lat_rings, lng_rings) = search_rings(lat);
+        let lng_cells = (360.0 / CELL_DEG) as i32;
+        let mut best: Option<(f64, &Place)> = None;
+        for dy in -lat_rings..=lat_rings {
+            for dx in -lng_rings..=lng_rings {
+                let x = (cx + dx + lng_cells / 2).rem_euclid(lng_cells) - lng_cells / 2;
+                let Some(indices) = self.grid.get(&(cy + dy, x)) else {
+                    continue;
+                };
+                for &idx in indices {
+                    let place = &self.places[idx];
+                    let dist = haversine_km(lat, lng, place.lat, place.lng);
+                    if best.map_or(true, |(d, _)| dist < d) {
+                        best = Some((dist, place));
+                    }
+                }
+            }
+        }
+        let (dist, place) = best?;
+        if dist > MAX_DISTANCE_KM {
+            return None;
+        }
+        let region_key = format!("{}.{}", place.country_code, place.admin1_code);
+        Some(PlaceInfo {
+            country: Some(
+                self.countries
+                    .get(&place.country_code)
+                    .cloned()
+                    .unwrap_or_else(|| place.country_code.clone()),
+            )
+            .filter(|s| !s.is_empty()),
+            region: self.regions.get(&region_key).cloned(),
+            city: Some(place.city.clone()).filter(|s| !s.is_empty()),
+        })
+    }
+}
+
+fn parse_cities(text: &str) -> Vec<Place> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split('\t').collect();
+            if cols.len() < 11 {
+                return None;
+            }
+            Some(Place {
+                lat: cols[4].parse().ok()?,
+                lng: cols[5].parse().ok()?,
+                city: cols[1].to_string(),
+                country_code: cols[8].to_string(),
+                admin1_code: cols[10].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses `code<TAB>...` tables, taking the name from column `name_col`.
+fn parse_code_names(text: &str, name_col: usize) -> HashMap<String, String> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split('\t').collect();
+            let name = cols.get(name_col)?.trim();
+            (!name.is_empty()).then(|| (cols[0].to_string(), name.to_string()))
+        })
+        .collect()
+}
+
+fn geonames_dir(paths: &AppPaths) -> PathBuf {
+    if let Some(dir) = env::var_os("PHOTO_TAGGER_GEONAMES_DIR") {
+        return PathBuf::from(dir);
+    }
+    paths.resolve_model(Path::new(GEONAMES_DIR))
+}
+
+/// Returns the process-wide geocoder, loading the dataset on first use. `None` when no
+/// dataset is installed.
+pub fn shared(paths: &AppPaths) -> Option<Arc<Geocoder>> {
+    let mut cached = GEOCODER.lock().unwrap();
+    if cached.is_none() {
+        match Geocoder::load(&geonames_dir(paths)) {
+            Ok(geocoder) => *cached = Some(Arc::new(geocoder)),
+            Err(err) => {
+                if !MISSING_WARNED.swap(true, Ordering::Relaxed) {
+                    log::warn!("Reverse geocoding unavailable: {err}");
+                }
+                return None;
+            }
+        }
+    }
+    cached.clone()
+}
+
+/// Resolves and stores places for the given photos. Photos without GPS have their places cleared.
+pub fn geocode_photos(conn: &DbConnection, paths: &AppPaths, photo_ids: &[i64]) -> Result<usize> {
+    let Some(geocoder) = shared(paths) else {
+        return Ok(0);
+    };
+    let mut updated = 0;
+    for (id, lat, lng) in db::list_gps_positions(conn, photo_ids)? {
+        let place = match (lat, lng) {
+            (Some(lat), Some(lng)) => geocoder.lookup(lat, lng).unwrap_or_default(),
+            _ => PlaceInfo::default(),
+        };
+        db::set_photo_place(conn, id, &place)?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place(lat: f64, lng: f64, city: &str, cc: &str, admin1: &str) -> Place {
+        Place {
+            lat,
+            lng,
+            city: city.into(),
+            country_code: cc.into(),
+            admin1_code: admin1.into(),
+        }
+    }
+
+    #[test]
+    fn lookup_picks_nearest_place() {
+        let regions = HashMap::from([("JP.22".to_string(), "Kyoto".to_string())]);
+        let countries = HashMap::from([("JP".to_string(), "Japan".to_string())]);
+        let geocoder = Geocoder::from_places(
+            vec![
+                place(35.0211, 135.7538, "Kyoto", "JP", "22"),
+                place(34.6937, 135.5023, "Osaka", "JP", "32"),
+            ],
+            regions,
+            countries,
+        );
+        let info = geocoder.lookup(35.0116, 135.7681).unwrap();
+        assert_eq!(info.city.as_deref(), Some("Kyoto"));
+        assert_eq!(info.region.as_deref(), Some("Kyoto"));
+        assert_eq!(info.country.as_deref(), Some("Japan"));
+        assert!(geocoder.lookup(0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn lookup_widens_the_search_at_high_latitudes() {
+        // 2.5 degrees of longitude are ~95 km at 70°N, three cells east of the query.
+        let geocoder = Geocoder::from_places(
+            vec![
+                place(70.0, 28.4, "East", "NO", "20"),
+                place(69.12, 25.9, "South", "NO", "20"),
+            ],
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let info = geocoder.lookup(70.0, 25.9).unwrap();
+        assert_eq!(info.city.as_deref(), Some("East"));
+        assert_eq!(search_rings(0.0), (2, 2));
+        assert_eq!(search_rings(89.5).1, 180);
+    }
+}
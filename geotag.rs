@@ -0,0 +1,409 @@
+use crate::config::AppPaths;
+use crate::db::{self, DbConnection};
+use crate::error::{Error, Result};
+use crate::exiftool;
+use crate::models::{GeotagMatch, GeotagMiss, GeotagReport, GeotagRequest};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub time: i64,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// Time-sorted points merged from one or more track files.
+#[derive(Debug, Default)]
+pub struct Track {
+    points: Vec<TrackPoint>,
+}
+
+impl Track {
+    pub fn load(files: &[String]) -> Result<Self> {
+        let mut points = Vec::new();
+        for file in files {
+            let path = Path::new(file);
+            let text = std::fs::read_to_string(path)?;
+            let ext = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            let parsed = match ext.as_str() {
+                "gpx" => parse_gpx(&text)?,
+                "kml" => parse_kml(&text)?,
+                "geojson" | "json" => parse_geojson(&text)?,
+                _ => {
+                    return Err(Error::Validation(format!(
+                        "Unsupported track format: {}",
+                        path.display()
+                    )))
+                }
+            };
+            log::info!(
+                "Loaded {} track point(s) from {}",
+                parsed.len(),
+                path.display()
+            );
+            points.extend(parsed);
+        }
+        Ok(Self::from_points(points))
+    }
+
+    pub fn from_points(mut points: Vec<TrackPoint>) -> Self {
+        points.retain(|p| p.lat.is_finite() && p.lng.is_finite());
+        points.sort_by_key(|p| p.time);
+        points.dedup_by_key(|p| p.time);
+        Self { points }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Position at `time` (UTC seconds). Interpolates linearly between the bracketing points when
+    /// they are at most `max_gap` apart; otherwise snaps to the nearest point within `max_gap`.
+    /// Returns the position and whether it was interpolated.
+    pub fn locate(&self, time: i64, max_gap: i64) -> Option<(f64, f64, bool)> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let idx = self.points.partition_point(|p| p.time < time);
+        let after = self.points.get(idx);
+        let before = idx.checked_sub(1).and_then(|i| self.points.get(i));
+        match (before, after) {
+            (_, Some(b)) if b.time == time => Some((b.lat, b.lng, false)),
+            (Some(a), Some(b)) if b.time - a.time <= max_gap => {
+                let t = (time - a.time) as f64 / (b.time - a.time) as f64;
+                Some((
+                    a.lat + (b.lat - a.lat) * t,
+                    a.lng + (b.lng - a.lng) * t,
+                    true,
+                ))
+            }
+            (before, after) => {
+                let nearest = [before, after]
+                    .into_iter()
+                    .flatten()
+                    .min_by_key(|p| (p.time - time).abs())?;
+                ((nearest.time - time).abs() <= max_gap).then_some((
+                    nearest.lat,
+                    nearest.lng,
+                    false,
+                ))
+            }
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.timestamp())
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|dt| dt.and_utc().timestamp())
+        })
+        .ok()
+}
+
+fn attr_f64(start: &BytesStart, name: &[u8]) -> Option<f64> {
+    start
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == name)
+        .and_then(|a| std::str::from_utf8(&a.value).ok()?.trim().parse().ok())
+}
+
+fn xml_error(err: quick_xml::Error) -> Error {
+    Error::Validation(format!("Track parse failed: {err}"))
+}
+
+pub fn parse_gpx(text: &str) -> Result<Vec<TrackPoint>> {
+    let mut reader = Reader::from_str(text);
+    let mut points = Vec::new();
+    let mut current: Option<(f64, f64)> = None;
+    let mut in_time = false;
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"trkpt" | b"rtept" | b"wpt" => {
+                    current = attr_f64(&e, b"lat").zip(attr_f64(&e, b"lon"));
+                }
+                b"time" => in_time = current.is_some(),
+                _ => {}
+            },
+            Event::Text(t) => {
+                if in_time {
+                    if let (Some((lat, lng)), Some(time)) =
+                        (current, parse_time(&String::from_utf8_lossy(&t)))
+                    {
+                        points.push(TrackPoint { time, lat, lng });
+                        current = None;
+                    }
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"time" => in_time = false,
+                b"trkpt" | b"rtept" | b"wpt" => current = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(points)
+}
+
+/// Supports `gx:Track` (`when` + `gx:coord` pairs) and time-stamped `Point` placemarks.
+pub fn parse_kml(text: &str) -> Result<Vec<TrackPoint>> {
+    let mut reader = Reader::from_str(text);
+    let mut points = Vec::new();
+    let mut whens: Vec<i64> = Vec::new();
+    let mut coords: Vec<(f64, f64)> = Vec::new();
+    let mut element: Vec<u8> = Vec::new();
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(e) => element = e.local_name().as_ref().to_vec(),
+            Event::Text(t) => {
+                let text = String::from_utf8_lossy(&t);
+                match element.as_slice() {
+                    b"when" => whens.extend(parse_time(&text)),
+                    b"coord" => {
+                        let mut parts = text.split_whitespace().map(|v| v.parse::<f64>());
+                        if let (Some(Ok(lng)), Some(Ok(lat))) = (parts.next(), parts.next()) {
+                            coords.push((lat, lng));
+                        }
+                    }
+                    b"coordinates" => {
+                        let mut parts = text.trim().split(',').map(|v| v.trim().parse::<f64>());
+                        if let (Some(Ok(lng)), Some(Ok(lat))) = (parts.next(), parts.next()) {
+                            coords.push((lat, lng));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                let name = e.local_name();
+                if matches!(name.as_ref(), b"Track" | b"Placemark") {
+                    if whens.len() == coords.len() {
+                        points.extend(
+                            whens
+                                .iter()
+                                .zip(coords.iter())
+                                .map(|(&time, &(lat, lng))| TrackPoint { time, lat, lng }),
+                        );
+                    }
+                    whens.clear();
+                    coords.clear();
+                }
+                element.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(points)
+}
+
+/// Supports LineStrings with a `coordTimes`/`times` property (as written by togeojson) and
+/// Point features with a `time`/`timestamp` property.
+pub fn parse_geojson(text: &str) -> Result<Vec<TrackPoint>> {
+    let root: Value = serde_json::from_str(text)?;
+    let features = match root.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => root
+            .get("features")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default(),
+        Some("Feature") => vec![root],
+        _ => Vec::new(),
+    };
+    let mut points = Vec::new();
+    for feature in features {
+        let props = feature.get("properties").cloned().unwrap_or(Value::Null);
+        let Some(geometry) = feature.get("geometry") else {
+            continue;
+        };
+        let coords = geometry.get("coordinates").cloned().unwrap_or(Value::Null);
+        match geometry.get("type").and_then(Value::as_str) {
+            Some("Point") => {
+                let time = ["time", "timestamp"]
+                    .iter()
+                    .find_map(|k| props.get(*k).and_then(Value::as_str))
+                    .and_then(parse_time);
+                if let (Some(time), Some((lat, lng))) = (time, geojson_position(&coords)) {
+                    points.push(TrackPoint { time, lat, lng });
+                }
+            }
+            Some("LineString") => {
+                let times = geojson_times(&props, None);
+                push_line(&mut points, &coords, &times);
+            }
+            Some("MultiLineString") => {
+                for (idx, line) in coords.as_array().into_iter().flatten().enumerate() {
+                    let times = geojson_times(&props, Some(idx));
+                    push_line(&mut points, line, &times);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(points)
+}
+
+fn geojson_times(props: &Value, line: Option<usize>) -> Vec<Option<i64>> {
+    let times = props.get("coordTimes").or_else(|| props.get("times"));
+    let times = match (times, line) {
+        (Some(Value::Array(arr)), Some(idx)) if arr.first().map_or(false, Value::is_array) => {
+            arr.get(idx)
+        }
+        (times, _) => times,
+    };
+    times
+        .and_then(Value::as_array)
+        .map(|arr| {
+            arr.iter()
+                .map(|v| v.as_str().and_then(parse_time))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn geojson_position(value: &Value) -> Option<(f64, f64)> {
+    let arr = value.as_array()?;
+    let lng = arr.first()?.as_f64()?;
+    let lat = arr.get(1)?.as_f64()?;
+    Some((lat, lng))
+}
+
+fn push_line(points: &mut Vec<TrackPoint>, coords: &Value, times: &[Option<i64>]) {
+    for (pos, time) in coords.as_array().into_iter().flatten().zip(times) {
+        if let (Some((lat, lng)), Some(time)) = (geojson_position(pos), *time) {
+            points.push(TrackPoint { time, lat, lng });
+        }
+    }
+}
+
+/// Matches the requested photos against the track logs and stores the positions.
+pub fn run_geotag(
+    conn: &DbConnection,
+    paths: &AppPaths,
+    request: &GeotagRequest,
+) -> Result<GeotagReport> {
+    let track = Track::load(&request.track_paths)?;
+    let mut report = GeotagReport {
+        track_points: track.len(),
+        ..Default::default()
+    };
+    if track.is_empty() {
+        return Ok(report);
+    }
+    let max_gap = request.max_gap_seconds.max(0);
+    let candidates = db::list_time_targets(conn, &request.photo_ids, request.filters.as_ref())?;
+    for candidate in candidates {
+        if candidate.has_gps && !request.overwrite_existing {
+            report.skipped_existing += 1;
+            continue;
+        }
+        let Some(date_taken) = candidate.date_taken else {
+            report.missed.push(GeotagMiss {
+                id: candidate.id,
+                file_name: candidate.file_name,
+                reason: "no capture time".into(),
+            });
+            continue;
+        };
+        match track.locate(date_taken + request.time_offset_seconds, max_gap) {
+            Some((lat, lng, interpolated)) => report.matched.push(GeotagMatch {
+                id: candidate.id,
+                path: candidate.path,
+                file_name: candidate.file_name,
+                lat,
+                lng,
+                interpolated,
+            }),
+            None => report.missed.push(GeotagMiss {
+                id: candidate.id,
+                file_name: candidate.file_name,
+                reason: "no track point within max gap".into(),
+            }),
+        }
+    }
+
+    if request.dry_run {
+        return Ok(report);
+    }
+    let updates: Vec<(i64, f64, f64)> = report
+        .matched
+        .iter()
+        .map(|m| (m.id, m.lat, m.lng))
+        .collect();
+    db::set_gps_positions(conn, &updates)?;
+
+    if request.write_sidecars {
+        for m in &report.matched {
+            let tags = [
+                ("XMP-exif:GPSLatitude", format!("{:.7}", m.lat)),
+                ("XMP-exif:GPSLongitude", format!("{:.7}", m.lng)),
+            ];
+            match exiftool::write_sidecar_tags(paths, Path::new(&m.path), &tags) {
+                Ok(_) => report.sidecars_written += 1,
+                Err(err) => {
+                    log::warn!("Sidecar GPS write failed for {}: {}", m.path, err);
+                    report.sidecar_errors += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_interpolates_within_gap() {
+        let track = Track::from_points(vec![
+            TrackPoint {
+                time: 100,
+                lat: 10.0,
+                lng: 20.0,
+            },
+            TrackPoint {
+                time: 200,
+                lat: 11.0,
+                lng: 22.0,
+            },
+        ]);
+        let (lat, lng, interpolated) = track.locate(150, 300).unwrap();
+        assert!((lat - 10.5).abs() < 1e-9);
+        assert!((lng - 21.0).abs() < 1e-9);
+        assert!(interpolated);
+        assert!(track.locate(150, 60).is_none());
+        assert_eq!(track.locate(230, 60), Some((11.0, 22.0, false)));
+        assert!(track.locate(1000, 60).is_none());
+    }
+
+    #[test]
+    fn parses_gpx_track_points() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx><trk><trkseg>
+  <trkpt lat="35.0116" lon="135.7681"><ele>40</ele><time>2023-05-01T01:00:00Z</time></trkpt>
+  <trkpt lat="35.0120" lon="135.7690"><time>2023-05-01T01:00:10Z</time></trkpt>
+</trkseg></trk></gpx>"#;
+        let points = parse_gpx(gpx).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].time - points[0].time, 10);
+        assert!((points[0].lng - 135.7681).abs() < 1e-9);
+    }
+}
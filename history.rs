@@ -0,0 +1,376 @@
+use crate::db::DbConnection;
+use crate::error::{Error, Result};
+use crate::models::EditAction;
+use rusqlite::{params, types::Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Actions older than this are dropped from the journal when the library is opened.
+pub const MAX_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// The part of the library an action may change; it is captured before and after the edit and
+/// only the differences are journaled.
+pub enum Scope {
+    /// Rating, pick, reject and color label of these photos.
+    Cull(Vec<i64>),
+    /// Tag rows matching a condition on `tags`, with its parameters.
+    Tags(String, Vec<Value>),
+}
+
+impl Scope {
+    /// One tag of one photo.
+    pub fn photo_tag(photo_id: i64, tag: &str) -> Self {
+        Scope::Tags(
+            "photo_id = ? AND tag = ? COLLATE NOCASE".into(),
+            vec![photo_id.into(), tag.to_string().into()],
+        )
+    }
+
+    /// Every row of these tags across the library.
+    pub fn tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Self {
+        let params: Vec<Value> = tags
+            .into_iter()
+            .map(|tag| Value::from(tag.to_string()))
+            .collect();
+        let placeholders = vec!["?"; params.len()].join(",");
+        Scope::Tags(format!("tag COLLATE NOCASE IN ({placeholders})"), params)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CullState {
+    rating: Option<i64>,
+    picked: bool,
+    rejected: bool,
+    #[serde(default)]
+    color_label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TagState {
+    tag: String,
+    confidence: Option<f64>,
+    source: String,
+    locked: bool,
+    created_at: Option<i64>,
+}
+
+enum Snapshot {
+    Cull(BTreeMap<i64, CullState>),
+    Tags(BTreeMap<(i64, String), TagState>),
+}
+
+/// A journal row: photo id, kind, and the JSON state before and after.
+type Entry = (i64, &'static str, Option<String>, Option<String>);
+
+fn snapshot(conn: &DbConnection, scope: &Scope) -> Result<Snapshot> {
+    match scope {
+        Scope::Cull(photo_ids) => {
+            let mut stmt = conn.prepare(
+                "SELECT id, rating, picked, rejected, color_label FROM photos
+                 WHERE id IN (SELECT value FROM json_each(?1))",
+            )?;
+            let rows = stmt.query_map(params![serde_json::to_string(photo_ids)?], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    CullState {
+                        rating: row.get(1)?,
+                        picked: row.get::<_, i64>(2)? == 1,
+                        rejected: row.get::<_, i64>(3)? == 1,
+                        color_label: row.get(4)?,
+                    },
+                ))
+            })?;
+            let mut states = BTreeMap::new();
+            for row in rows {
+                let (id, state) = row?;
+                states.insert(id, state);
+            }
+            Ok(Snapshot::Cull(states))
+        }
+        Scope::Tags(condition, values) => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT photo_id, tag, confidence, source, locked, created_at FROM tags
+                 WHERE {condition}"
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    TagState {
+                        tag: row.get(1)?,
+                        confidence: row.get(2)?,
+                        source: row.get(3)?,
+                        locked: row.get::<_, i64>(4)? == 1,
+                        created_at: row.get(5)?,
+                    },
+                ))
+            })?;
+            let mut states = BTreeMap::new();
+            for row in rows {
+                let (photo_id, state) = row?;
+                states.insert((photo_id, state.tag.clone()), state);
+            }
+            Ok(Snapshot::Tags(states))
+        }
+    }
+}
+
+fn diff_states<K: Ord, S: PartialEq + Serialize>(
+    kind: &'static str,
+    before: &BTreeMap<K, S>,
+    after: &BTreeMap<K, S>,
+    photo_id: impl Fn(&K) -> i64,
+) -> Result<Vec<Entry>> {
+    let keys: BTreeSet<&K> = before.keys().chain(after.keys()).collect();
+    let mut entries = Vec::new();
+    for key in keys {
+        let (old, new) = (before.get(key), after.get(key));
+        if old != new {
+            entries.push((
+                photo_id(key),
+                kind,
+                old.map(serde_json::to_string).transpose()?,
+                new.map(serde_json::to_string).transpose()?,
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+fn diff(before: &Snapshot, after: &Snapshot) -> Result<Vec<Entry>> {
+    match (before, after) {
+        (Snapshot::Cull(before), Snapshot::Cull(after)) => {
+            diff_states("cull", before, after, |id| *id)
+        }
+        (Snapshot::Tags(before), Snapshot::Tags(after)) => {
+            diff_states("tag", before, after, |(photo_id, _)| *photo_id)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Runs `edit` and journals what it changed within `scope` as one undoable action. Starting a
+/// new action discards the actions that were undone. Edits that change nothing leave no trace.
+pub fn record<T>(
+    conn: &DbConnection,
+    label: &str,
+    scope: Scope,
+    edit: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let before = snapshot(conn, &scope)?;
+    let result = edit()?;
+    // The edit already happened; a journal failure only costs the ability to undo it.
+    if let Err(err) = journal(conn, label, &before, &snapshot(conn, &scope)?) {
+        log::warn!("Failed to journal '{}': {}", label, err);
+    }
+    Ok(result)
+}
+
+fn journal(conn: &DbConnection, label: &str, before: &Snapshot, after: &Snapshot) -> Result<()> {
+    let entries = diff(before, after)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM edit_actions WHERE undone = 1", [])?;
+    tx.execute(
+        "INSERT INTO edit_actions (label) VALUES (?1)",
+        params![label],
+    )?;
+    let action_id = tx.last_insert_rowid();
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO edit_journal (action_id, photo_id, kind, before, after)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (photo_id, kind, before, after) in entries {
+            stmt.execute(params![action_id, photo_id, kind, before, after])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Reverts the latest action that is not undone; `None` when there is nothing to undo.
+pub fn undo(conn: &DbConnection) -> Result<Option<EditAction>> {
+    let id = conn.query_row(
+        "SELECT MAX(id) FROM edit_actions WHERE undone = 0",
+        [],
+        |row| row.get::<_, Option<i64>>(0),
+    )?;
+    replay(conn, id, true)
+}
+
+/// Re-applies the earliest undone action; `None` when there is nothing to redo.
+pub fn redo(conn: &DbConnection) -> Result<Option<EditAction>> {
+    let id = conn.query_row(
+        "SELECT MIN(id) FROM edit_actions WHERE undone = 1",
+        [],
+        |row| row.get::<_, Option<i64>>(0),
+    )?;
+    replay(conn, id, false)
+}
+
+fn replay(conn: &DbConnection, id: Option<i64>, undo: bool) -> Result<Option<EditAction>> {
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let tx = conn.unchecked_transaction()?;
+    let mut entries = Vec::new();
+    {
+        let mut stmt = tx.prepare(
+            "SELECT photo_id, kind, before, after FROM edit_journal
+             WHERE action_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?;
+        for row in rows {
+            entries.push(row?);
+        }
+    }
+    if undo {
+        entries.reverse();
+    }
+
+    for (photo_id, kind, before, after) in entries {
+        let (from, to) = if undo {
+            (after, before)
+        } else {
+            (before, after)
+        };
+        match kind.as_str() {
+            "cull" => {
+                let Some(to) = to else {
+                    continue;
+                };
+                let state: CullState = serde_json::from_str(&to)?;
+                tx.execute(
+                    "UPDATE photos SET rating = ?1, picked = ?2, rejected = ?3, color_label = ?4,
+                         last_modified = strftime('%s','now')
+                     WHERE id = ?5",
+                    params![
+                        state.rating,
+                        state.picked,
+                        state.rejected,
+                        state.color_label,
+                        photo_id
+                    ],
+                )?;
+            }
+            "tag" => {
+                if let Some(from) = from {
+                    let state: TagState = serde_json::from_str(&from)?;
+                    tx.execute(
+                        "DELETE FROM tags WHERE photo_id = ?1 AND tag = ?2",
+                        params![photo_id, state.tag],
+                    )?;
+                }
+                if let Some(to) = to {
+                    let state: TagState = serde_json::from_str(&to)?;
+                    tx.execute(
+                        "INSERT OR REPLACE INTO tags
+                             (photo_id, tag, confidence, source, locked, created_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            photo_id,
+                            state.tag,
+                            state.confidence,
+                            state.source,
+                            state.locked,
+                            state.created_at
+                        ],
+                    )?;
+                }
+            }
+            other => {
+                return Err(Error::Validation(format!(
+                    "Unknown journal entry kind '{}'",
+                    other
+                )))
+            }
+        }
+    }
+    tx.execute(
+        "UPDATE edit_actions SET undone = ?1 WHERE id = ?2",
+        params![undo, id],
+    )?;
+    tx.commit()?;
+    Ok(list_actions(conn, Some(id), 1)?.pop())
+}
+
+/// Recent actions, newest first, including undone ones that can still be redone.
+pub fn history(conn: &DbConnection, limit: i64) -> Result<Vec<EditAction>> {
+    list_actions(conn, None, limit)
+}
+
+fn list_actions(conn: &DbConnection, id: Option<i64>, limit: i64) -> Result<Vec<EditAction>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.label, a.undone, a.created_at, COUNT(DISTINCT j.photo_id)
+         FROM edit_actions a LEFT JOIN edit_journal j ON j.action_id = a.id
+         WHERE ?1 IS NULL OR a.id = ?1
+         GROUP BY a.id ORDER BY a.id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![id, limit], |row| {
+        Ok(EditAction {
+            id: row.get(0)?,
+            label: row.get(1)?,
+            undone: row.get::<_, i64>(2)? == 1,
+            created_at: row.get(3)?,
+            photo_count: row.get(4)?,
+        })
+    })?;
+    let mut actions = Vec::new();
+    for row in rows {
+        actions.push(row?);
+    }
+    Ok(actions)
+}
+
+/// Drops actions older than `max_age_secs`; returns how many were removed.
+pub fn prune(conn: &DbConnection, max_age_secs: i64) -> Result<usize> {
+    Ok(conn.execute(
+        "DELETE FROM edit_actions WHERE created_at < strftime('%s','now') - ?1",
+        params![max_age_secs],
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_renames_journal_as_removal_and_addition() {
+        let state = |tag: &str| TagState {
+            tag: tag.into(),
+            confidence: Some(0.8),
+            source: "auto".into(),
+            locked: false,
+            created_at: None,
+        };
+        let before = Snapshot::Tags(BTreeMap::from([
+            ((1, "dog".to_string()), state("dog")),
+            ((2, "cat".to_string()), state("cat")),
+        ]));
+        let after = Snapshot::Tags(BTreeMap::from([
+            ((1, "canine".to_string()), state("canine")),
+            ((2, "cat".to_string()), state("cat")),
+        ]));
+        let entries = diff(&before, &after).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|(photo_id, kind, ..)| *photo_id == 1 && *kind == "tag"));
+        assert!(entries
+            .iter()
+            .any(|(_, _, before, after)| before.is_none() && after.is_some()));
+        assert!(entries
+            .iter()
+            .any(|(_, _, before, after)| before.is_some() && after.is_none()));
+    }
+}
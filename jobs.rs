@@ -1,11 +1,13 @@
-use crate::config::{AppPaths, TaggingConfig};
+use crate::cache;
+use crate::config::{AppPaths, CacheConfig, MetadataSyncConfig, RenditionConfig, TaggingConfig};
 use crate::db::{self, DbPool};
 use crate::error::{Error, Result};
 use crate::embedding;
 use crate::exiftool;
+use crate::geocode;
 use crate::models::{ExifMetadata, ImportProgressEvent, PhotoRecord, StageProgress, TaggingResult};
+use crate::raw;
 use crate::tagging::TaggingEngine;
-use crate::thumbnails;
 use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
 use std::collections::HashSet;
 use std::fs;
@@ -20,11 +22,11 @@ use uuid::Uuid;
 use walkdir::WalkDir;
 use xxhash_rust::xxh3::xxh3_128;
 
-const SUPPORTED_EXT: &[&str] = &[
-    "jpg", "jpeg", "png", "tiff", "tif", "cr2", "nef", "arw", "dng", "raf",
-];
+/// Imported besides the RAW formats in [`raw::RAW_EXTENSIONS`].
+const IMAGE_EXT: &[&str] = &["jpg", "jpeg", "png", "tiff", "tif"];
 
 const STAGES: [&str; 5] = ["exif", "thumbnail", "hash", "tagging", "embedding"];
+const CHECK_STAGES: [&str; 2] = ["verify", "rebuild"];
 
 #[derive(Clone, Default)]
 pub struct JobManager {
@@ -51,6 +53,9 @@ impl JobManager {
         pool: DbPool,
         paths: AppPaths,
         tagging: TaggingConfig,
+        cache_config: CacheConfig,
+        renditions: RenditionConfig,
+        metadata_sync: MetadataSyncConfig,
     ) -> Result<String> {
         let mut current = self.inner.current.lock().unwrap();
         if current.is_some() {
@@ -62,7 +67,7 @@ impl JobManager {
         let job_id = Uuid::new_v4().to_string();
         let cancel = Arc::new(AtomicBool::new(false));
         let cancel_files = Arc::new(Mutex::new(HashSet::new()));
-        let tracker = ProgressTracker::new(app.clone());
+        let tracker = ProgressTracker::new(app.clone(), &STAGES);
 
         *current = Some(JobHandle {
             id: job_id.clone(),
@@ -73,9 +78,11 @@ impl JobManager {
         let handles = spawn_pipeline(
             app,
             root,
-            pool,
-            paths,
+            pool.clone(),
+            paths.clone(),
             tagging,
+            renditions.clone(),
+            metadata_sync,
             cancel,
             cancel_files,
             tracker.clone(),
@@ -87,6 +94,63 @@ impl JobManager {
             for handle in handles {
                 let _ = handle.join();
             }
+            if cache_config.max_bytes.is_some() {
+                match pool.get() {
+                    Ok(conn) => {
+                        if let Err(err) = cache::collect_garbage(
+                            &conn,
+                            &paths,
+                            &renditions,
+                            cache_config.max_bytes,
+                        ) {
+                            log::warn!("Cache size enforcement failed: {}", err);
+                        }
+                    }
+                    Err(err) => log::warn!("Cache size enforcement skipped: {}", err),
+                }
+            }
+            manager.finish_job(&job_id_for_thread, &tracker);
+        });
+
+        Ok(job_id)
+    }
+
+    /// Verifies every photo's preview and thumbnail and, when `rebuild` is set, regenerates
+    /// broken ones from the original. Runs as a job so it excludes imports and reports through
+    /// the same `import-progress` events, with `verify` and `rebuild` stages.
+    pub fn start_cache_check(
+        &self,
+        app: tauri::AppHandle,
+        pool: DbPool,
+        paths: AppPaths,
+        renditions: RenditionConfig,
+        rebuild: bool,
+    ) -> Result<String> {
+        let mut current = self.inner.current.lock().unwrap();
+        if current.is_some() {
+            return Err(Error::Init(
+                "A job is already running; cancel it before checking the cache.".into(),
+            ));
+        }
+
+        let job_id = Uuid::new_v4().to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let tracker = ProgressTracker::new(app, &CHECK_STAGES);
+        *current = Some(JobHandle {
+            id: job_id.clone(),
+            cancel: cancel.clone(),
+            cancel_files: Arc::new(Mutex::new(HashSet::new())),
+        });
+
+        let manager = self.clone();
+        let job_id_for_thread = job_id.clone();
+        thread::spawn(move || {
+            if let Err(err) =
+                run_cache_check(&pool, &paths, &renditions, rebuild, &cancel, &tracker)
+            {
+                tracker.on_error();
+                log::error!("Cache check failed: {}", err);
+            }
             manager.finish_job(&job_id_for_thread, &tracker);
         });
 
@@ -156,8 +220,8 @@ struct StageCounters {
 }
 
 impl ProgressTracker {
-    fn new(app: tauri::AppHandle) -> Self {
-        let stages = STAGES
+    fn new(app: tauri::AppHandle, stages: &'static [&'static str]) -> Self {
+        let stages = stages
             .iter()
             .map(|name| StageCounters {
                 name,
@@ -217,7 +281,8 @@ impl ProgressTracker {
             stage.in_progress.fetch_add(1, Ordering::Relaxed);
         }
         *self.state.current_file.lock().unwrap() = path.to_str().map(|s| s.to_string());
-        *self.state.current_stage.lock().unwrap() = Some(STAGES[stage].to_string());
+        *self.state.current_stage.lock().unwrap() =
+            self.state.stages.get(stage).map(|s| s.name.to_string());
     }
 
     fn stage_complete(&self, stage: usize) {
@@ -314,6 +379,8 @@ fn spawn_pipeline(
     pool: DbPool,
     paths: AppPaths,
     tagging: TaggingConfig,
+    renditions: RenditionConfig,
+    metadata_sync: MetadataSyncConfig,
     cancel: Arc<AtomicBool>,
     cancel_files: Arc<Mutex<HashSet<String>>>,
     tracker: ProgressTracker,
@@ -362,12 +429,14 @@ fn spawn_pipeline(
     for _ in 0..2 {
         let rx = thumb_rx.clone();
         let tx = hash_tx.clone();
+        let pool = pool.clone();
         let paths = paths.clone();
+        let renditions = renditions.clone();
         let cancel = cancel.clone();
         let cancel_files = cancel_files.clone();
         let tracker = tracker.clone();
         handles.push(thread::spawn(move || {
-            run_thumbnail_stage(rx, tx, paths, cancel, cancel_files, tracker);
+            run_thumbnail_stage(rx, tx, pool, paths, renditions, cancel, cancel_files, tracker);
         }));
     }
 
@@ -388,11 +457,22 @@ fn spawn_pipeline(
         let pool = pool.clone();
         let paths = paths.clone();
         let tagging = tagging.clone();
+        let metadata_sync = metadata_sync.clone();
         let cancel = cancel.clone();
         let cancel_files = cancel_files.clone();
         let tracker = tracker.clone();
         handles.push(thread::spawn(move || {
-            run_tagging_stage(rx, tx, pool, paths, tagging, cancel, cancel_files, tracker);
+            run_tagging_stage(
+                rx,
+                tx,
+                pool,
+                paths,
+                tagging,
+                metadata_sync,
+                cancel,
+                cancel_files,
+                tracker,
+            );
         }));
     }
 
@@ -410,6 +490,66 @@ fn spawn_pipeline(
     Ok(handles)
 }
 
+fn run_cache_check(
+    pool: &DbPool,
+    paths: &AppPaths,
+    renditions: &RenditionConfig,
+    rebuild: bool,
+    cancel: &AtomicBool,
+    tracker: &ProgressTracker,
+) -> Result<()> {
+    let conn = pool.get()?;
+    let photos = db::list_rendition_targets(&conn)?;
+    for _ in &photos {
+        tracker.on_discovered();
+        tracker.stage_pending_inc(0);
+    }
+    tracker.emit_progress(true);
+
+    for photo in photos {
+        if cancel.load(Ordering::Relaxed) {
+            tracker.mark_canceled();
+            break;
+        }
+        let path = PathBuf::from(&photo.path);
+        tracker.stage_pending_dec(0);
+        tracker.stage_start(0, &path);
+        let problems = cache::verify_photo(&conn, &photo)?;
+        tracker.stage_complete(0);
+        if problems.is_empty() {
+            tracker.on_processed();
+            tracker.emit_progress(false);
+            continue;
+        }
+        for problem in &problems {
+            log::warn!("Cache check: {}", problem);
+        }
+        if !rebuild {
+            tracker.on_error();
+            tracker.on_processed();
+            tracker.emit_progress(false);
+            continue;
+        }
+        tracker.stage_start(1, &path);
+        match cache::rebuild_photo(&conn, paths, renditions, &photo) {
+            Ok(true) => tracker.stage_complete(1),
+            Ok(false) => {
+                tracker.on_error();
+                tracker.stage_error(1);
+                log::warn!("Could not rebuild renditions for {}", photo.path);
+            }
+            Err(err) => {
+                tracker.on_error();
+                tracker.stage_error(1);
+                log::warn!("Rebuild failed for {}: {}", photo.path, err);
+            }
+        }
+        tracker.on_processed();
+        tracker.emit_progress(false);
+    }
+    Ok(())
+}
+
 fn spawn_discovery(
     app: tauri::AppHandle,
     root: PathBuf,
@@ -570,7 +710,9 @@ fn run_exif_stage(
 fn run_thumbnail_stage(
     rx: Receiver<FileWork>,
     tx: Sender<FileWork>,
+    pool: DbPool,
     paths: AppPaths,
+    renditions: RenditionConfig,
     cancel: Arc<AtomicBool>,
     cancel_files: Arc<Mutex<HashSet<String>>>,
     tracker: ProgressTracker,
@@ -592,53 +734,34 @@ fn run_thumbnail_stage(
         }
         tracker.stage_start(1, &work.path);
 
-        let hash_hint = name_hint(&work.path);
-        let preview_output = paths.previews_dir.join(format!("{hash_hint}_preview.jpg"));
-        let has_preview = match exiftool::extract_preview(&paths, &work.path, &preview_output) {
-            Ok(value) => value,
-            Err(err) => {
-                log::warn!(
-                    "Preview extraction failed for {}: {}",
-                    work.path.display(),
-                    err
-                );
-                false
-            }
-        };
-        let preview_path = if has_preview && preview_output.exists() {
-            Some(preview_output)
-        } else {
-            if thumbnails::is_supported_image(&work.path) {
-                match thumbnails::build_preview(&work.path, &paths.previews_dir) {
-                    Ok(path) if path.exists() => Some(path),
-                    Ok(path) => {
-                        log::warn!("Preview output missing for {}", path.display());
-                        None
-                    }
-                    Err(err) => {
-                        log::warn!("Preview generation failed for {}: {}", work.path.display(), err);
-                        None
+        // The content hash keys the rendition cache, so it is computed here rather than in the
+        // hash stage; unchanged files re-imported from a new location reuse their renditions.
+        match compute_hash(&work.path) {
+            Ok(hash) => {
+                let source = cache::SourceImage {
+                    path: &work.path,
+                    content_hash: &hash,
+                    orientation: work.exif.orientation,
+                    color_space: work.exif.color_space.as_deref(),
+                };
+                let rendered = cache::render_photo(&paths, &renditions, &source);
+                if let Ok(conn) = pool.get() {
+                    if let Err(err) = cache::register(&conn, &rendered, &hash) {
+                        log::warn!(
+                            "Cache registration failed for {}: {}",
+                            work.path.display(),
+                            err
+                        );
                     }
                 }
-            } else {
-                log::warn!(
-                    "No embedded preview found for {}; skipping preview generation",
-                    work.path.display()
-                );
-                None
+                work.preview_path = rendered.preview.map(|file| file.path);
+                work.thumb_path = rendered.thumb.map(|file| file.path);
+                work.hash = Some(hash);
             }
-        };
-        let thumb_path = preview_path.as_ref().and_then(|preview| {
-            thumbnails::build_thumbnail(preview, &paths.thumbs_dir)
-                .map_err(|err| {
-                    log::warn!("Thumbnail generation failed for {}: {}", preview.display(), err);
-                    err
-                })
-                .ok()
-        });
-
-        work.preview_path = preview_path;
-        work.thumb_path = thumb_path;
+            Err(err) => {
+                log::warn!("Hash failed for {}: {}", work.path.display(), err);
+            }
+        }
 
         tracker.stage_complete(1);
         if tx.send(work).is_err() {
@@ -673,7 +796,11 @@ fn run_hash_stage(
         }
         tracker.stage_start(2, &work.path);
 
-        match compute_hash(&work.path) {
+        let hash = match work.hash.take() {
+            Some(hash) => Ok(hash),
+            None => compute_hash(&work.path),
+        };
+        match hash {
             Ok(hash) => {
                 work.hash = Some(hash);
                 if let Some(preview_path) = work.preview_path.as_ref() {
@@ -711,6 +838,7 @@ fn run_tagging_stage(
     pool: DbPool,
     paths: AppPaths,
     tagging: TaggingConfig,
+    metadata_sync: MetadataSyncConfig,
     cancel: Arc<AtomicBool>,
     cancel_files: Arc<Mutex<HashSet<String>>>,
     tracker: ProgressTracker,
@@ -765,6 +893,7 @@ fn run_tagging_stage(
             model: work.exif.model.clone(),
             lens: work.exif.lens.clone(),
             date_taken: work.exif.datetime_original,
+            date_taken_original: None,
             iso: work.exif.iso,
             fnumber: work.exif.fnumber,
             focal_length: work.exif.focal_length,
@@ -772,6 +901,9 @@ fn run_tagging_stage(
             exposure_comp: work.exif.exposure_comp,
             gps_lat: work.exif.gps_lat,
             gps_lng: work.exif.gps_lng,
+            place_country: None,
+            place_region: None,
+            place_city: None,
             thumb_path: work
                 .thumb_path
                 .as_ref()
@@ -780,10 +912,17 @@ fn run_tagging_stage(
                 .preview_path
                 .as_ref()
                 .map(|p| p.to_string_lossy().to_string()),
+            orientation: work.exif.orientation,
+            color_space: work.exif.color_space.clone(),
             dhash: work.dhash,
             rating: None,
             picked: false,
             rejected: false,
+            color_label: None,
+            // Set below through `set_imported_text`, which keeps edits made in the app.
+            title: None,
+            caption: None,
+            notes: None,
             last_modified: None,
             import_batch_id: Some(work.import_batch_id.clone()),
             created_at: None,
@@ -820,6 +959,26 @@ fn run_tagging_stage(
                     Ok(photo_id) => {
                         photo.id = Some(photo_id);
                         work.photo_id = Some(photo_id);
+                        if metadata_sync.enabled {
+                            if let Err(err) = db::set_imported_text(
+                                &conn,
+                                photo_id,
+                                work.exif.title.as_deref(),
+                                work.exif.caption.as_deref(),
+                            ) {
+                                log::warn!("Title and caption persistence failed for {}: {}", photo.path, err);
+                            }
+                        }
+                        if let Some(raw) = work.exif.raw.as_ref() {
+                            if let Err(err) = db::upsert_raw_metadata(&conn, photo_id, raw) {
+                                log::warn!("Raw metadata persistence failed for {}: {}", photo.path, err);
+                            }
+                        }
+                        if photo.gps_lat.is_some() && photo.gps_lng.is_some() {
+                            if let Err(err) = geocode::geocode_photos(&conn, &paths, &[photo_id]) {
+                                log::warn!("Reverse geocoding failed for {}: {}", photo.path, err);
+                            }
+                        }
                         if let Err(err) = db::replace_auto_tags(&conn, photo_id, tagging, &work.exif)
                         {
                             tracker.on_error();
@@ -915,10 +1074,12 @@ fn run_embedding_stage(
 }
 
 fn is_supported(path: &Path) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| SUPPORTED_EXT.contains(&ext.to_lowercase().as_str()))
-        .unwrap_or(false)
+    raw::is_raw(path)
+        || path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| IMAGE_EXT.contains(&ext.to_lowercase().as_str()))
+            .unwrap_or(false)
 }
 
 fn is_canceled(path: &Path, cancel: &AtomicBool, cancel_files: &Mutex<HashSet<String>>) -> bool {
@@ -936,12 +1097,6 @@ fn compute_hash(path: &Path) -> Result<String> {
     Ok(format!("{:x}", digest))
 }
 
-fn name_hint(path: &Path) -> String {
-    let key = path.to_string_lossy();
-    let digest = xxh3_128(key.as_bytes());
-    format!("{:x}", digest)
-}
-
 fn compute_dhash(path: &Path) -> Result<u64> {
     let img = image::open(path)?.to_luma8();
     let resized = image::imageops::resize(&img, 9, 8, image::imageops::FilterType::Triangle);
@@ -0,0 +1,217 @@
+use crate::db::{self, DbConnection};
+use crate::error::{Error, Result};
+use crate::models::Keyword;
+use rusqlite::types::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// One entry of a Lightroom keyword list: a keyword with its synonyms, nested by tab depth.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeywordNode {
+    pub name: String,
+    /// `[name]` in the list: a grouping keyword Lightroom does not export to photos.
+    pub is_category: bool,
+    pub synonyms: Vec<String>,
+    pub children: Vec<KeywordNode>,
+}
+
+/// Parses Lightroom's keyword-list export: one keyword per line, children indented by one tab
+/// more than their parent, `{synonym}` lines under the keyword they belong to and `[category]`
+/// keywords in brackets.
+pub fn parse_keyword_list(text: &str) -> Result<Vec<KeywordNode>> {
+    let mut roots: Vec<KeywordNode> = Vec::new();
+    // Path of child indices from the roots to the most recent keyword at each depth.
+    let mut path: Vec<usize> = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        let content = line.trim_start_matches('\t');
+        if content.trim().is_empty() {
+            continue;
+        }
+        let depth = line.len() - content.len();
+        let content = content.trim();
+
+        if let Some(synonym) = content
+            .strip_prefix('{')
+            .and_then(|rest| rest.strip_suffix('}'))
+        {
+            if depth == 0 || depth > path.len() {
+                return Err(Error::Validation(format!(
+                    "Keyword list line {}: synonym without a keyword",
+                    line_no + 1
+                )));
+            }
+            path.truncate(depth);
+            node_at(&mut roots, &path)
+                .synonyms
+                .push(synonym.trim().to_string());
+            continue;
+        }
+
+        if depth > path.len() {
+            return Err(Error::Validation(format!(
+                "Keyword list line {}: indented more than one level below its parent",
+                line_no + 1
+            )));
+        }
+        path.truncate(depth);
+        let category = content
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'));
+        let node = KeywordNode {
+            name: category.unwrap_or(content).trim().to_string(),
+            is_category: category.is_some(),
+            ..Default::default()
+        };
+        let siblings = if path.is_empty() {
+            &mut roots
+        } else {
+            &mut node_at(&mut roots, &path).children
+        };
+        siblings.push(node);
+        path.push(siblings.len() - 1);
+    }
+    Ok(roots)
+}
+
+fn node_at<'a>(roots: &'a mut [KeywordNode], path: &[usize]) -> &'a mut KeywordNode {
+    let mut node = &mut roots[path[0]];
+    for &index in &path[1..] {
+        node = &mut node.children[index];
+    }
+    node
+}
+
+/// Writes keywords in Lightroom's keyword-list format.
+pub fn format_keyword_list(roots: &[KeywordNode]) -> String {
+    fn write(out: &mut String, node: &KeywordNode, depth: usize) {
+        out.push_str(&"\t".repeat(depth));
+        if node.is_category {
+            out.push_str(&format!("[{}]\n", node.name));
+        } else {
+            out.push_str(&node.name);
+            out.push('\n');
+        }
+        for synonym in &node.synonyms {
+            out.push_str(&"\t".repeat(depth + 1));
+            out.push_str(&format!("{{{synonym}}}\n"));
+        }
+        for child in &node.children {
+            write(out, child, depth + 1);
+        }
+    }
+    let mut out = String::new();
+    for root in roots {
+        write(&mut out, root, 0);
+    }
+    out
+}
+
+/// Builds the keyword tree from the flat list stored in the database.
+pub fn keyword_tree(keywords: &[Keyword]) -> Vec<KeywordNode> {
+    let mut children: HashMap<Option<i64>, Vec<&Keyword>> = HashMap::new();
+    for keyword in keywords {
+        // Keywords whose parent no longer exists are shown at the top level.
+        let parent = keyword
+            .parent_id
+            .filter(|id| keywords.iter().any(|k| k.id == *id));
+        children.entry(parent).or_default().push(keyword);
+    }
+    fn build(
+        children: &HashMap<Option<i64>, Vec<&Keyword>>,
+        parent: Option<i64>,
+    ) -> Vec<KeywordNode> {
+        children
+            .get(&parent)
+            .map(|list| {
+                list.iter()
+                    .map(|keyword| KeywordNode {
+                        name: keyword.name.clone(),
+                        is_category: keyword.is_category,
+                        synonyms: keyword.aliases.clone(),
+                        children: build(children, Some(keyword.id)),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    build(&children, None)
+}
+
+/// Merges a Lightroom keyword list into the hierarchy. Existing keywords are moved under the
+/// parent the list gives them. Returns the number of keywords in the file.
+pub fn import_keyword_list(conn: &DbConnection, path: &Path) -> Result<usize> {
+    let text = std::fs::read_to_string(path)?;
+    let roots = parse_keyword_list(&text)?;
+    fn import(conn: &DbConnection, node: &KeywordNode, parent_id: Option<i64>) -> Result<usize> {
+        let id = db::ensure_keyword(conn, &node.name, parent_id, node.is_category)?;
+        for synonym in &node.synonyms {
+            db::set_keyword_alias(conn, synonym, id)?;
+        }
+        let mut count = 1;
+        for child in &node.children {
+            count += import(conn, child, Some(id))?;
+        }
+        Ok(count)
+    }
+    let tx = conn.unchecked_transaction()?;
+    let mut count = 0;
+    for root in &roots {
+        count += import(conn, root, None)?;
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// Writes the whole hierarchy as a Lightroom keyword list; returns the number of keywords.
+pub fn export_keyword_list(conn: &DbConnection, path: &Path) -> Result<usize> {
+    let keywords = db::list_keywords(conn)?;
+    std::fs::write(path, format_keyword_list(&keyword_tree(&keywords)))?;
+    Ok(keywords.len())
+}
+
+/// Condition on `tags.tag` matching any of `tags`, or any keyword below them in the hierarchy,
+/// by name or alias. Tags that are not keywords match themselves. Matching ignores case, like
+/// keyword names and aliases do.
+pub fn push_tag_match(sql: &mut String, params: &mut Vec<Value>, tags: &[&String]) {
+    let seeds = vec!["(?)"; tags.len()].join(",");
+    sql.push_str(&format!(
+        "tag COLLATE NOCASE IN (
+            WITH RECURSIVE seed(name) AS (VALUES {seeds}),
+            subtree(id) AS (
+                SELECT k.id FROM keywords k JOIN seed s ON k.name = s.name
+                UNION SELECT a.keyword_id FROM keyword_aliases a JOIN seed s ON a.alias = s.name
+                UNION SELECT k.id FROM keywords k JOIN subtree t ON k.parent_id = t.id
+            )
+            SELECT name FROM seed
+            UNION SELECT name FROM keywords WHERE id IN subtree
+            UNION SELECT alias FROM keyword_aliases WHERE keyword_id IN subtree
+        )"
+    ));
+    params.extend(tags.iter().map(|tag| Value::from((*tag).clone())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "animal\n\tdog\n\t\t{doggy}\n\t\t{hound}\n\tcat\n[Places]\n\tKyoto\n";
+
+    #[test]
+    fn parses_lightroom_keyword_list() {
+        let roots = parse_keyword_list(LIST).unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].children[0].name, "dog");
+        assert_eq!(roots[0].children[0].synonyms, vec!["doggy", "hound"]);
+        assert_eq!(roots[1].name, "Places");
+        assert!(roots[1].is_category && !roots[0].is_category);
+        assert_eq!(roots[1].children[0].name, "Kyoto");
+        assert!(parse_keyword_list("animal\n\t\tdog\n").is_err());
+    }
+
+    #[test]
+    fn keyword_list_round_trips() {
+        let roots = parse_keyword_list(LIST).unwrap();
+        assert_eq!(format_keyword_list(&roots), LIST);
+    }
+}
@@ -1,25 +1,46 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod backup;
+mod cache;
+mod color;
 mod config;
 mod db;
 mod error;
 mod embedding;
 mod exiftool;
+mod filter;
+mod geocode;
+mod geotag;
 mod gpu;
+mod history;
 mod jobs;
+mod keywords;
+mod mapview;
+mod metadata;
 mod models;
 mod onnx;
+mod protocol;
+mod query;
+mod raw;
 mod schema;
 mod tagging;
 mod thumbnails;
 
-use crate::config::{AppPaths, InferenceDevicePreference, TaggingConfig};
+use crate::config::{
+    AppPaths, BackupConfig, CacheConfig, InferenceDevicePreference, MetadataSyncConfig,
+    RenditionConfig, Settings, TaggingConfig,
+};
 use crate::db::DbPool;
 use crate::error::Error;
+use crate::history::Scope;
 use crate::jobs::JobManager;
 use crate::models::{
-    InferenceBackendInfo, InferenceStatus, PhotoWithTags, QueryFilters, SmartViewCounts,
+    Album, BackupInfo, CacheGcReport, ColorLabel, EditAction, FacetCounts, GeotagReport,
+    GeotagRequest, InferenceBackendInfo, InferenceStatus, Keyword, KeywordInput,
+    MapClusterResponse, MapQuery, PhotoPage, PhotoTextRequest, PhotoTextResult, QueryFilters,
+    RenditionInfo, RestoreReport, SmartCollection, SmartCollectionInput, SmartViewCounts,
+    TagEditReport, TagUsage, TimeShiftItem, TimeShiftPreview, TimeShiftRequest, TimeShiftResult,
 };
 use tauri::Manager;
 use crate::tagging::TaggingEngine;
@@ -35,6 +56,10 @@ pub struct AppState {
     db: DbPool,
     paths: AppPaths,
     tagging: Arc<Mutex<TaggingConfig>>,
+    cache: Arc<Mutex<CacheConfig>>,
+    renditions: Arc<Mutex<RenditionConfig>>,
+    backups: Arc<Mutex<BackupConfig>>,
+    metadata_sync: Arc<Mutex<MetadataSyncConfig>>,
     jobs: JobManager,
 }
 
@@ -64,18 +89,53 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-fn query_photos(
+fn query_photos(state: tauri::State<AppState>, filters: QueryFilters) -> InvokeResult<PhotoPage> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::query_photo_page(&conn, filters).map_err(|e| e.to_string())
+}
+
+/// Per-value counts for the filter panels under the current filters.
+#[tauri::command]
+fn facet_counts(
     state: tauri::State<AppState>,
     filters: QueryFilters,
-) -> InvokeResult<Vec<PhotoWithTags>> {
+    facets: Option<Vec<String>>,
+    limit: Option<usize>,
+) -> InvokeResult<Vec<FacetCounts>> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::query_photos(&conn, filters).map_err(|e| e.to_string())
+    db::facet_counts(&conn, &filters, &facets.unwrap_or_default(), limit).map_err(|e| e.to_string())
+}
+
+/// Checks a search expression without running it; errors carry the character range to highlight.
+#[tauri::command]
+fn validate_query(query: String) -> Result<(), query::QueryError> {
+    if query.trim().is_empty() {
+        return Ok(());
+    }
+    query::parse(&query).map(|_| ())
+}
+
+#[tauri::command]
+fn get_photo_metadata(
+    state: tauri::State<AppState>,
+    photo_id: i64,
+) -> InvokeResult<Option<serde_json::Value>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::get_raw_metadata(&conn, photo_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn add_manual_tag(state: tauri::State<AppState>, photo_id: i64, tag: String) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::add_manual_tag(&conn, photo_id, &tag).map_err(|e| e.to_string())
+    // Journal the tag as stored, which is the keyword's name when `tag` is an alias.
+    let tag = db::canonical_tag(&conn, &tag).map_err(|e| e.to_string())?;
+    history::record(
+        &conn,
+        &format!("Add tag \"{tag}\""),
+        Scope::photo_tag(photo_id, &tag),
+        || db::add_manual_tag(&conn, photo_id, &tag),
+    )
+    .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -85,7 +145,13 @@ fn remove_manual_tag(
     tag: String,
 ) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::remove_tag(&conn, photo_id, &tag).map_err(|e| e.to_string())
+    history::record(
+        &conn,
+        &format!("Remove tag \"{tag}\""),
+        Scope::photo_tag(photo_id, &tag),
+        || db::remove_tag(&conn, photo_id, &tag),
+    )
+    .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -110,6 +176,11 @@ async fn rerun_auto(state: tauri::State<'_, AppState>, photo_id: i64) -> InvokeR
         gps_lng: photo.photo.gps_lng,
         width: photo.photo.width,
         height: photo.photo.height,
+        orientation: photo.photo.orientation,
+        color_space: photo.photo.color_space.clone(),
+        title: photo.photo.title.clone(),
+        caption: photo.photo.caption.clone(),
+        raw: None,
     };
     let config = state.tagging.lock().unwrap().clone();
     let pool = state.db.clone();
@@ -224,6 +295,11 @@ fn test_inference(
                 gps_lng: photo.photo.gps_lng,
                 width: photo.photo.width,
                 height: photo.photo.height,
+                orientation: photo.photo.orientation,
+                color_space: photo.photo.color_space.clone(),
+                title: photo.photo.title.clone(),
+                caption: photo.photo.caption.clone(),
+                raw: None,
             };
             let start = std::time::Instant::now();
             let _ = engine.classify(std::path::Path::new(preview), &exif);
@@ -250,19 +326,28 @@ fn set_rating(
     rating: Option<i64>,
 ) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::set_rating(&conn, photo_id, rating).map_err(|e| e.to_string())
+    history::record(&conn, "Rating", Scope::Cull(vec![photo_id]), || {
+        db::set_rating(&conn, photo_id, rating)
+    })
+    .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn toggle_picked(state: tauri::State<AppState>, photo_id: i64, value: bool) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::set_picked(&conn, photo_id, value).map_err(|e| e.to_string())
+    history::record(&conn, "Pick", Scope::Cull(vec![photo_id]), || {
+        db::set_picked(&conn, photo_id, value)
+    })
+    .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn toggle_rejected(state: tauri::State<AppState>, photo_id: i64, value: bool) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::set_rejected(&conn, photo_id, value).map_err(|e| e.to_string())
+    history::record(&conn, "Reject", Scope::Cull(vec![photo_id]), || {
+        db::set_rejected(&conn, photo_id, value)
+    })
+    .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -270,13 +355,46 @@ fn batch_update_cull(
     state: tauri::State<AppState>,
     photo_ids: Vec<i64>,
     rating: Option<Option<i64>>,
+    clear_rating: Option<bool>,
     picked: Option<bool>,
     rejected: Option<bool>,
+    color_label: Option<String>,
+    clear_color_label: Option<bool>,
 ) -> InvokeResult<()> {
     let conn = state.db.get().map_err(|e| e.to_string())?;
-    db::batch_update_cull(&conn, &photo_ids, rating, picked, rejected)
-        .map(|_| ())
-        .map_err(|e| e.to_string())
+    // A JSON null arrives as `None`, so clearing needs its own flag.
+    let rating = if clear_rating.unwrap_or(false) {
+        Some(None)
+    } else {
+        rating
+    };
+    let color_label = if clear_color_label.unwrap_or(false) {
+        Some(None)
+    } else {
+        color_label.as_deref().map(Some)
+    };
+    let label = format!("Cull {} photo(s)", photo_ids.len());
+    history::record(&conn, &label, Scope::Cull(photo_ids.clone()), || {
+        db::batch_update_cull(&conn, &photo_ids, rating, picked, rejected, color_label)
+    })
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_color_labels(state: tauri::State<AppState>) -> InvokeResult<Vec<ColorLabel>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::list_color_labels(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn rename_color_label(
+    state: tauri::State<AppState>,
+    color: String,
+    name: String,
+) -> InvokeResult<()> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::rename_color_label(&conn, &color, &name).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -285,6 +403,592 @@ fn get_smart_views_counts(state: tauri::State<AppState>) -> InvokeResult<SmartVi
     db::get_smart_view_counts(&conn).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn list_tags(state: tauri::State<AppState>) -> InvokeResult<Vec<TagUsage>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::list_tag_usage(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn rename_tag(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+    include_locked: Option<bool>,
+) -> InvokeResult<TagEditReport> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    let label = format!("Rename tag \"{from}\" to \"{to}\"");
+    let scope = Scope::tags([from.as_str(), to.as_str()]);
+    history::record(&conn, &label, scope, || {
+        db::merge_tags(&conn, &[from.clone()], &to, include_locked.unwrap_or(false))
+    })
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn merge_tags(
+    state: tauri::State<AppState>,
+    tags: Vec<String>,
+    into: String,
+    include_locked: Option<bool>,
+) -> InvokeResult<TagEditReport> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    let label = format!("Merge {} tag(s) into \"{into}\"", tags.len());
+    let scope = Scope::tags(tags.iter().map(String::as_str).chain([into.as_str()]));
+    history::record(&conn, &label, scope, || {
+        db::merge_tags(&conn, &tags, &into, include_locked.unwrap_or(false))
+    })
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_tag(
+    state: tauri::State<AppState>,
+    tag: String,
+    include_locked: Option<bool>,
+) -> InvokeResult<TagEditReport> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    history::record(
+        &conn,
+        &format!("Delete tag \"{tag}\""),
+        Scope::tags([tag.as_str()]),
+        || db::delete_tag_everywhere(&conn, &tag, include_locked.unwrap_or(false)),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn undo_edit(state: tauri::State<AppState>) -> InvokeResult<Option<EditAction>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    history::undo(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn redo_edit(state: tauri::State<AppState>) -> InvokeResult<Option<EditAction>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    history::redo(&conn).map_err(|e| e.to_string())
+}
+
+/// Recent undoable actions, newest first.
+#[tauri::command]
+fn edit_history(
+    state: tauri::State<AppState>,
+    limit: Option<i64>,
+) -> InvokeResult<Vec<EditAction>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    history::history(&conn, limit.unwrap_or(50)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_keywords(state: tauri::State<AppState>) -> InvokeResult<Vec<Keyword>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::list_keywords(&conn).map_err(|e| e.to_string())
+}
+
+/// Creates a keyword when `id` is missing, otherwise renames/moves it and replaces its aliases.
+#[tauri::command]
+fn save_keyword(
+    state: tauri::State<AppState>,
+    id: Option<i64>,
+    input: KeywordInput,
+) -> InvokeResult<Keyword> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::save_keyword(&conn, id, &input).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_keyword(state: tauri::State<AppState>, id: i64) -> InvokeResult<()> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::delete_keyword(&conn, id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn import_keyword_list(state: tauri::State<AppState>, path: String) -> InvokeResult<usize> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    keywords::import_keyword_list(&conn, std::path::Path::new(&path)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn export_keyword_list(state: tauri::State<AppState>, path: String) -> InvokeResult<usize> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    keywords::export_keyword_list(&conn, std::path::Path::new(&path)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_albums(state: tauri::State<AppState>) -> InvokeResult<Vec<Album>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::list_albums(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_album(state: tauri::State<AppState>, name: String) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::create_album(&conn, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn rename_album(state: tauri::State<AppState>, album_id: i64, name: String) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::rename_album(&conn, album_id, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_album(state: tauri::State<AppState>, album_id: i64) -> InvokeResult<()> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::delete_album(&conn, album_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn add_to_album(
+    state: tauri::State<AppState>,
+    album_id: i64,
+    photo_ids: Vec<i64>,
+    index: Option<usize>,
+) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::add_to_album(&conn, album_id, &photo_ids, index).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_from_album(
+    state: tauri::State<AppState>,
+    album_id: i64,
+    photo_ids: Vec<i64>,
+) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::remove_from_album(&conn, album_id, &photo_ids).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn reorder_album(
+    state: tauri::State<AppState>,
+    album_id: i64,
+    photo_ids: Vec<i64>,
+) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::reorder_album(&conn, album_id, &photo_ids).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_album_cover(
+    state: tauri::State<AppState>,
+    album_id: i64,
+    photo_id: Option<i64>,
+) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::set_album_cover(&conn, album_id, photo_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn duplicate_album(
+    state: tauri::State<AppState>,
+    album_id: i64,
+    name: Option<String>,
+) -> InvokeResult<Album> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::duplicate_album(&conn, album_id, name.as_deref()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_smart_collections(state: tauri::State<AppState>) -> InvokeResult<Vec<SmartCollection>> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::list_smart_collections(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_smart_collection(
+    state: tauri::State<AppState>,
+    input: SmartCollectionInput,
+) -> InvokeResult<SmartCollection> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::create_smart_collection(&conn, &input).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_smart_collection(
+    state: tauri::State<AppState>,
+    id: i64,
+    input: SmartCollectionInput,
+) -> InvokeResult<SmartCollection> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::update_smart_collection(&conn, id, &input).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_smart_collection(state: tauri::State<AppState>, id: i64) -> InvokeResult<usize> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::delete_smart_collection(&conn, id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn reorder_smart_collections(
+    state: tauri::State<AppState>,
+    parent_id: Option<i64>,
+    ids: Vec<i64>,
+) -> InvokeResult<()> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::reorder_smart_collections(&conn, parent_id, &ids).map_err(|e| e.to_string())
+}
+
+fn write_time_sidecars(paths: &AppPaths, items: &[TimeShiftItem]) -> (usize, usize) {
+    let mut written = 0;
+    let mut errors = 0;
+    for item in items {
+        let Some(value) = exiftool::format_datetime(item.shifted) else {
+            errors += 1;
+            continue;
+        };
+        let tags = [
+            ("XMP-exif:DateTimeOriginal", value.clone()),
+            ("XMP-xmp:CreateDate", value),
+        ];
+        match exiftool::write_sidecar_tags(paths, Path::new(&item.path), &tags) {
+            Ok(_) => written += 1,
+            Err(err) => {
+                log::warn!("Sidecar time write failed for {}: {}", item.path, err);
+                errors += 1;
+            }
+        }
+    }
+    (written, errors)
+}
+
+#[tauri::command]
+fn preview_time_shift(
+    state: tauri::State<AppState>,
+    request: TimeShiftRequest,
+) -> InvokeResult<TimeShiftPreview> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    db::preview_time_shift(&conn, &request).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn apply_time_shift(
+    state: tauri::State<'_, AppState>,
+    request: TimeShiftRequest,
+) -> InvokeResult<TimeShiftResult> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<TimeShiftResult> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        let items = db::apply_time_shift(&conn, &request).map_err(|e| e.to_string())?;
+        let (sidecars_written, sidecar_errors) = if request.write_sidecars {
+            write_time_sidecars(&paths, &items)
+        } else {
+            (0, 0)
+        };
+        Ok(TimeShiftResult {
+            updated: items.len(),
+            sidecars_written,
+            sidecar_errors,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn revert_time_shift(
+    state: tauri::State<'_, AppState>,
+    photo_ids: Vec<i64>,
+    write_sidecars: Option<bool>,
+) -> InvokeResult<TimeShiftResult> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<TimeShiftResult> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        let items = db::revert_time_shift(&conn, &photo_ids).map_err(|e| e.to_string())?;
+        let (sidecars_written, sidecar_errors) = if write_sidecars.unwrap_or(false) {
+            write_time_sidecars(&paths, &items)
+        } else {
+            (0, 0)
+        };
+        Ok(TimeShiftResult {
+            updated: items.len(),
+            sidecars_written,
+            sidecar_errors,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Mirrors title and caption into the XMP sidecars (dc:title / dc:description, which is where
+/// IPTC Core keeps them). A blank value removes the tag.
+fn write_text_sidecars(
+    paths: &AppPaths,
+    files: &[String],
+    request: &PhotoTextRequest,
+) -> (usize, usize) {
+    let mut tags = Vec::new();
+    if let Some(title) = &request.title {
+        tags.push(("XMP-dc:Title", title.trim().to_string()));
+    }
+    if let Some(caption) = &request.caption {
+        tags.push(("XMP-dc:Description", caption.trim().to_string()));
+    }
+    if tags.is_empty() {
+        return (0, 0);
+    }
+    let mut written = 0;
+    let mut errors = 0;
+    for file in files {
+        match exiftool::write_sidecar_tags(paths, Path::new(file), &tags) {
+            Ok(_) => written += 1,
+            Err(err) => {
+                log::warn!("Sidecar text write failed for {}: {}", file, err);
+                errors += 1;
+            }
+        }
+    }
+    (written, errors)
+}
+
+#[tauri::command]
+async fn set_photo_text(
+    state: tauri::State<'_, AppState>,
+    request: PhotoTextRequest,
+) -> InvokeResult<PhotoTextResult> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    let write_sidecars = request
+        .write_sidecars
+        .unwrap_or(state.metadata_sync.lock().unwrap().enabled);
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<PhotoTextResult> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        let files = db::set_photo_text(&conn, &request).map_err(|e| e.to_string())?;
+        let (sidecars_written, sidecar_errors) = if write_sidecars {
+            write_text_sidecars(&paths, &files, &request)
+        } else {
+            (0, 0)
+        };
+        Ok(PhotoTextResult {
+            updated: files.len(),
+            sidecars_written,
+            sidecar_errors,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn geotag_from_tracks(
+    state: tauri::State<'_, AppState>,
+    request: GeotagRequest,
+) -> InvokeResult<GeotagReport> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<GeotagReport> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        let report = geotag::run_geotag(&conn, &paths, &request).map_err(|e| e.to_string())?;
+        if !request.dry_run {
+            let ids: Vec<i64> = report.matched.iter().map(|m| m.id).collect();
+            if let Err(err) = geocode::geocode_photos(&conn, &paths, &ids) {
+                log::warn!("Reverse geocoding after geotag failed: {err}");
+            }
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn reverse_geocode(
+    state: tauri::State<'_, AppState>,
+    photo_ids: Option<Vec<i64>>,
+    refresh: Option<bool>,
+) -> InvokeResult<usize> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<usize> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        let ids = match photo_ids {
+            Some(ids) => ids,
+            None => db::list_geocode_candidates(&conn, refresh.unwrap_or(false))
+                .map_err(|e| e.to_string())?,
+        };
+        geocode::geocode_photos(&conn, &paths, &ids).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn gc_cache(state: tauri::State<'_, AppState>) -> InvokeResult<CacheGcReport> {
+    if state.jobs.is_importing() {
+        return Err("Cannot clean the cache while an import is running".into());
+    }
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    let max_bytes = state.cache.lock().unwrap().max_bytes;
+    let renditions = state.renditions.lock().unwrap().clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<CacheGcReport> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        cache::collect_garbage(&conn, &paths, &renditions, max_bytes).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Starts the cache integrity check job; broken renditions are re-rendered when `rebuild` is
+/// set (the default). Progress arrives through `import-progress` events.
+#[tauri::command]
+fn check_cache(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    rebuild: Option<bool>,
+) -> InvokeResult<String> {
+    state
+        .jobs
+        .start_cache_check(
+            app,
+            state.db.clone(),
+            state.paths.clone(),
+            state.renditions.lock().unwrap().clone(),
+            rebuild.unwrap_or(true),
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_cache_limit(state: tauri::State<AppState>, max_bytes: Option<u64>) -> InvokeResult<()> {
+    let max_bytes = max_bytes.filter(|bytes| *bytes > 0);
+    Settings::update(&state.paths, |settings| {
+        settings.cache.max_bytes = max_bytes
+    })
+    .map_err(|e| e.to_string())?;
+    state.cache.lock().unwrap().max_bytes = max_bytes;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_rendition_profiles(state: tauri::State<AppState>) -> InvokeResult<RenditionConfig> {
+    Ok(state.renditions.lock().unwrap().clone())
+}
+
+#[tauri::command]
+fn set_rendition_profiles(
+    state: tauri::State<AppState>,
+    config: RenditionConfig,
+) -> InvokeResult<RenditionConfig> {
+    config.validate().map_err(|e| e.to_string())?;
+    Settings::update(&state.paths, |settings| {
+        settings.renditions = config.clone()
+    })
+    .map_err(|e| e.to_string())?;
+    *state.renditions.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+/// The backup settings, with `dir` resolved to the folder actually in use.
+#[tauri::command]
+fn get_backup_config(state: tauri::State<AppState>) -> InvokeResult<BackupConfig> {
+    let mut config = state.backups.lock().unwrap().clone();
+    config.dir = Some(config.resolve_dir(&state.paths));
+    Ok(config)
+}
+
+#[tauri::command]
+fn set_backup_config(
+    state: tauri::State<AppState>,
+    config: BackupConfig,
+) -> InvokeResult<BackupConfig> {
+    config.validate().map_err(|e| e.to_string())?;
+    Settings::update(&state.paths, |settings| settings.backup = config.clone())
+        .map_err(|e| e.to_string())?;
+    *state.backups.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+#[tauri::command]
+fn get_metadata_sync(state: tauri::State<AppState>) -> InvokeResult<MetadataSyncConfig> {
+    Ok(state.metadata_sync.lock().unwrap().clone())
+}
+
+#[tauri::command]
+fn set_metadata_sync(
+    state: tauri::State<AppState>,
+    config: MetadataSyncConfig,
+) -> InvokeResult<MetadataSyncConfig> {
+    Settings::update(&state.paths, |settings| {
+        settings.metadata_sync = config.clone()
+    })
+    .map_err(|e| e.to_string())?;
+    *state.metadata_sync.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+#[tauri::command]
+async fn backup_now(state: tauri::State<'_, AppState>) -> InvokeResult<BackupInfo> {
+    let pool = state.db.clone();
+    let config = state.backups.lock().unwrap().clone();
+    let dir = config.resolve_dir(&state.paths);
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<BackupInfo> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        backup::create_backup(&conn, &dir, config.keep).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn list_backups(state: tauri::State<AppState>) -> InvokeResult<Vec<BackupInfo>> {
+    let dir = state.backups.lock().unwrap().resolve_dir(&state.paths);
+    backup::list_backups(&dir).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_backup(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> InvokeResult<RestoreReport> {
+    if state.jobs.is_importing() {
+        return Err("Cannot restore while an import is running".into());
+    }
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    let config = state.backups.lock().unwrap().clone();
+    let dir = config.resolve_dir(&paths);
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<RestoreReport> {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        backup::restore_backup(&mut conn, &paths, Path::new(&path), &dir, config.keep)
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Best cached rendition for showing a photo at `display_size` pixels (longer edge, already
+/// scaled for the device pixel ratio). Renders the rendition when it is not cached yet.
+#[tauri::command]
+async fn get_rendition(
+    state: tauri::State<'_, AppState>,
+    photo_id: i64,
+    display_size: u32,
+) -> InvokeResult<Option<RenditionInfo>> {
+    let pool = state.db.clone();
+    let paths = state.paths.clone();
+    let renditions = state.renditions.lock().unwrap().clone();
+    tauri::async_runtime::spawn_blocking(move || -> InvokeResult<Option<RenditionInfo>> {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        cache::rendition_for_photo(&conn, &paths, &renditions, photo_id, display_size)
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn query_map_clusters(
+    state: tauri::State<AppState>,
+    query: MapQuery,
+) -> InvokeResult<MapClusterResponse> {
+    let conn = state.db.get().map_err(|e| e.to_string())?;
+    mapview::query_clusters(&conn, &query).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn export_csv(
     state: tauri::State<AppState>,
@@ -329,6 +1033,9 @@ async fn import_folder(
             state.db.clone(),
             state.paths.clone(),
             state.tagging.lock().unwrap().clone(),
+            state.cache.lock().unwrap().clone(),
+            state.renditions.lock().unwrap().clone(),
+            state.metadata_sync.lock().unwrap().clone(),
         )
         .map_err(|e| e.to_string())
 }
@@ -367,6 +1074,17 @@ fn show_in_folder(path: String) -> InvokeResult<()> {
     Ok(())
 }
 
+/// Handler for `phototag://` requests. Missing renditions are rendered in the background, so
+/// the frontend never has to deal with stale `thumb_path`/`preview_path` values.
+fn serve_rendition(
+    app: &tauri::AppHandle,
+    request: &tauri::http::Request,
+) -> std::result::Result<tauri::http::Response, Box<dyn std::error::Error>> {
+    let state = app.state::<AppState>();
+    let renditions = state.renditions.lock().unwrap().clone();
+    protocol::respond(&state.db, &state.paths, &renditions, request)
+}
+
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
@@ -393,14 +1111,22 @@ fn main() {
         "PHOTO_TAGGER_FACE_MODEL",
     );
     let db_pool = db::init_database(&paths).expect("Failed to initialize database");
+    let settings = Settings::load(&paths);
+    let backups = Arc::new(Mutex::new(settings.backup));
+    backup::spawn_scheduler(db_pool.clone(), paths.clone(), backups.clone());
 
     tauri::Builder::default()
         .manage(AppState {
             db: db_pool,
             paths,
             tagging: Arc::new(Mutex::new(tagging)),
+            cache: Arc::new(Mutex::new(settings.cache)),
+            renditions: Arc::new(Mutex::new(settings.renditions)),
+            backups,
+            metadata_sync: Arc::new(Mutex::new(settings.metadata_sync)),
             jobs: JobManager::default(),
         })
+        .register_uri_scheme_protocol(protocol::SCHEME, serve_rendition)
         .setup(|app| {
             if let Err(err) = onnx::init_ort_dylib_path(&app.app_handle()) {
                 log::warn!("Failed to initialize ORT DLL path: {err}");
@@ -416,6 +1142,9 @@ fn main() {
             is_directory,
             show_in_folder,
             query_photos,
+            facet_counts,
+            validate_query,
+            get_photo_metadata,
             add_manual_tag,
             remove_manual_tag,
             rerun_auto,
@@ -424,7 +1153,55 @@ fn main() {
             toggle_picked,
             toggle_rejected,
             batch_update_cull,
+            list_color_labels,
+            rename_color_label,
             get_smart_views_counts,
+            list_smart_collections,
+            create_smart_collection,
+            update_smart_collection,
+            delete_smart_collection,
+            reorder_smart_collections,
+            list_tags,
+            rename_tag,
+            merge_tags,
+            delete_tag,
+            undo_edit,
+            redo_edit,
+            edit_history,
+            list_keywords,
+            save_keyword,
+            delete_keyword,
+            import_keyword_list,
+            export_keyword_list,
+            list_albums,
+            create_album,
+            rename_album,
+            delete_album,
+            add_to_album,
+            remove_from_album,
+            reorder_album,
+            set_album_cover,
+            duplicate_album,
+            preview_time_shift,
+            apply_time_shift,
+            revert_time_shift,
+            set_photo_text,
+            geotag_from_tracks,
+            reverse_geocode,
+            query_map_clusters,
+            gc_cache,
+            check_cache,
+            set_cache_limit,
+            get_rendition_profiles,
+            set_rendition_profiles,
+            get_backup_config,
+            set_backup_config,
+            get_metadata_sync,
+            set_metadata_sync,
+            backup_now,
+            list_backups,
+            restore_backup,
+            get_rendition,
             find_duplicates,
             find_similar,
             get_inference_status,
@@ -0,0 +1,192 @@
+use crate::db::{self, DbConnection, GeoPoint};
+use crate::error::Result;
+use crate::models::{MapBounds, MapCluster, MapClusterResponse, MapQuery};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Markers closer than this on screen (in pixels at the requested zoom) are merged.
+const CLUSTER_RADIUS_PX: f64 = 60.0;
+const TILE_SIZE: f64 = 256.0;
+const MAX_ZOOM: u8 = 22;
+
+/// Web Mercator world pixel coordinates at `zoom`.
+fn project(lat: f64, lng: f64, zoom: u8) -> (f64, f64) {
+    let scale = TILE_SIZE * f64::from(1u32 << zoom.min(MAX_ZOOM));
+    let lat = lat.clamp(-85.05112878, 85.05112878).to_radians();
+    let x = (lng + 180.0) / 360.0 * scale;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * scale;
+    (x, y)
+}
+
+struct ClusterAcc {
+    count: usize,
+    sum_lat: f64,
+    /// Longitudes are summed unwrapped around the first point's, so the mean of points on both
+    /// sides of the antimeridian stays next to them.
+    sum_lng: f64,
+    bounds: MapBounds,
+    best: GeoPoint,
+}
+
+/// `lng` moved by whole turns to within 180 degrees of `reference`.
+fn unwrap_lng(lng: f64, reference: f64) -> f64 {
+    reference + (lng - reference + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Normalizes a longitude to [-180, 180).
+fn wrap_lng(lng: f64) -> f64 {
+    (lng + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Widens `bounds` to take in `lng` the shorter way round. Bounds that cross the antimeridian
+/// come out with `west > east`, like the bounds of a `MapQuery`, rather than spanning the world.
+fn extend_lng(bounds: &mut MapBounds, lng: f64) {
+    let span = (bounds.east - bounds.west).rem_euclid(360.0);
+    if (lng - bounds.west).rem_euclid(360.0) <= span {
+        return;
+    }
+    let eastward = (lng - bounds.east).rem_euclid(360.0);
+    let westward = (bounds.west - lng).rem_euclid(360.0);
+    if eastward <= westward {
+        bounds.east = lng;
+    } else {
+        bounds.west = lng;
+    }
+}
+
+/// Picks the marker thumbnail: picks first, then higher rating, then the most recent capture.
+fn better_representative(candidate: &GeoPoint, current: &GeoPoint) -> bool {
+    (
+        candidate.picked,
+        candidate.rating.unwrap_or(0),
+        candidate.date_taken.unwrap_or(0),
+    ) > (
+        current.picked,
+        current.rating.unwrap_or(0),
+        current.date_taken.unwrap_or(0),
+    )
+}
+
+/// Grid-based clustering in screen space, so clusters stay a constant size while zooming.
+pub fn cluster_points(points: Vec<GeoPoint>, zoom: u8) -> Vec<MapCluster> {
+    let mut cells: HashMap<(i64, i64), ClusterAcc> = HashMap::new();
+    for point in points {
+        let (x, y) = project(point.lat, point.lng, zoom);
+        let key = (
+            (x / CLUSTER_RADIUS_PX).floor() as i64,
+            (y / CLUSTER_RADIUS_PX).floor() as i64,
+        );
+        match cells.get_mut(&key) {
+            Some(acc) => {
+                acc.count += 1;
+                acc.sum_lat += point.lat;
+                acc.sum_lng += unwrap_lng(point.lng, acc.sum_lng / (acc.count - 1) as f64);
+                acc.bounds.north = acc.bounds.north.max(point.lat);
+                acc.bounds.south = acc.bounds.south.min(point.lat);
+                extend_lng(&mut acc.bounds, point.lng);
+                if better_representative(&point, &acc.best) {
+                    acc.best = point;
+                }
+            }
+            None => {
+                cells.insert(
+                    key,
+                    ClusterAcc {
+                        count: 1,
+                        sum_lat: point.lat,
+                        sum_lng: point.lng,
+                        bounds: MapBounds {
+                            north: point.lat,
+                            south: point.lat,
+                            east: point.lng,
+                            west: point.lng,
+                        },
+                        best: point,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut clusters: Vec<MapCluster> = cells
+        .into_values()
+        .map(|acc| MapCluster {
+            lat: acc.sum_lat / acc.count as f64,
+            lng: wrap_lng(acc.sum_lng / acc.count as f64),
+            count: acc.count,
+            photo_id: acc.best.id,
+            thumb_path: acc.best.thumb_path,
+            bounds: acc.bounds,
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.count.cmp(&a.count).then(a.photo_id.cmp(&b.photo_id)));
+    clusters
+}
+
+pub fn query_clusters(conn: &DbConnection, query: &MapQuery) -> Result<MapClusterResponse> {
+    let points = db::query_geo_points(conn, &query.bounds, &query.filters)?;
+    let total = points.len();
+    Ok(MapClusterResponse {
+        clusters: cluster_points(points, query.zoom),
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: i64, lat: f64, lng: f64) -> GeoPoint {
+        GeoPoint {
+            id,
+            lat,
+            lng,
+            thumb_path: None,
+            rating: None,
+            picked: false,
+            date_taken: None,
+        }
+    }
+
+    #[test]
+    fn nearby_points_merge_until_zoomed_in() {
+        let points = || {
+            vec![
+                point(1, 35.0116, 135.7681),
+                point(2, 35.0120, 135.7690),
+                point(3, 34.6937, 135.5023),
+            ]
+        };
+        let clusters = cluster_points(points(), 8);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].count, 2);
+
+        let clusters = cluster_points(points(), 20);
+        assert_eq!(clusters.len(), 3);
+    }
+
+    #[test]
+    fn representative_prefers_picked() {
+        let mut picked = point(2, 35.0, 135.0);
+        picked.picked = true;
+        let clusters = cluster_points(vec![point(1, 35.0, 135.0), picked], 10);
+        assert_eq!(clusters[0].photo_id, 2);
+    }
+
+    #[test]
+    fn bounds_take_the_short_way_across_the_antimeridian() {
+        let mut bounds = MapBounds {
+            north: 0.0,
+            south: 0.0,
+            east: 179.5,
+            west: 179.5,
+        };
+        extend_lng(&mut bounds, -179.8);
+        extend_lng(&mut bounds, 179.9);
+        assert_eq!((bounds.west, bounds.east), (179.5, -179.8));
+        extend_lng(&mut bounds, 178.0);
+        assert_eq!((bounds.west, bounds.east), (178.0, -179.8));
+        assert!((wrap_lng(unwrap_lng(-179.8, 179.5)) + 179.8).abs() < 1e-9);
+        assert!((unwrap_lng(-179.8, 179.5) - 180.2).abs() < 1e-9);
+    }
+}
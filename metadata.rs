@@ -0,0 +1,88 @@
+use crate::error::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+use serde_json::Value;
+use std::io::{Read, Write};
+
+/// Name of the SQL function that inflates a `photo_metadata.raw` blob back into JSON text.
+pub const META_JSON_FN: &str = "photo_meta_json";
+
+pub fn compress_json(value: &Value) -> Result<Vec<u8>> {
+    let data = serde_json::to_vec(value)?;
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(data.len() / 4), Compression::default());
+    encoder.write_all(&data)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn decompress_to_string(blob: &[u8]) -> std::io::Result<String> {
+    let mut decoder = ZlibDecoder::new(blob);
+    let mut out = String::new();
+    decoder.read_to_string(&mut out)?;
+    Ok(out)
+}
+
+pub fn decompress_json(blob: &[u8]) -> Result<Value> {
+    let text = decompress_to_string(blob)?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Registers the scalar functions the query builder relies on. Called for every pooled connection.
+pub fn register_sql_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        META_JSON_FN,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let blob = ctx.get::<Option<Vec<u8>>>(0)?;
+            match blob {
+                Some(blob) => decompress_to_string(&blob)
+                    .map(Some)
+                    .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e))),
+                None => Ok(None),
+            }
+        },
+    )
+}
+
+/// ExifTool is run with `-G`, so keys look like `MakerNotes:FocusMode`. This builds a view keyed by
+/// the bare tag name so field extraction can keep using `Make`, `ISO`, etc.
+pub fn ungrouped_view(raw: &Value) -> Value {
+    let Value::Object(map) = raw else {
+        return raw.clone();
+    };
+    let mut out = serde_json::Map::with_capacity(map.len());
+    for (key, value) in map {
+        let name = key.split_once(':').map(|(_, tag)| tag).unwrap_or(key);
+        if !out.contains_key(name) {
+            out.insert(name.to_string(), value.clone());
+        }
+    }
+    Value::Object(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn compressed_json_round_trips() {
+        let value =
+            json!({ "EXIF:Make": "Canon", "MakerNotes:FocusMode": "AF-C", "EXIF:ISO": 800 });
+        let blob = compress_json(&value).unwrap();
+        assert_eq!(decompress_json(&blob).unwrap(), value);
+    }
+
+    #[test]
+    fn ungrouped_view_strips_group_prefix() {
+        let raw =
+            json!({ "SourceFile": "a.jpg", "EXIF:Make": "Nikon", "Composite:GPSLatitude": 35.0 });
+        let view = ungrouped_view(&raw);
+        assert_eq!(view["Make"], json!("Nikon"));
+        assert_eq!(view["GPSLatitude"], json!(35.0));
+        assert_eq!(view["SourceFile"], json!("a.jpg"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::RenditionFormat;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use std::collections::HashMap;
@@ -18,6 +19,7 @@ pub struct PhotoRecord {
     pub model: Option<String>,
     pub lens: Option<String>,
     pub date_taken: Option<i64>,
+    pub date_taken_original: Option<i64>,
     pub iso: Option<i64>,
     pub fnumber: Option<f64>,
     pub focal_length: Option<f64>,
@@ -25,12 +27,27 @@ pub struct PhotoRecord {
     pub exposure_comp: Option<f64>,
     pub gps_lat: Option<f64>,
     pub gps_lng: Option<f64>,
+    pub place_country: Option<String>,
+    pub place_region: Option<String>,
+    pub place_city: Option<String>,
     pub thumb_path: Option<String>,
     pub preview_path: Option<String>,
+    pub orientation: Option<i64>,
+    pub color_space: Option<String>,
     pub dhash: Option<i64>,
     pub rating: Option<i64>,
     pub picked: bool,
     pub rejected: bool,
+    /// One of the colors in `color_labels` (`red`, `yellow`, ...).
+    #[serde(default)]
+    pub color_label: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// Private notes; never written to sidecars.
+    #[serde(default)]
+    pub notes: Option<String>,
     pub last_modified: Option<i64>,
     pub import_batch_id: Option<String>,
     pub created_at: Option<i64>,
@@ -51,7 +68,24 @@ pub struct TagRecord {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct QueryFilters {
     pub search: Option<String>,
+    /// Photos with any of these tags; same as `tags_any`.
     pub tags: Vec<String>,
+    /// Photos must have every one of these tags.
+    #[serde(default)]
+    pub tags_all: Vec<String>,
+    /// Photos must have at least one of these tags.
+    #[serde(default)]
+    pub tags_any: Vec<String>,
+    /// Photos with any of these tags are left out.
+    #[serde(default)]
+    pub tags_exclude: Vec<String>,
+    /// Only tags from these sources count for the tag filters: `auto`, `manual`, `place`, or
+    /// `locked` for locked tags of any source.
+    #[serde(default)]
+    pub tag_sources: Vec<String>,
+    /// Only tags at least this confident count; manual tags have confidence 1.
+    #[serde(default)]
+    pub tag_min_confidence: Option<f32>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub lens: Option<String>,
@@ -64,12 +98,42 @@ pub struct QueryFilters {
     pub date_from: Option<i64>,
     pub date_to: Option<i64>,
     pub has_gps: Option<bool>,
+    pub place_country: Option<String>,
+    pub place_region: Option<String>,
+    pub place_city: Option<String>,
+    pub color_space: Option<String>,
+    /// Photos with any of these color labels; `none` selects unlabeled photos.
+    #[serde(default)]
+    pub color_labels: Vec<String>,
+    /// Boolean search expression, see [`crate::query::parse`].
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub meta: Vec<MetaFilter>,
     pub mode: Option<String>,
     pub smart_view: Option<String>,
+    /// Restricts to an album; results come in album order unless `sort_by` is given.
+    #[serde(default)]
+    pub album_id: Option<i64>,
     pub sort_by: Option<String>,
     pub sort_dir: Option<String>,
     pub limit: Option<i64>,
+    /// Ignored when `cursor` is given.
     pub offset: Option<i64>,
+    /// `next_cursor` of the previous [`PhotoPage`]; continues right after its last photo.
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+/// Filter on any tag in the stored ExifTool JSON, e.g. `MakerNotes:FocusMode = "AF-C"`.
+/// Keys without a group prefix match the tag in any group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetaFilter {
+    pub key: String,
+    #[serde(default)]
+    pub op: Option<String>,
+    #[serde(default)]
+    pub value: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -78,6 +142,15 @@ pub struct PhotoWithTags {
     pub tags: Vec<TagRecord>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PhotoPage {
+    pub photos: Vec<PhotoWithTags>,
+    /// Photos matching the filters across all pages.
+    pub total: i64,
+    /// Set when the page is full; pass it back as `QueryFilters::cursor` for the next page.
+    pub next_cursor: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ImportProgressEvent {
     pub discovered: usize,
@@ -143,6 +216,15 @@ pub struct ExifMetadata {
     pub gps_lng: Option<f64>,
     pub width: Option<i64>,
     pub height: Option<i64>,
+    pub orientation: Option<i64>,
+    /// Source color space ("sRGB", "Adobe RGB", "Display P3", ...).
+    pub color_space: Option<String>,
+    /// Embedded XMP/IPTC title and caption.
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    /// Full ExifTool JSON entry (group-prefixed keys); persisted separately in `photo_metadata`.
+    #[serde(skip)]
+    pub raw: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -161,9 +243,296 @@ pub struct CsvExportRow {
     pub fnumber: Option<f64>,
     pub focal: Option<f64>,
     pub shutter: Option<f64>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub notes: Option<String>,
     pub tags: Vec<String>,
 }
 
+/// Targets either an explicit selection (`photo_ids`) or everything matching `filters`.
+/// The offset is either given directly or derived from a reference photo and its true time.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TimeShiftRequest {
+    #[serde(default)]
+    pub photo_ids: Vec<i64>,
+    #[serde(default)]
+    pub filters: Option<QueryFilters>,
+    #[serde(default)]
+    pub offset_seconds: Option<i64>,
+    #[serde(default)]
+    pub reference_photo_id: Option<i64>,
+    #[serde(default)]
+    pub reference_time: Option<i64>,
+    #[serde(default)]
+    pub write_sidecars: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeShiftItem {
+    pub id: i64,
+    pub path: String,
+    pub file_name: String,
+    pub date_taken: i64,
+    pub shifted: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TimeShiftPreview {
+    pub offset_seconds: i64,
+    pub items: Vec<TimeShiftItem>,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TimeShiftResult {
+    pub updated: usize,
+    pub sidecars_written: usize,
+    pub sidecar_errors: usize,
+}
+
+/// Targets `photo_ids`, or everything matching `filters`. A `None` field is left unchanged and a
+/// blank one is cleared. Sidecars get the title and caption; notes stay in the library.
+/// `write_sidecars` defaults to the metadata sync setting.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PhotoTextRequest {
+    #[serde(default)]
+    pub photo_ids: Vec<i64>,
+    #[serde(default)]
+    pub filters: Option<QueryFilters>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub write_sidecars: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PhotoTextResult {
+    pub updated: usize,
+    pub sidecars_written: usize,
+    pub sidecar_errors: usize,
+}
+
+/// `time_offset_seconds` is added to each capture time to convert camera local time to the
+/// track's UTC; it also absorbs clock drift.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct GeotagRequest {
+    pub track_paths: Vec<String>,
+    #[serde(default)]
+    pub photo_ids: Vec<i64>,
+    #[serde(default)]
+    pub filters: Option<QueryFilters>,
+    #[serde(default)]
+    pub time_offset_seconds: i64,
+    #[serde(default = "default_geotag_max_gap")]
+    pub max_gap_seconds: i64,
+    #[serde(default)]
+    pub overwrite_existing: bool,
+    #[serde(default)]
+    pub write_sidecars: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_geotag_max_gap() -> i64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeotagMatch {
+    pub id: i64,
+    pub path: String,
+    pub file_name: String,
+    pub lat: f64,
+    pub lng: f64,
+    pub interpolated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeotagMiss {
+    pub id: i64,
+    pub file_name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct GeotagReport {
+    pub track_points: usize,
+    pub matched: Vec<GeotagMatch>,
+    pub missed: Vec<GeotagMiss>,
+    pub skipped_existing: usize,
+    pub sidecars_written: usize,
+    pub sidecar_errors: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct PlaceInfo {
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub struct MapBounds {
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct MapQuery {
+    pub bounds: MapBounds,
+    pub zoom: u8,
+    #[serde(default)]
+    pub filters: QueryFilters,
+}
+
+/// A map marker; `count == 1` is a single photo, otherwise a cluster centred on its members.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapCluster {
+    pub lat: f64,
+    pub lng: f64,
+    pub count: usize,
+    pub photo_id: i64,
+    pub thumb_path: Option<String>,
+    /// Extent of the members; `west > east` when it crosses the antimeridian.
+    pub bounds: MapBounds,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct MapClusterResponse {
+    pub clusters: Vec<MapCluster>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenditionInfo {
+    pub photo_id: i64,
+    pub profile: String,
+    pub path: String,
+    pub max_dim: u32,
+    pub format: RenditionFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CacheGcReport {
+    pub files_removed: usize,
+    pub bytes_reclaimed: u64,
+    pub evicted: usize,
+    pub remaining_bytes: u64,
+}
+
+/// A library snapshot on disk. `schema_version` is the newest applied migration in it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BackupInfo {
+    pub path: String,
+    pub file_name: String,
+    pub size: u64,
+    pub created_at: i64,
+    pub schema_version: Option<String>,
+}
+
+/// `safety_backup` is the snapshot of the library taken just before it was replaced.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RestoreReport {
+    pub restored_from: String,
+    pub schema_version: Option<String>,
+    pub migrated: bool,
+    pub safety_backup: BackupInfo,
+}
+
+/// A tag in use in the library with how many photos carry it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TagUsage {
+    pub tag: String,
+    pub photo_count: i64,
+    /// Photos per tag source (`auto`, `manual`, `place`).
+    pub by_source: HashMap<String, i64>,
+    pub locked_count: i64,
+}
+
+/// One undoable user action from the edit journal.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct EditAction {
+    pub id: i64,
+    pub label: String,
+    /// Undone actions can be redone until a new action is recorded.
+    pub undone: bool,
+    pub created_at: i64,
+    pub photo_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TagEditReport {
+    /// Tag rows renamed or merged into the target.
+    pub updated: usize,
+    /// Tag rows deleted, including duplicates folded away by a merge.
+    pub removed: usize,
+    /// Locked tag rows left untouched.
+    pub skipped_locked: usize,
+}
+
+/// A node of the keyword hierarchy. Tags equal to the name or an alias belong to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyword {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub aliases: Vec<String>,
+    /// Written as `[name]` in Lightroom keyword lists.
+    #[serde(default)]
+    pub is_category: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct KeywordInput {
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Album {
+    pub id: i64,
+    pub name: String,
+    /// The chosen cover, else the first photo in the album.
+    pub cover_photo_id: Option<i64>,
+    pub item_count: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// A saved search, or a folder grouping them. Collections are queried by passing
+/// `collection:<id>` as `QueryFilters::smart_view`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartCollection {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub name: String,
+    pub is_folder: bool,
+    pub filters: Option<QueryFilters>,
+    pub position: i64,
+    /// Live number of matching photos; `None` for folders.
+    pub count: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SmartCollectionInput {
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub is_folder: bool,
+    #[serde(default)]
+    pub filters: Option<QueryFilters>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SmartViewCounts {
     pub unsorted: i64,
@@ -171,6 +540,32 @@ pub struct SmartViewCounts {
     pub rejects: i64,
     pub last_import: i64,
     pub all: i64,
+    /// Photos per color label, in label order.
+    #[serde(default)]
+    pub color_labels: Vec<ColorLabel>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ColorLabel {
+    pub color: String,
+    /// User-facing name, e.g. "Needs retouch" for red.
+    pub name: String,
+    pub count: i64,
+}
+
+/// Grouped counts for one facet of the photos matching a filter.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FacetCounts {
+    pub facet: String,
+    pub values: Vec<FacetValue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FacetValue {
+    /// `None` counts the photos without a value. ISO buckets use the query language's range
+    /// syntax, e.g. `400..799`.
+    pub value: Option<String>,
+    pub count: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
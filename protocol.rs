@@ -0,0 +1,335 @@
+use crate::cache;
+use crate::config::{AppPaths, RenditionConfig, RenditionFormat, RenditionProfile};
+use crate::db::DbPool;
+use crate::error::Error;
+use crossbeam_channel::{unbounded, Sender};
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::http::{Request, Response, ResponseBuilder};
+
+/// `phototag://localhost/<photo_id>/<size>` (`https://phototag.localhost/...` on Windows).
+/// `<size>` is either a pixel size for the longer edge or a rendition profile name.
+pub const SCHEME: &str = "phototag";
+
+/// Renditions at most this large are kept in memory once served.
+const HOT_ENTRY_MAX_BYTES: usize = 512 * 1024;
+const HOT_CACHE_MAX_BYTES: usize = 48 * 1024 * 1024;
+
+/// Renditions are content addressed, but a photo's rendition for a given size changes when the
+/// photo is re-imported or re-oriented, so clients revalidate after an hour using the ETag.
+const CACHE_CONTROL: &str = "private, max-age=3600";
+
+/// Threads rendering the renditions that requests missed, off the webview's thread.
+const RENDER_WORKERS: usize = 2;
+
+/// How long a miss waits for its render before answering 404. Only misses whose render starts
+/// right away wait, since the handler blocks the webview while it does.
+const MISS_WAIT: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref HOT_CACHE: Mutex<HotCache> = Mutex::new(HotCache::default());
+    static ref RENDER_QUEUE: Sender<RenderJob> = spawn_render_workers();
+    /// Renders queued or running by `(photo_id, profile)`, so repeated misses queue a single
+    /// render and can wait for it.
+    static ref PENDING_RENDERS: Mutex<HashMap<(i64, String), Arc<PendingRender>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Small byte-bounded LRU of recently served thumbnails, keyed by cache file path.
+#[derive(Default)]
+struct HotCache {
+    entries: HashMap<String, Arc<Vec<u8>>>,
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl HotCache {
+    fn get(&mut self, key: &str) -> Option<Arc<Vec<u8>>> {
+        let data = self.entries.get(key)?.clone();
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).unwrap();
+            self.order.push_back(key);
+        }
+        Some(data)
+    }
+
+    fn insert(&mut self, key: String, data: Arc<Vec<u8>>) {
+        if data.len() > HOT_ENTRY_MAX_BYTES || self.entries.contains_key(&key) {
+            return;
+        }
+        self.bytes += data.len();
+        self.entries.insert(key.clone(), data);
+        self.order.push_back(key);
+        while self.bytes > HOT_CACHE_MAX_BYTES {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+    }
+}
+
+/// A queued or running render that misses for the same rendition wait on.
+#[derive(Default)]
+struct PendingRender {
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+impl PendingRender {
+    fn finish(&self) {
+        *self.done.lock().unwrap() = true;
+        self.finished.notify_all();
+    }
+
+    /// Waits up to `timeout` for the render to finish; returns whether it did.
+    fn wait(&self, timeout: Duration) -> bool {
+        let done = self.done.lock().unwrap();
+        let (done, _) = self
+            .finished
+            .wait_timeout_while(done, timeout, |done| !*done)
+            .unwrap();
+        *done
+    }
+}
+
+struct RenderJob {
+    pool: DbPool,
+    paths: AppPaths,
+    config: RenditionConfig,
+    photo_id: i64,
+    profile: RenditionProfile,
+    render: Arc<PendingRender>,
+}
+
+/// The queue is unbounded: renders are deduplicated, so it holds at most one job per rendition
+/// the frontend asked for, and dropping jobs would leave their images broken.
+fn spawn_render_workers() -> Sender<RenderJob> {
+    let (sender, receiver) = unbounded::<RenderJob>();
+    for _ in 0..RENDER_WORKERS {
+        let receiver = receiver.clone();
+        thread::spawn(move || {
+            for job in receiver {
+                let rendered = job.pool.get().map_err(Error::from).and_then(|conn| {
+                    cache::rendition_for_profile(
+                        &conn,
+                        &job.paths,
+                        &job.config,
+                        job.photo_id,
+                        &job.profile,
+                    )
+                });
+                if let Err(err) = rendered {
+                    log::warn!(
+                        "Rendering '{}' for photo {} failed: {}",
+                        job.profile.name,
+                        job.photo_id,
+                        err
+                    );
+                }
+                PENDING_RENDERS
+                    .lock()
+                    .unwrap()
+                    .remove(&(job.photo_id, job.profile.name));
+                job.render.finish();
+            }
+        });
+    }
+    sender
+}
+
+fn queue_render(
+    pool: &DbPool,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    photo_id: i64,
+    profile: &RenditionProfile,
+) -> Arc<PendingRender> {
+    let pending = (photo_id, profile.name.clone());
+    let render = {
+        let mut renders = PENDING_RENDERS.lock().unwrap();
+        if let Some(render) = renders.get(&pending) {
+            return render.clone();
+        }
+        let render = Arc::new(PendingRender::default());
+        renders.insert(pending.clone(), render.clone());
+        render
+    };
+    let job = RenderJob {
+        pool: pool.clone(),
+        paths: paths.clone(),
+        config: config.clone(),
+        photo_id,
+        profile: profile.clone(),
+        render: render.clone(),
+    };
+    if RENDER_QUEUE.send(job).is_err() {
+        PENDING_RENDERS.lock().unwrap().remove(&pending);
+        render.finish();
+    }
+    render
+}
+
+#[derive(Debug, PartialEq)]
+enum SizeSpec {
+    Pixels(u32),
+    Profile(String),
+}
+
+/// Extracts `(photo_id, size)` from a protocol URI, ignoring host and query string.
+fn parse_uri(uri: &str) -> Option<(i64, SizeSpec)> {
+    let rest = uri.split_once("://").map(|(_, rest)| rest).unwrap_or(uri);
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let mut segments = path.split('/').skip(1).filter(|s| !s.is_empty());
+    let photo_id = segments.next()?.parse().ok()?;
+    let size = match segments.next() {
+        None => SizeSpec::Pixels(0),
+        Some(size) => match size.parse() {
+            Ok(pixels) => SizeSpec::Pixels(pixels),
+            Err(_) => SizeSpec::Profile(size.to_string()),
+        },
+    };
+    Some((photo_id, size))
+}
+
+fn content_type(format: RenditionFormat) -> &'static str {
+    match format {
+        RenditionFormat::Jpeg => "image/jpeg",
+        RenditionFormat::Webp => "image/webp",
+    }
+}
+
+fn status(code: u16) -> Result<Response, Box<dyn std::error::Error>> {
+    ResponseBuilder::new()
+        .status(code)
+        .header("Cache-Control", "no-store")
+        .body(Vec::new())
+}
+
+/// Serves a cached rendition. On a miss the render is queued on a worker; when a worker takes
+/// it right away the request waits up to [`MISS_WAIT`] for it, otherwise (or when it takes
+/// longer) 404 is returned and the frontend retries the image shortly after. The ETag is the
+/// cache file name, which already hashes the content and render parameters.
+pub fn respond(
+    pool: &DbPool,
+    paths: &AppPaths,
+    config: &RenditionConfig,
+    request: &Request,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let Some((photo_id, size)) = parse_uri(request.uri()) else {
+        return status(400);
+    };
+    let profile = match &size {
+        SizeSpec::Pixels(pixels) => config.best_for(*pixels),
+        SizeSpec::Profile(name) => match config.profile(name) {
+            Some(profile) => profile,
+            None => return status(400),
+        },
+    };
+    // The connection is not held while waiting, the render worker may need it.
+    let lookup = || -> crate::error::Result<_> {
+        let conn = pool.get()?;
+        cache::cached_rendition(&conn, paths, config, photo_id, profile)
+    };
+    let mut rendition = lookup()?;
+    if rendition.is_none() {
+        let render = queue_render(pool, paths, config, photo_id, profile);
+        // Waiting behind a long queue would stall the webview; those misses are retried.
+        if RENDER_QUEUE.len() <= RENDER_WORKERS && render.wait(MISS_WAIT) {
+            rendition = lookup()?;
+        }
+    }
+    let Some(rendition) = rendition else {
+        return status(404);
+    };
+
+    let etag = Path::new(&rendition.path)
+        .file_stem()
+        .map(|stem| format!("\"{}\"", stem.to_string_lossy()))
+        .unwrap_or_default();
+    let not_modified = request
+        .headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false);
+    if not_modified {
+        return ResponseBuilder::new()
+            .status(304)
+            .header("ETag", etag.as_str())
+            .header("Cache-Control", CACHE_CONTROL)
+            .body(Vec::new());
+    }
+
+    let cached = HOT_CACHE.lock().unwrap().get(&rendition.path);
+    let data = match cached {
+        Some(data) => data,
+        None => {
+            let data = Arc::new(std::fs::read(&rendition.path)?);
+            HOT_CACHE
+                .lock()
+                .unwrap()
+                .insert(rendition.path.clone(), data.clone());
+            data
+        }
+    };
+    ResponseBuilder::new()
+        .status(200)
+        .mimetype(content_type(rendition.format))
+        .header("ETag", etag.as_str())
+        .header("Cache-Control", CACHE_CONTROL)
+        .body(data.as_ref().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_uri_forms() {
+        assert_eq!(
+            parse_uri("phototag://localhost/42/640"),
+            Some((42, SizeSpec::Pixels(640)))
+        );
+        assert_eq!(
+            parse_uri("https://phototag.localhost/42/preview?v=1"),
+            Some((42, SizeSpec::Profile("preview".into())))
+        );
+        assert_eq!(
+            parse_uri("phototag://localhost/7"),
+            Some((7, SizeSpec::Pixels(0)))
+        );
+        assert_eq!(parse_uri("phototag://localhost/abc/320"), None);
+    }
+
+    #[test]
+    fn hot_cache_evicts_least_recently_used() {
+        let mut cache = HotCache::default();
+        let chunk = HOT_ENTRY_MAX_BYTES;
+        let count = HOT_CACHE_MAX_BYTES / chunk;
+        for i in 0..count {
+            cache.insert(format!("k{i}"), Arc::new(vec![0; chunk]));
+        }
+        assert!(cache.get("k0").is_some());
+        cache.insert("new".into(), Arc::new(vec![0; chunk]));
+        assert!(cache.get("k0").is_some());
+        assert!(cache.get("k1").is_none());
+        assert!(cache.bytes <= HOT_CACHE_MAX_BYTES);
+    }
+
+    #[test]
+    fn pending_renders_wake_waiters() {
+        let render = Arc::new(PendingRender::default());
+        assert!(!render.wait(Duration::from_millis(1)));
+        let finisher = render.clone();
+        let handle = thread::spawn(move || finisher.finish());
+        assert!(render.wait(Duration::from_secs(5)));
+        handle.join().unwrap();
+    }
+}
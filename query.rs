@@ -0,0 +1,657 @@
+use crate::keywords;
+use chrono::NaiveDate;
+use rusqlite::types::Value;
+use serde::Serialize;
+
+/// A parse error with the character range of the offending input, so the search box can
+/// underline it.
+#[derive(Debug, Clone, PartialEq, Serialize, thiserror::Error)]
+#[error("{message} (at {start}..{end})")]
+pub struct QueryError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl QueryError {
+    fn new(message: impl Into<String>, start: usize, end: usize) -> Self {
+        Self {
+            message: message.into(),
+            start,
+            end,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn sql(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// Free text matched against the search index; phrases match word for word, bare words
+    /// as prefixes.
+    Text {
+        text: String,
+        phrase: bool,
+    },
+    Tag(String),
+    Camera(String),
+    Lens(String),
+    /// Color label by color or by its user-given name.
+    Label(String),
+    Number {
+        column: &'static str,
+        op: CmpOp,
+        value: f64,
+    },
+    /// Half-open `[from, to)` range of capture times.
+    Date {
+        from: Option<i64>,
+        to: Option<i64>,
+    },
+    Picked,
+    Rejected,
+    Unsorted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    /// The flag is set for `:`, which some fields accept where they reject `=`-style comparisons.
+    Op(CmpOp, bool),
+    Word(String),
+    Quoted(String),
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+fn is_op_char(c: char) -> bool {
+    matches!(c, ':' | '<' | '>' | '=')
+}
+
+fn lex(input: &str) -> Result<Vec<Spanned>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            '"' => {
+                i += 1;
+                let text_start = i;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(QueryError::new("Unterminated quote", start, i));
+                }
+                let text: String = chars[text_start..i].iter().collect();
+                i += 1;
+                Token::Quoted(text)
+            }
+            _ if is_op_char(c) => {
+                i += 1;
+                let op = match (c, chars.get(i)) {
+                    ('<', Some('=')) => Some(CmpOp::Le),
+                    ('>', Some('=')) => Some(CmpOp::Ge),
+                    _ => None,
+                };
+                match op {
+                    Some(op) => {
+                        i += 1;
+                        Token::Op(op, false)
+                    }
+                    None => match c {
+                        '<' => Token::Op(CmpOp::Lt, false),
+                        '>' => Token::Op(CmpOp::Gt, false),
+                        '=' => Token::Op(CmpOp::Eq, false),
+                        _ => Token::Op(CmpOp::Eq, true),
+                    },
+                }
+            }
+            _ => {
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], '(' | ')' | '"')
+                    && !is_op_char(chars[i])
+                {
+                    i += 1;
+                }
+                Token::Word(chars[start..i].iter().collect())
+            }
+        };
+        tokens.push(Spanned {
+            token,
+            start,
+            end: i,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Spanned> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Spanned { token: Token::Word(w), .. }) if w == keyword)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.parse_and()?;
+        while self.peek_keyword("OR") {
+            self.next();
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    /// `AND` is optional: juxtaposed terms must all match.
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.parse_not()?;
+        loop {
+            if self.peek_keyword("AND") {
+                self.next();
+            } else if matches!(
+                self.peek(),
+                None | Some(Spanned {
+                    token: Token::RParen,
+                    ..
+                })
+            ) || self.peek_keyword("OR")
+            {
+                break;
+            }
+            let right = self.parse_not()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.peek_keyword("NOT") {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.next() else {
+            return Err(QueryError::new(
+                "Expected a search term",
+                self.len,
+                self.len,
+            ));
+        };
+        match token.token {
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Spanned {
+                        token: Token::RParen,
+                        ..
+                    }) => Ok(expr),
+                    _ => Err(QueryError::new(
+                        "Missing closing parenthesis",
+                        token.start,
+                        token.end,
+                    )),
+                }
+            }
+            Token::RParen => Err(QueryError::new(
+                "Unexpected closing parenthesis",
+                token.start,
+                token.end,
+            )),
+            Token::Op(..) => Err(QueryError::new(
+                "Operator without a field",
+                token.start,
+                token.end,
+            )),
+            Token::Quoted(text) => Ok(Expr::Term(Term::Text { text, phrase: true })),
+            Token::Word(word) => {
+                if let Some(Spanned {
+                    token: Token::Op(op, colon),
+                    ..
+                }) = self.peek().cloned()
+                {
+                    self.next();
+                    return self.parse_field(&word, token.start, op, colon);
+                }
+                if let Some(negated) = word.strip_prefix('-').filter(|w| !w.is_empty()) {
+                    return Ok(Expr::Not(Box::new(Expr::Term(word_term(negated)))));
+                }
+                if matches!(word.as_str(), "AND" | "OR") {
+                    return Err(QueryError::new(
+                        format!("'{word}' needs a term on both sides"),
+                        token.start,
+                        token.end,
+                    ));
+                }
+                Ok(Expr::Term(word_term(&word)))
+            }
+        }
+    }
+
+    fn parse_field(
+        &mut self,
+        field: &str,
+        field_start: usize,
+        op: CmpOp,
+        colon: bool,
+    ) -> Result<Expr, QueryError> {
+        let (value, start, end) = match self.next() {
+            Some(Spanned {
+                token: Token::Word(value) | Token::Quoted(value),
+                start,
+                end,
+            }) => (value, start, end),
+            other => {
+                let at = other.map(|t| t.start).unwrap_or(self.len);
+                return Err(QueryError::new(
+                    format!("Expected a value for '{field}'"),
+                    at,
+                    at,
+                ));
+            }
+        };
+        let field_end = field_start + field.chars().count();
+        let text_only = |term: fn(String) -> Term| {
+            if colon || op == CmpOp::Eq {
+                Ok(Expr::Term(term(value.clone())))
+            } else {
+                Err(QueryError::new(
+                    format!("'{field}' only supports ':'"),
+                    field_start,
+                    end,
+                ))
+            }
+        };
+        match field.to_ascii_lowercase().as_str() {
+            "tag" => text_only(Term::Tag),
+            "camera" => text_only(Term::Camera),
+            "lens" => text_only(Term::Lens),
+            "label" => text_only(Term::Label),
+            "date" => parse_date(&value, op, start, end).map(Expr::Term),
+            name => {
+                let column = match name {
+                    "iso" => "iso",
+                    "f" | "aperture" => "fnumber",
+                    "focal" => "focal_length",
+                    "rating" => "rating",
+                    _ => {
+                        return Err(QueryError::new(
+                            format!("Unknown field '{field}'"),
+                            field_start,
+                            field_end,
+                        ))
+                    }
+                };
+                let number = value.parse::<f64>().map_err(|_| {
+                    QueryError::new(format!("'{value}' is not a number"), start, end)
+                })?;
+                Ok(Expr::Term(Term::Number {
+                    column,
+                    op,
+                    value: number,
+                }))
+            }
+        }
+    }
+}
+
+/// Bare words: the cull states are keywords, everything else is searched as text.
+fn word_term(word: &str) -> Term {
+    match word.to_ascii_lowercase().as_str() {
+        "picked" => Term::Picked,
+        "rejected" => Term::Rejected,
+        "unsorted" => Term::Unsorted,
+        _ => Term::Text {
+            text: word.to_string(),
+            phrase: false,
+        },
+    }
+}
+
+/// Parses `2023`, `2023-05` or `2023-05-14` into the `[start, end)` span it covers.
+fn parse_date_span(value: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let year: i32 = parts.first()?.parse().ok()?;
+    match parts.len() {
+        1 => Some((
+            NaiveDate::from_ymd_opt(year, 1, 1)?,
+            NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+        )),
+        2 => {
+            let month: u32 = parts[1].parse().ok()?;
+            let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+            let end = if month == 12 {
+                NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+            } else {
+                NaiveDate::from_ymd_opt(year, month + 1, 1)?
+            };
+            Some((start, end))
+        }
+        3 => {
+            let day =
+                NaiveDate::from_ymd_opt(year, parts[1].parse().ok()?, parts[2].parse().ok()?)?;
+            Some((day, day.succ_opt()?))
+        }
+        _ => None,
+    }
+}
+
+/// Capture times are stored as naive local time read as UTC, so dates are converted the same way.
+fn timestamp(date: NaiveDate) -> i64 {
+    date.and_hms_opt(0, 0, 0)
+        .map(|dt| chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(dt, chrono::Utc))
+        .map(|dt| dt.timestamp())
+        .unwrap_or_default()
+}
+
+fn parse_date(value: &str, op: CmpOp, start: usize, end: usize) -> Result<Term, QueryError> {
+    let span = |text: &str| {
+        parse_date_span(text).ok_or_else(|| {
+            QueryError::new(
+                format!("'{text}' is not a date (use YYYY, YYYY-MM or YYYY-MM-DD)"),
+                start,
+                end,
+            )
+        })
+    };
+    if let Some((from, to)) = value.split_once("..") {
+        if op != CmpOp::Eq {
+            return Err(QueryError::new(
+                "Date ranges only work with 'date:'",
+                start,
+                end,
+            ));
+        }
+        let from = if from.is_empty() {
+            None
+        } else {
+            Some(timestamp(span(from)?.0))
+        };
+        let to = if to.is_empty() {
+            None
+        } else {
+            Some(timestamp(span(to)?.1))
+        };
+        return Ok(Term::Date { from, to });
+    }
+    let (first, last) = span(value)?;
+    let (first, last) = (timestamp(first), timestamp(last));
+    Ok(match op {
+        CmpOp::Eq => Term::Date {
+            from: Some(first),
+            to: Some(last),
+        },
+        CmpOp::Lt => Term::Date {
+            from: None,
+            to: Some(first),
+        },
+        CmpOp::Le => Term::Date {
+            from: None,
+            to: Some(last),
+        },
+        CmpOp::Gt => Term::Date {
+            from: Some(last),
+            to: None,
+        },
+        CmpOp::Ge => Term::Date {
+            from: Some(first),
+            to: None,
+        },
+    })
+}
+
+/// Parses a search expression such as
+/// `(dog OR cat) AND NOT rejected AND iso > 3200 AND lens:70-200`.
+///
+/// `AND`, `OR` and `NOT` must be upper case; `-word` is shorthand for `NOT word`. Fields are
+/// `tag:`, `camera:`, `lens:`, `label:` (color or label name), `date:` (with `..` ranges) and
+/// the numeric `iso`, `f`, `focal` and `rating`, which take `:`/`=`, `<`, `<=`, `>` or `>=`.
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    let tokens = lex(input)?;
+    let len = input.chars().count();
+    if tokens.is_empty() {
+        return Err(QueryError::new("Empty query", 0, len));
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        len,
+    };
+    let expr = parser.parse_or()?;
+    if let Some(extra) = parser.peek() {
+        return Err(QueryError::new(
+            "Unexpected closing parenthesis",
+            extra.start,
+            extra.end,
+        ));
+    }
+    Ok(expr)
+}
+
+fn fts_term(text: &str, phrase: bool) -> String {
+    let quoted = format!("\"{}\"", text.replace('"', "\"\""));
+    if phrase {
+        quoted
+    } else {
+        format!("{quoted}*")
+    }
+}
+
+fn like_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Appends `expr` as a parenthesized condition over `photos`. Comparisons on nullable columns
+/// are guarded so that `NOT` also matches photos missing the value.
+pub fn push_sql(expr: &Expr, sql: &mut String, params: &mut Vec<Value>) {
+    match expr {
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            let joiner = if matches!(expr, Expr::And(..)) {
+                " AND "
+            } else {
+                " OR "
+            };
+            sql.push('(');
+            push_sql(left, sql, params);
+            sql.push_str(joiner);
+            push_sql(right, sql, params);
+            sql.push(')');
+        }
+        Expr::Not(inner) => {
+            sql.push_str("(NOT ");
+            push_sql(inner, sql, params);
+            sql.push(')');
+        }
+        Expr::Term(term) => push_term(term, sql, params),
+    }
+}
+
+fn push_term(term: &Term, sql: &mut String, params: &mut Vec<Value>) {
+    match term {
+        Term::Text { text, phrase } => {
+            sql.push_str("(id IN (SELECT rowid FROM photo_search WHERE photo_search MATCH ?))");
+            params.push(fts_term(text, *phrase).into());
+        }
+        Term::Tag(tag) => {
+            sql.push_str("(id IN (SELECT photo_id FROM tags WHERE ");
+            keywords::push_tag_match(sql, params, &[tag]);
+            sql.push_str("))");
+        }
+        Term::Camera(camera) => {
+            sql.push_str("(coalesce(make, '') || ' ' || coalesce(model, '') LIKE ? ESCAPE '\\')");
+            params.push(like_pattern(camera).into());
+        }
+        Term::Lens(lens) => {
+            sql.push_str("(coalesce(lens, '') LIKE ? ESCAPE '\\')");
+            params.push(like_pattern(lens).into());
+        }
+        Term::Label(label) => {
+            sql.push_str(
+                "(color_label IN (SELECT color FROM color_labels
+                  WHERE color = ? COLLATE NOCASE OR name = ? COLLATE NOCASE))",
+            );
+            params.push(label.clone().into());
+            params.push(label.clone().into());
+        }
+        Term::Number { column, op, value } => {
+            sql.push_str(&format!(
+                "({column} IS NOT NULL AND {column} {} ?)",
+                op.sql()
+            ));
+            params.push((*value).into());
+        }
+        Term::Date { from, to } => {
+            sql.push_str("(date_taken IS NOT NULL");
+            if let Some(from) = from {
+                sql.push_str(" AND date_taken >= ?");
+                params.push((*from).into());
+            }
+            if let Some(to) = to {
+                sql.push_str(" AND date_taken < ?");
+                params.push((*to).into());
+            }
+            sql.push(')');
+        }
+        Term::Picked => sql.push_str("(picked = 1)"),
+        Term::Rejected => sql.push_str("(rejected = 1)"),
+        Term::Unsorted => sql.push_str("(rating IS NULL AND picked = 0 AND rejected = 0)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(word: &str) -> Expr {
+        Expr::Term(Term::Text {
+            text: word.into(),
+            phrase: false,
+        })
+    }
+
+    #[test]
+    fn parses_boolean_expression_with_fields() {
+        let expr = parse("(dog OR cat) AND NOT rejected AND iso > 3200 AND lens:70-200").unwrap();
+        let expected = Expr::And(
+            Box::new(Expr::And(
+                Box::new(Expr::And(
+                    Box::new(Expr::Or(Box::new(text("dog")), Box::new(text("cat")))),
+                    Box::new(Expr::Not(Box::new(Expr::Term(Term::Rejected)))),
+                )),
+                Box::new(Expr::Term(Term::Number {
+                    column: "iso",
+                    op: CmpOp::Gt,
+                    value: 3200.0,
+                })),
+            )),
+            Box::new(Expr::Term(Term::Lens("70-200".into()))),
+        );
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn builds_parameterized_sql() {
+        let expr = parse("lens:\"24-70\" f<2.8 date:2023-05..2023-06").unwrap();
+        let mut sql = String::new();
+        let mut params = Vec::new();
+        push_sql(&expr, &mut sql, &mut params);
+        assert_eq!(
+            sql,
+            "(((coalesce(lens, '') LIKE ? ESCAPE '\\') AND \
+             (fnumber IS NOT NULL AND fnumber < ?)) AND \
+             (date_taken IS NOT NULL AND date_taken >= ? AND date_taken < ?))"
+        );
+        assert_eq!(
+            params,
+            vec![
+                Value::Text("%24-70%".into()),
+                Value::Real(2.8),
+                Value::Integer(1682899200),
+                Value::Integer(1688169600),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(parse("dog AND (cat").unwrap_err().start, 8);
+        assert_eq!(
+            parse("\"open").unwrap_err(),
+            QueryError::new("Unterminated quote", 0, 5)
+        );
+        let err = parse("size>3").unwrap_err();
+        assert_eq!((err.start, err.end), (0, 4));
+        let err = parse("iso>abc").unwrap_err();
+        assert_eq!((err.start, err.end), (4, 7));
+        assert_eq!(parse("dog OR").unwrap_err().start, 6);
+    }
+}
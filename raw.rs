@@ -0,0 +1,44 @@
+use crate::error::{Error, Result};
+use crate::thumbnails;
+use image::{DynamicImage, RgbImage};
+use std::path::Path;
+
+/// Formats the native decoder is tried on. Everything else goes through `image` directly.
+/// Import accepts these on top of its plain image formats.
+pub const RAW_EXTENSIONS: &[&str] = &["cr2", "nef", "arw", "dng", "raf"];
+
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| RAW_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Demosaics a RAW file into an upright sRGB image no larger than `max_dim` on its longer edge.
+/// The pipeline applies the camera's as-shot white balance, the default base tone curve and the
+/// sRGB transfer function; there is no lens correction or noise reduction. The image is turned
+/// upright with the stored `orientation` rather than the RAW's own, so corrections made in the
+/// library apply like they do to embedded previews.
+pub fn decode(path: &Path, max_dim: u32, orientation: Option<i64>) -> Result<DynamicImage> {
+    let mut pipeline = imagepipe::Pipeline::new_from_file(path)
+        .map_err(|err| Error::Init(format!("RAW decode failed for {:?}: {err}", path)))?;
+    pipeline.globals.settings.maxwidth = max_dim as usize;
+    pipeline.globals.settings.maxheight = max_dim as usize;
+    pipeline.ops.transform.rotation = imagepipe::Rotation::Normal;
+    pipeline.ops.transform.fliph = false;
+    pipeline.ops.transform.flipv = false;
+    let decoded = pipeline
+        .output_8bit(None)
+        .map_err(|err| Error::Init(format!("RAW decode failed for {:?}: {err}", path)))?;
+    let image = RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+        .ok_or_else(|| {
+            Error::Init(format!(
+                "RAW decode produced a truncated image for {:?}",
+                path
+            ))
+        })?;
+    Ok(thumbnails::apply_orientation(
+        DynamicImage::ImageRgb8(image),
+        orientation,
+    ))
+}
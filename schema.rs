@@ -97,3 +97,414 @@ CREATE TABLE IF NOT EXISTS photo_embeddings (
 
 CREATE INDEX IF NOT EXISTS idx_photo_embeddings_norm ON photo_embeddings (norm);
 "#;
+
+pub const MIGRATION_0006: &str = r#"
+-- Full ExifTool JSON per photo, zlib-compressed (read back via photo_meta_json())
+CREATE TABLE IF NOT EXISTS photo_metadata (
+    photo_id INTEGER PRIMARY KEY,
+    raw BLOB NOT NULL,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (photo_id) REFERENCES photos (id) ON DELETE CASCADE
+);
+"#;
+
+pub const MIGRATION_0007: &str = r#"
+-- EXIF orientation (1-8), applied when previews are rendered
+ALTER TABLE photos ADD COLUMN orientation INTEGER;
+"#;
+
+pub const MIGRATION_0008: &str = r#"
+-- Capture time before any batch time shift, so shifts can be reverted
+ALTER TABLE photos ADD COLUMN date_taken_original INTEGER;
+"#;
+
+pub const MIGRATION_0009: &str = r#"
+-- Offline reverse-geocoded place names
+ALTER TABLE photos ADD COLUMN place_country TEXT;
+ALTER TABLE photos ADD COLUMN place_region TEXT;
+ALTER TABLE photos ADD COLUMN place_city TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_photos_place ON photos (place_country, place_region, place_city);
+"#;
+
+pub const MIGRATION_0010: &str = r#"
+-- R*Tree spatial index over geotagged photos, kept in sync by triggers
+CREATE VIRTUAL TABLE IF NOT EXISTS photo_geo USING rtree (
+    id,
+    min_lat, max_lat,
+    min_lng, max_lng
+);
+
+CREATE TRIGGER IF NOT EXISTS photos_geo_ai AFTER INSERT ON photos
+WHEN NEW.gps_lat IS NOT NULL AND NEW.gps_lng IS NOT NULL
+BEGIN
+    INSERT OR REPLACE INTO photo_geo (id, min_lat, max_lat, min_lng, max_lng)
+    VALUES (NEW.id, NEW.gps_lat, NEW.gps_lat, NEW.gps_lng, NEW.gps_lng);
+END;
+
+CREATE TRIGGER IF NOT EXISTS photos_geo_au AFTER UPDATE OF gps_lat, gps_lng ON photos
+BEGIN
+    DELETE FROM photo_geo WHERE id = OLD.id;
+    INSERT INTO photo_geo (id, min_lat, max_lat, min_lng, max_lng)
+    SELECT NEW.id, NEW.gps_lat, NEW.gps_lat, NEW.gps_lng, NEW.gps_lng
+    WHERE NEW.gps_lat IS NOT NULL AND NEW.gps_lng IS NOT NULL;
+END;
+
+CREATE TRIGGER IF NOT EXISTS photos_geo_ad AFTER DELETE ON photos
+BEGIN
+    DELETE FROM photo_geo WHERE id = OLD.id;
+END;
+
+INSERT OR REPLACE INTO photo_geo (id, min_lat, max_lat, min_lng, max_lng)
+SELECT id, gps_lat, gps_lat, gps_lng, gps_lng FROM photos
+WHERE gps_lat IS NOT NULL AND gps_lng IS NOT NULL;
+"#;
+
+pub const MIGRATION_0011: &str = r#"
+-- Content-addressed rendition cache; keys hash the source content plus render parameters
+CREATE TABLE IF NOT EXISTS cache_entries (
+    key TEXT PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    size INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_accessed INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_cache_entries_accessed ON cache_entries (last_accessed);
+CREATE INDEX IF NOT EXISTS idx_cache_entries_hash ON cache_entries (content_hash);
+"#;
+
+pub const MIGRATION_0012: &str = r#"
+-- Rendition profile parameters, so GC can drop renditions of changed or removed profiles
+ALTER TABLE cache_entries ADD COLUMN params TEXT NOT NULL DEFAULT '';
+"#;
+
+pub const MIGRATION_0013: &str = r#"
+-- Source color space detected at import (ICC profile description or EXIF ColorSpace)
+ALTER TABLE photos ADD COLUMN color_space TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_photos_color_space ON photos (color_space);
+"#;
+
+pub const MIGRATION_0014: &str = r#"
+-- Full-text index over file name, folder, tags, camera, lens and captions; rowid = photos.id.
+-- Triggers keep it in sync. The photo_metadata triggers need the photo_meta_json() function.
+CREATE VIRTUAL TABLE IF NOT EXISTS photo_search USING fts5 (
+    file_name,
+    folder,
+    tags,
+    camera,
+    lens,
+    captions,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS photos_search_ai AFTER INSERT ON photos
+BEGIN
+    DELETE FROM photo_search WHERE rowid = NEW.id;
+    INSERT INTO photo_search (rowid, file_name, folder, tags, camera, lens, captions)
+    VALUES (
+        NEW.id,
+        NEW.file_name,
+        substr(NEW.path, 1, length(NEW.path) - length(NEW.file_name)),
+        '',
+        trim(coalesce(NEW.make, '') || ' ' || coalesce(NEW.model, '')),
+        coalesce(NEW.lens, ''),
+        ''
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS photos_search_au AFTER UPDATE OF path, file_name, make, model, lens ON photos
+BEGIN
+    UPDATE photo_search SET
+        file_name = NEW.file_name,
+        folder = substr(NEW.path, 1, length(NEW.path) - length(NEW.file_name)),
+        camera = trim(coalesce(NEW.make, '') || ' ' || coalesce(NEW.model, '')),
+        lens = coalesce(NEW.lens, '')
+    WHERE rowid = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS photos_search_ad AFTER DELETE ON photos
+BEGIN
+    DELETE FROM photo_search WHERE rowid = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS tags_search_ai AFTER INSERT ON tags
+BEGIN
+    UPDATE photo_search
+    SET tags = coalesce((SELECT group_concat(tag, ' ') FROM tags WHERE photo_id = NEW.photo_id), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS tags_search_au AFTER UPDATE OF tag, photo_id ON tags
+BEGIN
+    UPDATE photo_search
+    SET tags = coalesce((SELECT group_concat(tag, ' ') FROM tags WHERE photo_id = OLD.photo_id), '')
+    WHERE rowid = OLD.photo_id;
+    UPDATE photo_search
+    SET tags = coalesce((SELECT group_concat(tag, ' ') FROM tags WHERE photo_id = NEW.photo_id), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS tags_search_ad AFTER DELETE ON tags
+BEGIN
+    UPDATE photo_search
+    SET tags = coalesce((SELECT group_concat(tag, ' ') FROM tags WHERE photo_id = OLD.photo_id), '')
+    WHERE rowid = OLD.photo_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS photo_metadata_search_ai AFTER INSERT ON photo_metadata
+BEGIN
+    UPDATE photo_search SET captions = coalesce((
+        SELECT group_concat(value, ' ') FROM json_each(photo_meta_json(NEW.raw))
+        WHERE key IN (
+            'XMP:Title', 'XMP:Description', 'XMP:Headline', 'IPTC:ObjectName',
+            'IPTC:Caption-Abstract', 'IPTC:Headline', 'EXIF:ImageDescription'
+        )
+    ), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS photo_metadata_search_au AFTER UPDATE OF raw ON photo_metadata
+BEGIN
+    UPDATE photo_search SET captions = coalesce((
+        SELECT group_concat(value, ' ') FROM json_each(photo_meta_json(NEW.raw))
+        WHERE key IN (
+            'XMP:Title', 'XMP:Description', 'XMP:Headline', 'IPTC:ObjectName',
+            'IPTC:Caption-Abstract', 'IPTC:Headline', 'EXIF:ImageDescription'
+        )
+    ), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+DELETE FROM photo_search;
+INSERT INTO photo_search (rowid, file_name, folder, tags, camera, lens, captions)
+SELECT
+    p.id,
+    p.file_name,
+    substr(p.path, 1, length(p.path) - length(p.file_name)),
+    coalesce((SELECT group_concat(tag, ' ') FROM tags WHERE photo_id = p.id), ''),
+    trim(coalesce(p.make, '') || ' ' || coalesce(p.model, '')),
+    coalesce(p.lens, ''),
+    coalesce((
+        SELECT group_concat(j.value, ' ')
+        FROM photo_metadata m, json_each(photo_meta_json(m.raw)) j
+        WHERE m.photo_id = p.id AND j.key IN (
+            'XMP:Title', 'XMP:Description', 'XMP:Headline', 'IPTC:ObjectName',
+            'IPTC:Caption-Abstract', 'IPTC:Headline', 'EXIF:ImageDescription'
+        )
+    ), '')
+FROM photos p;
+"#;
+
+pub const MIGRATION_0015: &str = r#"
+-- User-defined smart collections (saved QueryFilters as JSON) and the folders that group them
+CREATE TABLE IF NOT EXISTS smart_collections (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    parent_id INTEGER,
+    name TEXT NOT NULL,
+    is_folder INTEGER NOT NULL DEFAULT 0,
+    filters TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (parent_id) REFERENCES smart_collections (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_smart_collections_parent ON smart_collections (parent_id, position);
+"#;
+
+pub const MIGRATION_0016: &str = r#"
+-- Hand-assembled albums; album_items.position gives the album order
+CREATE TABLE IF NOT EXISTS albums (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    cover_photo_id INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS album_items (
+    album_id INTEGER NOT NULL,
+    photo_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (album_id, photo_id),
+    FOREIGN KEY (album_id) REFERENCES albums (id) ON DELETE CASCADE,
+    FOREIGN KEY (photo_id) REFERENCES photos (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_album_items_order ON album_items (album_id, position);
+CREATE INDEX IF NOT EXISTS idx_album_items_photo ON album_items (photo_id);
+
+CREATE TRIGGER IF NOT EXISTS photos_album_ad AFTER DELETE ON photos
+BEGIN
+    DELETE FROM album_items WHERE photo_id = OLD.id;
+    UPDATE albums SET cover_photo_id = NULL WHERE cover_photo_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS albums_ad AFTER DELETE ON albums
+BEGIN
+    DELETE FROM album_items WHERE album_id = OLD.id;
+END;
+"#;
+
+pub const MIGRATION_0017: &str = r#"
+-- Keyword hierarchy; tags are matched to keywords by name or alias
+CREATE TABLE IF NOT EXISTS keywords (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    parent_id INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (parent_id) REFERENCES keywords (id) ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS keyword_aliases (
+    alias TEXT PRIMARY KEY COLLATE NOCASE,
+    keyword_id INTEGER NOT NULL,
+    FOREIGN KEY (keyword_id) REFERENCES keywords (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_keywords_parent ON keywords (parent_id);
+CREATE INDEX IF NOT EXISTS idx_keyword_aliases_keyword ON keyword_aliases (keyword_id);
+"#;
+
+pub const MIGRATION_0018: &str = r#"
+-- Undo journal: each user action stores the before/after state of every cull field or tag row
+-- it changed. Undone actions stay until the next new action replaces them.
+CREATE TABLE IF NOT EXISTS edit_actions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    label TEXT NOT NULL,
+    undone INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS edit_journal (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action_id INTEGER NOT NULL,
+    photo_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    before TEXT,
+    after TEXT,
+    FOREIGN KEY (action_id) REFERENCES edit_actions (id) ON DELETE CASCADE,
+    FOREIGN KEY (photo_id) REFERENCES photos (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_edit_journal_action ON edit_journal (action_id);
+CREATE INDEX IF NOT EXISTS idx_edit_journal_photo ON edit_journal (photo_id);
+CREATE INDEX IF NOT EXISTS idx_edit_actions_created ON edit_actions (created_at);
+
+CREATE TRIGGER IF NOT EXISTS photos_journal_ad AFTER DELETE ON photos
+BEGIN
+    DELETE FROM edit_journal WHERE photo_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS edit_actions_ad AFTER DELETE ON edit_actions
+BEGIN
+    DELETE FROM edit_journal WHERE action_id = OLD.id;
+END;
+"#;
+
+pub const MIGRATION_0019: &str = r#"
+-- Lightroom-style color labels; the label names are user-editable. The photos.color_label
+-- column is added first by apply_migration_0019, which skips it when it already exists.
+
+CREATE INDEX IF NOT EXISTS idx_photos_color_label ON photos (color_label);
+
+CREATE TABLE IF NOT EXISTS color_labels (
+    color TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO color_labels (color, name, position) VALUES
+    ('red', 'Red', 0),
+    ('yellow', 'Yellow', 1),
+    ('green', 'Green', 2),
+    ('blue', 'Blue', 3),
+    ('purple', 'Purple', 4);
+"#;
+
+pub const MIGRATION_0020: &str = r#"
+-- User-entered title, caption and notes. The columns are added first by apply_migration_0020.
+-- They are folded into photo_search.captions alongside the embedded XMP/IPTC captions.
+
+DROP TRIGGER IF EXISTS photos_search_ai;
+CREATE TRIGGER photos_search_ai AFTER INSERT ON photos
+BEGIN
+    DELETE FROM photo_search WHERE rowid = NEW.id;
+    INSERT INTO photo_search (rowid, file_name, folder, tags, camera, lens, captions)
+    VALUES (
+        NEW.id,
+        NEW.file_name,
+        substr(NEW.path, 1, length(NEW.path) - length(NEW.file_name)),
+        '',
+        trim(coalesce(NEW.make, '') || ' ' || coalesce(NEW.model, '')),
+        coalesce(NEW.lens, ''),
+        trim(
+            coalesce(NEW.title, '') || ' ' || coalesce(NEW.caption, '') || ' '
+            || coalesce(NEW.notes, '')
+        )
+    );
+END;
+
+DROP VIEW IF EXISTS photo_captions;
+CREATE VIEW photo_captions AS
+SELECT
+    p.id AS photo_id,
+    trim(
+        coalesce(p.title, '') || ' ' || coalesce(p.caption, '') || ' ' || coalesce(p.notes, '')
+        || ' ' || coalesce((
+            SELECT group_concat(j.value, ' ')
+            FROM photo_metadata m, json_each(photo_meta_json(m.raw)) j
+            WHERE m.photo_id = p.id AND j.key IN (
+                'XMP:Title', 'XMP:Description', 'XMP:Headline', 'IPTC:ObjectName',
+                'IPTC:Caption-Abstract', 'IPTC:Headline', 'EXIF:ImageDescription'
+            )
+        ), '')
+    ) AS captions
+FROM photos p;
+
+CREATE TRIGGER IF NOT EXISTS photos_search_text_au AFTER UPDATE OF title, caption, notes ON photos
+BEGIN
+    UPDATE photo_search
+    SET captions = coalesce((SELECT captions FROM photo_captions WHERE photo_id = NEW.id), '')
+    WHERE rowid = NEW.id;
+END;
+
+DROP TRIGGER IF EXISTS photo_metadata_search_ai;
+CREATE TRIGGER photo_metadata_search_ai AFTER INSERT ON photo_metadata
+BEGIN
+    UPDATE photo_search
+    SET captions = coalesce((SELECT captions FROM photo_captions WHERE photo_id = NEW.photo_id), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+DROP TRIGGER IF EXISTS photo_metadata_search_au;
+CREATE TRIGGER photo_metadata_search_au AFTER UPDATE OF raw ON photo_metadata
+BEGIN
+    UPDATE photo_search
+    SET captions = coalesce((SELECT captions FROM photo_captions WHERE photo_id = NEW.photo_id), '')
+    WHERE rowid = NEW.photo_id;
+END;
+
+UPDATE photo_search
+SET captions = coalesce((SELECT captions FROM photo_captions WHERE photo_id = photo_search.rowid), '');
+"#;
+
+pub const MIGRATION_0021: &str = r#"
+-- Lightroom [category] keywords keep their brackets when exported again. Run by
+-- apply_migration_0021 only when the column does not exist yet.
+ALTER TABLE keywords ADD COLUMN is_category INTEGER NOT NULL DEFAULT 0;
+"#;
+
+pub const MIGRATION_0022: &str = r#"
+-- Title and caption edited in the app are kept when the file is imported again. Run by
+-- apply_migration_0022 only when the columns do not exist yet. Text stored before cannot be
+-- told apart from imported text, so it is treated as edited rather than risk losing it.
+ALTER TABLE photos ADD COLUMN title_edited INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE photos ADD COLUMN caption_edited INTEGER NOT NULL DEFAULT 0;
+UPDATE photos SET title_edited = title IS NOT NULL, caption_edited = caption IS NOT NULL;
+"#;
@@ -1,5 +1,5 @@
 import { useEffect, useMemo, useRef, useState } from "react";
-import { convertFileSrc, invoke } from "@tauri-apps/api/tauri";
+import { invoke } from "@tauri-apps/api/tauri";
 import { open } from "@tauri-apps/api/dialog";
 import { listen } from "@tauri-apps/api/event";
 import { appWindow } from "@tauri-apps/api/window";
@@ -11,8 +11,24 @@ const SORT_OPTIONS = [
   { value: "picked", label: "Picked" },
   { value: "rejected", label: "Rejected" },
   { value: "file_name", label: "Filename" },
+  { value: "color_label", label: "Color label" },
+  { value: "relevance", label: "Relevance (search)" },
 ];
 
+// Lightroom shortcuts: 6-9 set red, yellow, green and blue.
+const COLOR_LABEL_KEYS = { 6: "red", 7: "yellow", 8: "green", 9: "blue" };
+
+// Query error positions are in characters (code points), not UTF-16 units.
+const splitQueryError = (text, { start, end }) => {
+  const chars = Array.from(text);
+  const stop = Math.max(end, start + 1);
+  return [
+    chars.slice(0, start).join(""),
+    chars.slice(start, stop).join("") || " ",
+    chars.slice(stop).join(""),
+  ];
+};
+
 const INFERENCE_DEVICE_OPTIONS = [
   { value: "auto", label: "Auto (GPU if available)" },
   { value: "gpu", label: "GPU (DirectML)" },
@@ -32,8 +48,47 @@ function usePersistentState(key, defaultValue) {
   return [value, setValue];
 }
 
-function resolvePath(path) {
-  return path ? convertFileSrc(path) : null;
+const RENDITION_BASE = navigator.userAgent.includes("Windows")
+  ? "https://phototag.localhost"
+  : "phototag://localhost";
+
+// Renditions are served by the backend's phototag:// protocol. `size` is the longer edge in
+// CSS pixels; the backend picks the closest profile. A rendition that is not cached yet answers
+// 404 while it renders in the background, so RenditionImage retries with a growing delay.
+function renditionUrl(photoId, size, attempt = 0) {
+  if (photoId == null) return null;
+  const pixels = Math.round(size * (window.devicePixelRatio || 1));
+  const url = `${RENDITION_BASE}/${photoId}/${pixels}`;
+  return attempt ? `${url}?attempt=${attempt}` : url;
+}
+
+// Misses are rendered in the background; keep retrying long enough for a large grid's queue to
+// drain, backing off to at most a few seconds between attempts.
+const RENDITION_RETRIES = 30;
+const RENDITION_RETRY_MAX_DELAY = 4000;
+
+function RenditionImage({ photoId, size, ...props }) {
+  const [attempt, setAttempt] = useState(0);
+  const retryTimer = useRef(null);
+
+  useEffect(() => {
+    setAttempt(0);
+    return () => clearTimeout(retryTimer.current);
+  }, [photoId, size]);
+
+  const handleError = () => {
+    if (attempt >= RENDITION_RETRIES) return;
+    const delay = Math.min(250 * 2 ** attempt, RENDITION_RETRY_MAX_DELAY);
+    retryTimer.current = setTimeout(() => setAttempt((value) => value + 1), delay);
+  };
+
+  return <img src={renditionUrl(photoId, size, attempt)} onError={handleError} {...props} />;
+}
+
+const THUMB_SIZE = 320;
+
+function previewSize() {
+  return Math.max(window.innerWidth, window.innerHeight);
 }
 
 function formatExposureTime(value) {
@@ -178,8 +233,8 @@ function ThumbCard({ photo, selected, onSelect, onDoubleClick, thumbSize, varian
       onDoubleClick={onDoubleClick}
       style={mergedStyle}
     >
-      {photo.thumb_path ? (
-        <img src={resolvePath(photo.thumb_path)} alt={photo.file_name} />
+      {photo.id != null ? (
+        <RenditionImage photoId={photo.id} size={THUMB_SIZE} alt={photo.file_name} loading="lazy" />
       ) : (
         <div className="thumb-placeholder">No preview</div>
       )}
@@ -339,6 +394,79 @@ function Toast({ toast, onUndo, onClose }) {
   );
 }
 
+function BackupPanel({ onRestored, onError }) {
+  const [backups, setBackups] = useState([]);
+  const [busy, setBusy] = useState(false);
+
+  const loadBackups = async () => {
+    try {
+      setBackups(await invoke("list_backups"));
+    } catch (err) {
+      onError(`Listing backups failed: ${err}`);
+    }
+  };
+
+  useEffect(() => {
+    loadBackups();
+  }, []);
+
+  const handleBackup = async () => {
+    setBusy(true);
+    try {
+      await invoke("backup_now");
+      await loadBackups();
+    } catch (err) {
+      onError(`Backup failed: ${err}`);
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  const handleRestore = async (backup) => {
+    const when = new Date(backup.created_at * 1000).toLocaleString();
+    if (!window.confirm(`Replace the library with the backup from ${when}? The current library is backed up first.`)) {
+      return;
+    }
+    setBusy(true);
+    try {
+      await invoke("restore_backup", { path: backup.path });
+      await loadBackups();
+      await onRestored();
+    } catch (err) {
+      onError(`Restore failed: ${err}`);
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  return (
+    <div className="settings-panel">
+      <div className="section-head">
+        <h4>Backups</h4>
+        <button className="ghost small" onClick={handleBackup} disabled={busy}>
+          {busy ? "Working..." : "Back up now"}
+        </button>
+      </div>
+      {backups.length === 0 ? (
+        <div className="muted">No backups yet</div>
+      ) : (
+        <div className="settings-models">
+          {backups.map((backup) => (
+            <div key={backup.path} className="settings-model">
+              <span className="muted" title={backup.path}>
+                {new Date(backup.created_at * 1000).toLocaleString()}
+              </span>
+              <button className="ghost small" onClick={() => handleRestore(backup)} disabled={busy}>
+                Restore
+              </button>
+            </div>
+          ))}
+        </div>
+      )}
+    </div>
+  );
+}
+
 function EmptyState({ onImport, onClearFilters }) {
   return (
     <div className="empty-state">
@@ -363,6 +491,7 @@ export default function App() {
   const [autoAdvance, setAutoAdvance] = usePersistentState("pt-auto-advance", true);
   const [lastImportPath, setLastImportPath] = usePersistentState("pt-last-import", "");
   const [photos, setPhotos] = useState([]);
+  const [photoTotal, setPhotoTotal] = useState(0);
   const [selection, setSelection] = useState([]);
   const [cursorIndex, setCursorIndex] = useState(0);
   const [progress, setProgress] = useState({
@@ -377,6 +506,7 @@ export default function App() {
   });
   const [importJobId, setImportJobId] = useState(null);
   const [errorMessage, setErrorMessage] = useState("");
+  const [queryError, setQueryError] = useState(null);
   const [rerunLoading, setRerunLoading] = useState(false);
   const [importing, setImporting] = useState(false);
   const [toast, setToast] = useState(null);
@@ -394,7 +524,6 @@ export default function App() {
   const [similarOpen, setSimilarOpen] = useState(false);
   const [similarResults, setSimilarResults] = useState([]);
   const [similarLoading, setSimilarLoading] = useState(false);
-  const lastActionRef = useRef(null);
   const searchRef = useRef(null);
   const anchorRef = useRef(null);
   const filmstripRef = useRef(null);
@@ -449,7 +578,7 @@ export default function App() {
 
   useEffect(() => {
     setImageReady(false);
-  }, [activePhoto?.photo?.preview_path]);
+  }, [activePhoto?.photo?.id]);
 
   useEffect(() => {
     if (mode === "CULL") {
@@ -536,6 +665,12 @@ export default function App() {
       const target = e.target;
       const isInput = target instanceof HTMLElement && ["INPUT", "TEXTAREA"].includes(target.tagName);
       if (isInput && e.key !== "Escape") return;
+      if ((e.ctrlKey || e.metaKey) && ["z", "Z", "y", "Y"].includes(e.key)) {
+        e.preventDefault();
+        if (e.key.toLowerCase() === "y" || e.shiftKey) handleRedo();
+        else handleUndo();
+        return;
+      }
       if (!photos.length) return;
       switch (e.key) {
         case " ":
@@ -562,6 +697,21 @@ export default function App() {
           e.preventDefault();
           applyCullChange({ rating: Number(e.key), label: `Rated ${e.key}` });
           break;
+        case "6":
+        case "7":
+        case "8":
+        case "9": {
+          e.preventDefault();
+          const color = COLOR_LABEL_KEYS[e.key];
+          const current = activePhoto?.photo.color_label;
+          // Pressing the key of the current label clears it, as in Lightroom.
+          applyCullChange(
+            current === color
+              ? { colorLabel: null, label: "Label cleared" }
+              : { colorLabel: color, label: `Labeled ${color}` }
+          );
+          break;
+        }
         case "p":
         case "P":
           e.preventDefault();
@@ -628,17 +778,27 @@ export default function App() {
     try {
       const limit = mode === "CULL" ? 500 : 800;
       const effectiveSmartView = mode === "CULL" ? "UNSORTED" : smartView;
+      try {
+        await invoke("validate_query", { query: filters.search });
+        setQueryError(null);
+      } catch (err) {
+        setQueryError(err);
+        return;
+      }
       const result = await invoke("query_photos", {
         filters: {
           ...filters,
+          search: null,
+          query: filters.search,
           mode,
           smart_view: effectiveSmartView === "ALL" ? null : effectiveSmartView,
           sort_by: filters.sort_by || (mode === "CULL" ? "last_modified" : "date_taken"),
           limit,
         },
       });
-      setPhotos(result);
-      updateSelectionAfterRefresh(result, options);
+      setPhotos(result.photos);
+      setPhotoTotal(result.total);
+      updateSelectionAfterRefresh(result.photos, options);
     } catch (err) {
       setErrorMessage(`Failed to load photos: ${err}`);
     }
@@ -659,35 +819,24 @@ export default function App() {
     el.scrollBy({ left: amount, behavior: "smooth" });
   };
 
-  const applyCullChange = async ({ rating, picked, rejected, label }) => {
+  const applyCullChange = async ({ rating, picked, rejected, colorLabel, label }) => {
     const ids = selection.length
       ? selection
       : activePhoto
       ? [activePhoto.photo.id]
       : [];
     if (!ids.length) return;
-    const before = photos
-      .filter((p) => ids.includes(p.photo.id))
-      .map((p) => ({
-        id: p.photo.id,
-        rating: p.photo.rating,
-        picked: p.photo.picked,
-        rejected: p.photo.rejected,
-      }));
 
     try {
       const payload = { photoIds: ids };
-      if (rating !== undefined) payload.rating = rating;
+      if (rating === null) payload.clearRating = true;
+      else if (rating !== undefined) payload.rating = rating;
       if (picked !== undefined) payload.picked = picked;
       if (rejected !== undefined) payload.rejected = rejected;
+      if (colorLabel === null) payload.clearColorLabel = true;
+      else if (colorLabel !== undefined) payload.colorLabel = colorLabel;
 
-      // When only clearing rating, some bindings ignore null in batch; call dedicated setter.
-      if (rating === null && picked === undefined && rejected === undefined) {
-        await Promise.all(ids.map((id) => invoke("set_rating", { photoId: id, rating: null })));
-      } else {
-        await invoke("batch_update_cull", payload);
-      }
-      lastActionRef.current = { before };
+      await invoke("batch_update_cull", payload);
       setToast({ message: label || "Updated", canUndo: true });
       const preferredIndex = autoAdvance ? cursorIndex + 1 : cursorIndex;
       await refreshPhotos({ preferredIndex });
@@ -758,13 +907,25 @@ export default function App() {
     if (!tagText || !selection.length) return;
     try {
       await Promise.all(selection.map((id) => invoke("add_manual_tag", { photoId: id, tag: tagText })));
-      setToast({ message: `Added tag "${tagText}"`, canUndo: false });
+      setToast({ message: `Added tag "${tagText}"`, canUndo: true });
       await refreshPhotos();
     } catch (err) {
       setErrorMessage(`Add tag failed: ${err}`);
     }
   };
 
+  const handleSetText = async (field, value) => {
+    if (!activePhoto || (activePhoto.photo[field] || "") === value.trim()) return;
+    try {
+      await invoke("set_photo_text", {
+        request: { photoIds: [activePhoto.photo.id], [field]: value },
+      });
+      await refreshPhotos();
+    } catch (err) {
+      setErrorMessage(`Saving ${field} failed: ${err}`);
+    }
+  };
+
   const handleRemoveTag = async (tag) => {
     if (!activePhoto) return;
     try {
@@ -912,30 +1073,22 @@ export default function App() {
   };
 
   const handleUndo = async () => {
-    if (!lastActionRef.current) return;
-    const { before } = lastActionRef.current;
     try {
-      for (const entry of before) {
-        const changes = {
-          rating: entry.rating ?? null,
-          picked: entry.picked,
-          rejected: entry.rejected,
-        };
-        if (changes.rating === null && changes.picked === undefined && changes.rejected === undefined) {
-          await invoke("set_rating", { photoId: entry.id, rating: null });
-        } else {
-          await invoke("batch_update_cull", {
-            photoIds: [entry.id],
-            ...changes,
-          });
-        }
-      }
-      await refreshPhotos();
+      const action = await invoke("undo_edit");
+      setToast(action ? { message: `Undid: ${action.label}`, canUndo: false } : null);
+      if (action) await refreshPhotos();
     } catch (err) {
       setErrorMessage(`Undo failed: ${err}`);
-    } finally {
-      setToast(null);
-      lastActionRef.current = null;
+    }
+  };
+
+  const handleRedo = async () => {
+    try {
+      const action = await invoke("redo_edit");
+      setToast(action ? { message: `Redid: ${action.label}`, canUndo: true } : null);
+      if (action) await refreshPhotos();
+    } catch (err) {
+      setErrorMessage(`Redo failed: ${err}`);
     }
   };
 
@@ -1006,10 +1159,30 @@ export default function App() {
             <input
               ref={searchRef}
               type="search"
-              placeholder="Search by filename or camera (/ to focus)"
+              placeholder="Search, e.g. (dog OR cat) iso>3200 (/ to focus)"
               value={filters.search}
+              className={queryError ? "invalid" : undefined}
               onChange={(e) => setFilters((f) => ({ ...f, search: e.target.value }))}
             />
+            {queryError && (
+              <div className="query-error" title={queryError.message}>
+                {queryError.message}
+                {filters.search && (
+                  <code>
+                    {(() => {
+                      const [before, marked, after] = splitQueryError(filters.search, queryError);
+                      return (
+                        <>
+                          {before}
+                          <mark>{marked}</mark>
+                          {after}
+                        </>
+                      );
+                    })()}
+                  </code>
+                )}
+              </div>
+            )}
           </div>
           <button onClick={handleImport} disabled={importing}>
             {importing ? "Importing..." : "Import Folder"}
@@ -1086,6 +1259,11 @@ export default function App() {
                   />
                 </label>
               )}
+              <span className="toolbar-item muted">
+                {photos.length < photoTotal
+                  ? `${photos.length} of ${photoTotal} photos`
+                  : `${photoTotal} photos`}
+              </span>
             </div>
             <label className="toolbar-toggle">
               <input
@@ -1160,16 +1338,18 @@ export default function App() {
                         activePhoto?.photo.picked ? "picked" : activePhoto?.photo.rejected ? "rejected" : ""
                       }`}
                     >
-                      {activePhoto?.photo.preview_path ? (
+                      {activePhoto?.photo ? (
                         <>
-                          <img
-                            src={resolvePath(activePhoto.photo.preview_path)}
+                          <RenditionImage
+                            photoId={activePhoto.photo.id}
+                            size={THUMB_SIZE}
                             alt=""
                             className={`preview-blur ${imageReady ? "ready" : ""}`}
                             aria-hidden="true"
                           />
-                          <img
-                            src={resolvePath(activePhoto.photo.preview_path)}
+                          <RenditionImage
+                            photoId={activePhoto.photo.id}
+                            size={previewSize()}
                             alt={activePhoto.photo.file_name}
                             className={`preview-large ${zoomMode === "ONE_TO_ONE" ? "zoomed" : ""} ${
                               imageReady ? "ready" : ""
@@ -1294,8 +1474,8 @@ export default function App() {
                               className="dup-thumb"
                               onClick={() => setSelection([photo.id])}
                             >
-                              {photo.thumb_path ? (
-                                <img src={resolvePath(photo.thumb_path)} alt={photo.file_name} />
+                              {photo.id != null ? (
+                                <RenditionImage photoId={photo.id} size={THUMB_SIZE} alt={photo.file_name} />
                               ) : (
                                 <div className="thumb-placeholder">No preview</div>
                               )}
@@ -1355,6 +1535,27 @@ export default function App() {
                   </span>
                 </div>
               </div>
+              <div className="text-block" key={activePhoto.photo.id}>
+                <h4>Description</h4>
+                <input
+                  type="text"
+                  placeholder="Title"
+                  defaultValue={activePhoto.photo.title || ""}
+                  onBlur={(e) => handleSetText("title", e.target.value)}
+                />
+                <textarea
+                  rows={2}
+                  placeholder="Caption"
+                  defaultValue={activePhoto.photo.caption || ""}
+                  onBlur={(e) => handleSetText("caption", e.target.value)}
+                />
+                <textarea
+                  rows={2}
+                  placeholder="Notes (not written to sidecars)"
+                  defaultValue={activePhoto.photo.notes || ""}
+                  onBlur={(e) => handleSetText("notes", e.target.value)}
+                />
+              </div>
               <div className="tags-block">
                 <div className="section-head">
                   <h4>Tags</h4>
@@ -1421,6 +1622,7 @@ export default function App() {
                   </div>
                 )}
               </div>
+              <BackupPanel onRestored={refreshPhotos} onError={setErrorMessage} />
               {similarOpen && (
                 <div className="similar-panel">
                   <div className="section-head">
@@ -1440,8 +1642,8 @@ export default function App() {
                         className="similar-card"
                         onClick={() => setSelection([item.id])}
                       >
-                        {item.thumb_path ? (
-                          <img src={resolvePath(item.thumb_path)} alt={item.file_name} />
+                        {item.id != null ? (
+                          <RenditionImage photoId={item.id} size={THUMB_SIZE} alt={item.file_name} />
                         ) : (
                           <div className="thumb-placeholder">No preview</div>
                         )}
@@ -1505,6 +1707,7 @@ export default function App() {
                   </div>
                 )}
               </div>
+              <BackupPanel onRestored={refreshPhotos} onError={setErrorMessage} />
             </>
           )}
         </aside>
@@ -1,6 +1,12 @@
-use crate::error::Result;
+use crate::color;
+use crate::config::{RenditionFormat, RenditionProfile};
+use crate::error::{Error, Result};
+use image::codecs::jpeg::JpegEncoder;
 use image::imageops::FilterType;
-use std::path::{Path, PathBuf};
+use image::{DynamicImage, RgbImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
 const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "tiff", "tif", "bmp", "gif", "webp"];
 
@@ -23,44 +29,114 @@ fn resize_dims(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
     (new_w, new_h)
 }
 
-fn resize_image(input: &Path, output: &Path, max_dim: u32) -> Result<()> {
-    let img = image::open(input)?;
+/// Applies an EXIF Orientation value (1-8) so the returned image is upright.
+pub fn apply_orientation(img: DynamicImage, orientation: Option<i64>) -> DynamicImage {
+    match orientation.unwrap_or(1) {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn needs_orientation(orientation: Option<i64>) -> bool {
+    matches!(orientation, Some(2..=8))
+}
+
+/// Rewrites an extracted preview in place so its pixels are upright sRGB. Embedded RAW previews
+/// are stored in sensor orientation and rely on the parent file's Orientation tag and color
+/// space; `icc` is the parent's profile, used when the preview has none of its own.
+pub fn normalize_preview(
+    path: &Path,
+    orientation: Option<i64>,
+    icc: Option<&[u8]>,
+    color_space: Option<&str>,
+) -> Result<()> {
+    let own_icc = color::read_icc_profile(path);
+    let icc = own_icc.as_deref().or(icc);
+    if !needs_orientation(orientation) && !color::needs_conversion(icc, color_space) {
+        return Ok(());
+    }
+    let img = color::to_srgb(image::open(path)?, icc, color_space);
+    apply_orientation(img, orientation).to_rgb8().save(path)?;
+    Ok(())
+}
+
+pub fn save_jpeg(rgb: &RgbImage, output: &Path, quality: u8) -> Result<()> {
+    let writer = BufWriter::new(File::create(output)?);
+    JpegEncoder::new_with_quality(writer, quality).encode_image(rgb)?;
+    Ok(())
+}
+
+/// Encodes `img` in the profile's format. Cache files never carry alpha or 16-bit samples.
+fn save_rendition(img: &DynamicImage, output: &Path, profile: &RenditionProfile) -> Result<()> {
+    let rgb = img.to_rgb8();
+    match profile.format {
+        RenditionFormat::Jpeg => save_jpeg(&rgb, output, profile.quality)?,
+        RenditionFormat::Webp => {
+            let encoded = webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+                .encode_simple(false, profile.quality as f32)
+                .map_err(|err| Error::Path(format!("WebP encoding failed: {err:?}")))?;
+            std::fs::write(output, &*encoded)?;
+        }
+    }
+    Ok(())
+}
+
+fn resize_image(
+    img: DynamicImage,
+    output: &Path,
+    profile: &RenditionProfile,
+    orientation: Option<i64>,
+) -> Result<()> {
+    let img = apply_orientation(img, orientation);
+    // Large profiles (e.g. 2560 px) must not upscale small sources.
+    let max_dim = profile.max_dim.min(img.width().max(img.height())).max(1);
     let (dst_w, dst_h) = resize_dims(img.width(), img.height(), max_dim);
     let mut used_gpu = false;
     #[cfg(target_os = "windows")]
     {
         if crate::gpu::gpu_preprocess_enabled() {
             if let Ok(gpu_resized) = crate::gpu::resize_rgba8(&img.to_rgba8(), dst_w, dst_h) {
-                gpu_resized.save(output)?;
+                save_rendition(&DynamicImage::ImageRgba8(gpu_resized), output, profile)?;
                 used_gpu = true;
             }
         }
     }
     if !used_gpu {
         let resized = img.resize(max_dim, max_dim, FilterType::CatmullRom);
-        resized.save(output)?;
+        save_rendition(&resized, output, profile)?;
+    }
+    Ok(())
+}
+
+fn ensure_parent(output: &Path) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
     }
     Ok(())
 }
 
-pub fn build_thumbnail(preview: &Path, dest_dir: &Path) -> Result<PathBuf> {
-    std::fs::create_dir_all(dest_dir)?;
-    let filename = preview
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("thumb.jpg");
-    let output = dest_dir.join(filename);
-    resize_image(preview, &output, 320)?;
-    Ok(output)
+/// Renders a rendition from an (already upright, sRGB) preview into `output`.
+pub fn build_thumbnail(preview: &Path, output: &Path, profile: &RenditionProfile) -> Result<()> {
+    ensure_parent(output)?;
+    resize_image(image::open(preview)?, output, profile, None)
 }
 
-pub fn build_preview(original_or_preview: &Path, dest_dir: &Path) -> Result<PathBuf> {
-    std::fs::create_dir_all(dest_dir)?;
-    let filename = original_or_preview
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("preview.jpg");
-    let output = dest_dir.join(filename);
-    resize_image(original_or_preview, &output, 1600)?;
-    Ok(output)
+/// Renders an upright sRGB rendition from the original into `output`. `color_space` is used
+/// when the file carries no ICC profile.
+pub fn build_preview(
+    original: &Path,
+    output: &Path,
+    profile: &RenditionProfile,
+    orientation: Option<i64>,
+    color_space: Option<&str>,
+) -> Result<()> {
+    ensure_parent(output)?;
+    let img = color::open_srgb(original, color_space)?;
+    resize_image(img, output, profile, orientation)
 }